@@ -1,164 +1,430 @@
 use spdlog::prelude::*;
 use std::{
-    collections::VecDeque,
-    net::{Ipv4Addr, UdpSocket},
+    io,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
 };
 
+use cache::Cache;
+use config::{ResolverConfig, ResolverMode};
+use error::ResolveError;
+use infra::InfraCache;
+use lookup::LookupResult;
 use message::DnsMessage;
+use replay::{Protocol, Recording, RecordingTransport, ReplayTransport};
+use resolver::{Delegation, Iteration, Priming, Query, Resolution, Resolver, Step, StubLookup};
+use root_hints::RootHints;
+use rr::ResourceRecord;
+use trace::TraceStep;
+use transport::{Connection, TcpTransport, Transport, UdpTransport};
 
+#[cfg(feature = "tokio")]
+pub mod async_resolver;
+pub mod cache;
+pub mod config;
+pub mod error;
+#[cfg(feature = "test-util")]
+pub mod fault;
 pub mod header;
+pub mod hosts;
+pub mod infra;
+pub mod lookup;
+pub mod lru;
 pub mod message;
 pub mod question;
+pub mod replay;
+pub mod resolv_conf;
+mod resolver;
+pub mod reverse;
+pub mod root_hints;
 pub mod rr;
+pub mod tcp;
+pub mod trace;
+pub mod transport;
 pub mod utility;
 
-/// A DNS client to query for a host name
-pub struct DnsClient {
-    binding_socket: UdpSocket,
+/// Maximum number of queries sent while resolving a single name
+const MAX_STEPS: usize = 64;
+/// Maximum nesting of name server address lookups
+const MAX_DEPTH: usize = 8;
+/// Maximum number of aliases followed while resolving a single name
+pub(crate) const MAX_CNAMES: usize = 8;
+
+/// Number of random local ports tried before leaving the choice to the
+/// operating system
+pub(crate) const BIND_ATTEMPTS: usize = 8;
+
+/// Response codes
+const NO_ERROR: u16 = 0;
+const SERVER_FAILURE: u16 = 2;
+pub(crate) const NAME_ERROR: u16 = 3;
+pub(crate) const REFUSED: u16 = 5;
+
+/// A DNS client to query for a host name, exchanging messages with name
+/// servers over the `U` transport, and over the `S` transport when a
+/// response doesn't fit in a datagram
+pub struct DnsClient<U: Transport = UdpTransport, S: Transport = TcpTransport> {
+    resolver: Resolver,
+    udp: Arc<U>,
+    tcp: Arc<S>,
 }
 
 impl DnsClient {
     /// Create a new DNS client
     pub fn new() -> DnsClient {
-        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).expect("Can't create socket!");
+        DnsClient::with_config(ResolverConfig::default())
+    }
 
-        debug!(
-            "Initialize host at address: {:#?}",
-            socket.local_addr().unwrap()
-        );
+    /// Create a new DNS client with the given settings
+    pub fn with_config(config: ResolverConfig) -> DnsClient {
+        DnsClient::with_transports(config, UdpTransport, TcpTransport)
+    }
+}
 
+impl<U: Transport, S: Transport> DnsClient<U, S> {
+    /// Create a new DNS client with the given settings, sending queries
+    /// over `udp` and asking again over `tcp` when a response is truncated
+    pub fn with_transports(config: ResolverConfig, udp: U, tcp: S) -> DnsClient<U, S> {
         DnsClient {
-            binding_socket: socket,
-        }
-    }
-
-    /// Query a host name from a DNS server
-    pub fn ask(&self, host_name: &str) {
-        let mut dns_servers = VecDeque::from(self.get_root_servers());
-        let mut found_ip_addrs = false;
-        while !dns_servers.is_empty() {
-            let dns_server = dns_servers.pop_front().unwrap();
-            let (ip_addrs, _) = self.resolve_name(host_name, &dns_server);
-            if ip_addrs.is_some() {
-                let ip_addrs = ip_addrs.unwrap();
-                println!("IP Address: \n");
-                println!("[\n\t{}\n]", ip_addrs.join("\n\t"));
-                found_ip_addrs = true;
-                break;
+            resolver: Resolver::new(config),
+            udp: Arc::new(udp),
+            tcp: Arc::new(tcp),
+        }
+    }
+
+    /// Call `hook` with every query sent while resolving names from the
+    /// root down
+    pub fn with_trace<F>(mut self, hook: F) -> DnsClient<U, S>
+    where
+        F: Fn(&TraceStep) + Send + Sync + 'static,
+    {
+        self.resolver.trace = Some(Arc::new(hook));
+        self
+    }
+
+    /// Round trip statistics of the name servers asked so far
+    pub fn infra(&self) -> &InfraCache {
+        &self.resolver.infra
+    }
+
+    /// Answers and delegations cached so far
+    pub fn cache(&self) -> &Cache {
+        &self.resolver.cache
+    }
+
+    /// Save the cached RRsets to the cache file of the configuration, if
+    /// any, giving the number of RRsets saved
+    pub fn save_cache(&self) -> io::Result<usize> {
+        self.resolver.save_cache()
+    }
+
+    /// Settings used by the client
+    pub fn config(&self) -> &ResolverConfig {
+        &self.resolver.config
+    }
+
+    /// Query a host name from a DNS server. The hosts file is consulted
+    /// first. Relative names are expanded with the search list: a candidate
+    /// that doesn't exist moves on to the next one, any other outcome ends
+    /// the search
+    pub fn ask(&self, host_name: &str) -> Result<LookupResult, ResolveError> {
+        if let Some(result) = self.resolver.hosts_lookup(host_name) {
+            return Ok(result);
+        }
+
+        let deadline = Instant::now() + self.config().deadline;
+        let mut last_error = None;
+        for candidate in self.config().search_candidates(host_name) {
+            debug!("Trying {} for {}", candidate, host_name);
+            match self.resolve(&candidate, rr::A, deadline) {
+                Ok(records) => return Ok(Resolver::address_result(candidate, &records, false)),
+                Err(err @ ResolveError::NxDomain { .. }) => last_error = Some(err),
+                Err(err) => {
+                    return match self.resolver.stale_records(&candidate, rr::A, &err) {
+                        Some(records) => {
+                            info!("Serving stale {} after: {}", candidate, err);
+                            Ok(Resolver::address_result(candidate, &records, true))
+                        }
+                        None => Err(err),
+                    }
+                }
             }
         }
-        if !found_ip_addrs {
-            error!("Can's resolve {}", host_name);
+
+        Err(last_error.unwrap_or(Resolver::no_candidate(host_name)))
+    }
+
+    /// Look up the host names of an address in the hosts file, or else by
+    /// asking for the PTR records of its `in-addr.arpa` or `ip6.arpa` name
+    pub fn reverse(&self, ip: IpAddr) -> Result<Vec<String>, ResolveError> {
+        if let Some(host_names) = self.resolver.hosts_reverse(ip) {
+            return Ok(host_names);
+        }
+
+        let name = reverse::reverse_name(ip);
+        let deadline = Instant::now() + self.config().deadline;
+        match self.resolve(&name, rr::PTR, deadline) {
+            Ok(records) => Ok(Resolver::record_values(&records, rr::PTR)),
+            Err(err) => match self.resolver.stale_records(&name, rr::PTR, &err) {
+                Some(records) => {
+                    info!("Serving stale {} after: {}", name, err);
+                    Ok(Resolver::record_values(&records, rr::PTR))
+                }
+                None => Err(err),
+            },
         }
     }
 
-    /// Get all root servers address
-    fn get_root_servers(&self) -> Vec<String> {
-        vec![
-            String::from("198.41.0.4"),     // a.root-servers.net
-            String::from("199.9.14.201"),   // b.root-servers.net
-            String::from("192.33.4.12"),    // c.root-servers.net
-            String::from("199.7.91.13"),    // d.root-servers.net
-            String::from("192.203.230.10"), // e.root-servers.net
-            String::from("192.5.5.241"),    // f.root-servers.net
-            String::from("198.97.190.53"),  // h.root-servers.net
-            String::from("192.36.148.17"),  // i.root-servers.net
-            String::from("192.58.128.30"),  // j.root-servers.net
-            String::from("193.0.14.129"),   // k.root-servers.net
-            String::from("199.7.83.42"),    // l.root-servers.net
-            String::from("202.12.27.33"),   // m.root-servers.net
-        ]
+    /// Resolve the records of `rr_type` of a fully qualified name, from
+    /// the cache when it holds the answer
+    fn resolve(
+        &self,
+        host_name: &str,
+        rr_type: u16,
+        deadline: Instant,
+    ) -> Result<Vec<ResourceRecord>, ResolveError> {
+        let (name, cached) = self.resolver.cached_records(host_name, rr_type)?;
+        if let Some(records) = cached {
+            self.prefetch(&name, rr_type);
+            return Ok(records);
+        }
+
+        self.resolve_uncached(&name, rr_type, deadline)
     }
 
-    /// Resolve a host name with a root dns server
-    fn resolve_name(
+    /// Resolve the records of `rr_type` of a fully qualified name by asking
+    /// name servers, even if they are cached
+    fn resolve_uncached(
         &self,
         host_name: &str,
-        root_dns_server: &str,
-    ) -> (Option<Vec<String>>, Option<Vec<String>>) {
-        let dns_question = message::DnsMessage::new(host_name);
-        let mut dns_servers = VecDeque::new();
-        dns_servers.push_back(root_dns_server.to_string());
-        let mut ns_names = vec![];
-        while !dns_servers.is_empty() {
-            let dns_server = dns_servers.pop_front().unwrap();
-            let conn = self.connect(&dns_server, 53);
-            if conn.is_ok() {
-                info!("Querying {} for {}", dns_server, host_name);
-                self.send(&dns_server, 53, &dns_question.into_bytes());
-                let bytes = self.listen().unwrap();
-                let dns_response = DnsMessage::parse(&bytes).unwrap();
-                if dns_response.header.an_cnt > 0 {
-                    let ip_addrs = dns_response
-                        .answers
-                        .iter()
-                        .filter(|an| an.is_host_addr())
-                        .map(|an| an.get_ip_addr())
-                        .collect();
-                    return (Some(ip_addrs), None);
+        rr_type: u16,
+        deadline: Instant,
+    ) -> Result<Vec<ResourceRecord>, ResolveError> {
+        match &self.config().mode {
+            ResolverMode::Iterative => {
+                if self.resolver.claim_priming() {
+                    // Failures are logged, the root hints are used instead
+                    let _ = self.prime_until(Resolver::priming_deadline(deadline));
                 }
+                let iteration = Iteration::new(&self.resolver, host_name, rr_type, 0, deadline);
+                self.run(iteration)
+            }
+            ResolverMode::Stub(name_servers) => {
+                let lookup =
+                    StubLookup::new(&self.resolver, host_name, rr_type, name_servers, deadline);
+                self.run(lookup)
+            }
+        }
+    }
+
+    /// Ask the root servers for the current root NS set and addresses
+    /// (RFC 8109) and use them instead of the root hints from then on
+    pub fn prime(&self) -> Result<RootHints, ResolveError> {
+        self.prime_until(Instant::now() + self.config().deadline)
+    }
+
+    /// Prime the root servers, giving up when `deadline` passes
+    fn prime_until(&self, deadline: Instant) -> Result<RootHints, ResolveError> {
+        let result = self.run(Priming::new(&self.resolver, deadline));
+        self.resolver.finish_priming(&result);
+
+        result
+    }
 
-                if dns_response.header.ar_cnt > 0 {
-                    let mut auth_servers = dns_response
-                        .additionals
-                        .iter()
-                        .filter(|ar| ar.is_host_addr())
-                        .map(|ar| ar.get_ip_addr())
-                        .collect();
-                    dns_servers.append(&mut auth_servers);
-                } else if dns_response.header.ns_cnt > 0 {
-                    ns_names = dns_response
-                        .authorities
-                        .iter()
-                        .map(|rr| DnsMessage::decode_address(&rr.rr_name))
-                        .collect();
+    /// Send the queries a resolution asks for until it is over
+    fn run<R: Resolution>(&self, mut resolution: R) -> Result<R::Output, ResolveError> {
+        loop {
+            match resolution.next(&self.resolver) {
+                Step::Query(query) => {
+                    let result = self.query(&query);
+                    resolution.handle(&self.resolver, query, result);
                 }
+                Step::Delegate(delegation) => {
+                    let auth_servers = self.resolve_name_servers(&delegation);
+                    resolution.delegated(&self.resolver, auth_servers);
+                }
+                Step::Done(result) => return result,
             }
         }
-        if !ns_names.is_empty() {
-            return (None, Some(ns_names));
+    }
+
+    /// Look up the addresses of name servers that came without glue
+    fn resolve_name_servers(&self, delegation: &Delegation) -> Vec<String> {
+        for ns_name in delegation.lookups() {
+            let records = match self.resolver.peek_records(ns_name, rr::A) {
+                Ok((_, Some(records))) => Ok(records),
+                Ok((name, None)) => self.run(delegation.lookup(&self.resolver, &name)),
+                Err(err) => Err(err),
+            };
+            match records {
+                Ok(records) => return Resolver::record_values(&records, rr::A),
+                Err(err) => debug!("Can't resolve name server {}: {}", ns_name, err),
+            }
         }
 
-        (None, None)
+        vec![]
     }
 
-    /// Send a udp message to a remote address
-    fn send(&self, remote_addr: &str, port: u16, msg: &[u8]) -> usize {
-        let result: usize = 0;
-        let addr = format!("{}:{}", remote_addr, port);
-        match self.binding_socket.send_to(msg, addr) {
-            Ok(number_of_bytes) => {
-                debug!(
-                    "Send a {}-byte message to address: {}:{}",
-                    number_of_bytes, remote_addr, port
-                );
-            }
-            Err(_) => error!(
-                "Failed sending message: {}",
-                std::str::from_utf8(msg).unwrap().to_string()
-            ),
+    /// Refresh the cached records of `rr_type` of `name` in the background
+    /// when they are popular and about to expire, so they never expire
+    /// into a full resolution
+    fn prefetch(&self, name: &str, rr_type: u16) {
+        if !self.resolver.prefetch_due(name, rr_type) {
+            return;
         }
 
-        result
+        debug!("Prefetching {}", name);
+        let prefetcher = self.prefetcher();
+        let name = name.to_string();
+        thread::spawn(move || {
+            let deadline = Instant::now() + prefetcher.config().deadline;
+            if let Err(err) = prefetcher.resolve_uncached(&name, rr_type, deadline) {
+                info!("Prefetch of {} failed: {}", name, err);
+                prefetcher.resolver.prefetch_failed(&name, rr_type);
+            }
+        });
     }
 
-    /// Connect to a remote address on a port
-    fn connect(&self, remote_addr: &str, port: u16) -> std::io::Result<()> {
-        debug!("Connecting to {}:{}", remote_addr, port);
-        let addr = format!("{}:{}", remote_addr, port);
-        self.binding_socket.connect(addr)
+    /// Client sharing the caches and root servers of this one, to resolve
+    /// names in the background
+    fn prefetcher(&self) -> DnsClient<U, S> {
+        DnsClient {
+            resolver: self.resolver.background(),
+            udp: self.udp.clone(),
+            tcp: self.tcp.clone(),
+        }
     }
 
-    /// Listen to a response from a remote address
-    fn listen(&self) -> Option<Vec<u8>> {
-        let mut buffer = [0; 1024];
-        match self.binding_socket.recv_from(&mut buffer) {
-            Ok((number_of_bytes, _)) => {
-                debug!("Received: {} bytes", number_of_bytes);
-                let result = Vec::from(&buffer[0..number_of_bytes]);
-                Some(result)
+    /// Send a question to a name server and wait for its response, giving
+    /// up when the timeout of this attempt or the deadline passes. Truncated
+    /// responses are asked again over TCP
+    fn query(&self, query: &Query) -> Result<DnsMessage, ResolveError> {
+        let wait = self.resolver.wait(query);
+        if wait.is_zero() {
+            return Err(query.timeout());
+        }
+
+        let dns_question = self.resolver.question(query);
+        if self.config().force_tcp {
+            return self.query_tcp(&dns_question, query, wait);
+        }
+
+        let dns_response = self.query_udp(&dns_question, query, wait)?;
+        if Resolver::is_truncated(&dns_response, query) {
+            return self.query_tcp(&dns_question, query, self.resolver.wait(query));
+        }
+
+        Ok(dns_response)
+    }
+
+    /// Send a question over UDP, discarding responses that don't match it
+    /// until one does or `wait` has passed
+    fn query_udp(
+        &self,
+        dns_question: &DnsMessage,
+        query: &Query,
+        wait: Duration,
+    ) -> Result<DnsMessage, ResolveError> {
+        let server_addr = self.resolver.server_addr(query)?;
+        let wait_until = Instant::now() + wait;
+        let mut connection = match self.udp.connect(server_addr, wait_until) {
+            Ok(connection) => connection,
+            Err(err) => {
+                debug!("Can't connect to {}: {}", query.server, err);
+                return Err(query.timeout());
             }
-            Err(_) => None,
+        };
+        info!("Querying {} for {} over UDP", query.server, query.name);
+        if let Err(err) = connection.send(&dns_question.to_be_bytes()) {
+            debug!("Can't send to {}: {}", query.server, err);
+            return Err(query.timeout());
         }
+
+        let mut malformed = None;
+        loop {
+            let bytes = match connection.receive(wait_until) {
+                Ok(bytes) => bytes,
+                Err(err) => {
+                    debug!(
+                        "No response from {} within {:?}: {}",
+                        query.server, wait, err
+                    );
+                    break;
+                }
+            };
+            let matched =
+                self.resolver
+                    .match_datagram(&bytes, dns_question, server_addr, &mut malformed);
+            if let Some(dns_response) = matched {
+                return Ok(dns_response);
+            }
+        }
+
+        Err(Resolver::udp_failure(query, malformed))
+    }
+
+    /// Send a question over TCP and read its response, for responses that
+    /// don't fit in a UDP datagram
+    fn query_tcp(
+        &self,
+        dns_question: &DnsMessage,
+        query: &Query,
+        wait: Duration,
+    ) -> Result<DnsMessage, ResolveError> {
+        if wait.is_zero() {
+            return Err(query.timeout());
+        }
+
+        let server_addr = self.resolver.server_addr(query)?;
+        info!("Querying {} for {} over TCP", query.server, query.name);
+        let bytes = match self.exchange_tcp(server_addr, &dns_question.to_be_bytes(), wait) {
+            Ok(bytes) => bytes,
+            Err(err) => {
+                debug!("TCP exchange with {} failed: {}", query.server, err);
+                return Err(query.timeout());
+            }
+        };
+
+        self.resolver.stream_response(&bytes, dns_question, query)
+    }
+
+    /// Send a message over a new stream connection and read the response,
+    /// waiting at most `timeout` for the whole exchange
+    fn exchange_tcp(&self, addr: SocketAddr, msg: &[u8], timeout: Duration) -> io::Result<Vec<u8>> {
+        let deadline = Instant::now() + timeout;
+        let mut connection = self.tcp.connect(addr, deadline)?;
+        connection.send(msg)?;
+        connection.receive(deadline)
+    }
+}
+
+impl DnsClient<RecordingTransport<UdpTransport>, RecordingTransport<TcpTransport>> {
+    /// Create a DNS client recording every query it sends and every
+    /// response it receives into `recording`
+    pub fn recording(config: ResolverConfig, recording: &Recording) -> Self {
+        DnsClient::with_transports(
+            config,
+            recording.record(UdpTransport, Protocol::Udp),
+            recording.record(TcpTransport, Protocol::Tcp),
+        )
+    }
+}
+
+impl DnsClient<ReplayTransport, ReplayTransport> {
+    /// Create a DNS client answered from `recording` instead of the network
+    pub fn replaying(config: ResolverConfig, recording: &Recording) -> Self {
+        DnsClient::with_transports(
+            config,
+            recording.replay(Protocol::Udp),
+            recording.replay(Protocol::Tcp),
+        )
+    }
+}
+
+impl Default for DnsClient {
+    fn default() -> Self {
+        DnsClient::new()
     }
 }
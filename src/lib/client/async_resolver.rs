@@ -0,0 +1,584 @@
+use spdlog::prelude::*;
+use std::collections::HashMap;
+use std::future::Future;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio::time;
+
+use crate::client::cache::Cache;
+use crate::client::config::{ResolverConfig, ResolverMode, EDNS_PAYLOAD_SIZE};
+use crate::client::error::ResolveError;
+use crate::client::infra::InfraCache;
+use crate::client::lookup::LookupResult;
+use crate::client::message::DnsMessage;
+use crate::client::resolv_conf::ResolvConf;
+use crate::client::resolver::{
+    Delegation, Iteration, Priming, Query, Resolution, Resolver, Step, StubLookup,
+};
+use crate::client::rr::{self, ResourceRecord};
+use crate::client::tcp;
+use crate::client::transport::UdpTransport;
+
+/// Number of shared sockets per address family that queries take in turn
+const POOL_SIZE: usize = 4;
+/// Number of queries sent from a shared socket before it is replaced by
+/// one on another random port
+const SOCKET_QUERIES: usize = 8;
+
+/// Queries waiting for a response on a shared socket, by server address
+/// and transaction ID
+type Senders = HashMap<(SocketAddr, u16), mpsc::UnboundedSender<Vec<u8>>>;
+/// Queries waiting on a shared socket, none once it stopped receiving
+type Pending = Arc<Mutex<Option<Senders>>>;
+
+/// Socket in a pool with the number of queries it has carried
+type Slot = Option<(Arc<SharedSocket>, usize)>;
+
+/// A DNS resolver for async code, resolving from the root down or asking
+/// recursive name servers like `DnsClient`. Queries of concurrent lookups
+/// are in flight together on a few UDP sockets per address family, told
+/// apart by server address and transaction ID
+pub struct AsyncResolver {
+    resolver: Resolver,
+    sockets_v4: Arc<SocketPool>,
+    sockets_v6: Arc<SocketPool>,
+}
+
+impl AsyncResolver {
+    /// Create a resolver with the given settings
+    pub fn with_config(config: ResolverConfig) -> AsyncResolver {
+        AsyncResolver {
+            resolver: Resolver::new(config),
+            sockets_v4: Arc::new(SocketPool::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED))),
+            sockets_v6: Arc::new(SocketPool::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED))),
+        }
+    }
+
+    /// Create a resolver asking the name servers of the system resolver
+    /// configuration
+    pub fn system() -> io::Result<AsyncResolver> {
+        Ok(AsyncResolver::with_config(
+            ResolvConf::read_system()?.to_config(),
+        ))
+    }
+
+    /// Round trip statistics of the name servers asked so far
+    pub fn infra(&self) -> &InfraCache {
+        &self.resolver.infra
+    }
+
+    /// Cache of answers shared by the lookups
+    pub fn cache(&self) -> &Cache {
+        &self.resolver.cache
+    }
+
+    /// Save the cached RRsets to the cache file of the configuration, if
+    /// any, giving the number of RRsets saved
+    pub fn save_cache(&self) -> io::Result<usize> {
+        self.resolver.save_cache()
+    }
+
+    /// Settings the resolver was created with
+    pub fn config(&self) -> &ResolverConfig {
+        &self.resolver.config
+    }
+
+    /// Look up the IPv4 and IPv6 addresses of a host name. The hosts file
+    /// is consulted first, then the candidates of the search list like
+    /// `DnsClient::ask`, asking for both address types at once. The lookup
+    /// fails only when neither type is found, with the error of IPv4
+    pub async fn lookup_ip(&self, host_name: &str) -> Result<LookupResult, ResolveError> {
+        if let Some(result) = self.resolver.hosts_lookup(host_name) {
+            return Ok(result);
+        }
+
+        let (v4, v6) = tokio::join!(
+            self.search(host_name, rr::A),
+            self.search(host_name, rr::AAAA)
+        );
+        let (name, records, stale) = match (v4, v6) {
+            (Ok((name, mut records, stale)), Ok((_, v6_records, v6_stale))) => {
+                records.extend(v6_records);
+                (name, records, stale || v6_stale)
+            }
+            (Ok(found), Err(err)) | (Err(err), Ok(found)) => {
+                debug!("Only one address type of {}: {}", host_name, err);
+                found
+            }
+            (Err(err), Err(_)) => return Err(err),
+        };
+        Ok(Resolver::address_result(name, &records, stale))
+    }
+
+    /// Look up the records of `rr_type` of a name, following aliases
+    pub async fn lookup(
+        &self,
+        name: &str,
+        rr_type: u16,
+    ) -> Result<Vec<ResourceRecord>, ResolveError> {
+        self.search(name, rr_type)
+            .await
+            .map(|(_, records, _)| records)
+    }
+
+    /// Try the candidates of the search list in order until one exists,
+    /// giving the candidate found, its records and whether they are stale
+    async fn search(
+        &self,
+        name: &str,
+        rr_type: u16,
+    ) -> Result<(String, Vec<ResourceRecord>, bool), ResolveError> {
+        let deadline = Instant::now() + self.config().deadline;
+        let mut last_error = None;
+        for candidate in self.config().search_candidates(name) {
+            debug!("Trying {} for {}", candidate, name);
+            match self.resolve(&candidate, rr_type, deadline).await {
+                Ok(records) => return Ok((candidate, records, false)),
+                Err(err @ ResolveError::NxDomain { .. }) => last_error = Some(err),
+                Err(err) => {
+                    return match self.resolver.stale_records(&candidate, rr_type, &err) {
+                        Some(records) => {
+                            info!("Serving stale {} after: {}", candidate, err);
+                            Ok((candidate, records, true))
+                        }
+                        None => Err(err),
+                    }
+                }
+            }
+        }
+
+        Err(last_error.unwrap_or(Resolver::no_candidate(name)))
+    }
+
+    /// Resolve the records of `rr_type` of a fully qualified name, from the
+    /// cache when it holds the answer
+    async fn resolve(
+        &self,
+        host_name: &str,
+        rr_type: u16,
+        deadline: Instant,
+    ) -> Result<Vec<ResourceRecord>, ResolveError> {
+        let (name, cached) = self.resolver.cached_records(host_name, rr_type)?;
+        if let Some(records) = cached {
+            self.prefetch(&name, rr_type);
+            return Ok(records);
+        }
+
+        self.resolve_uncached(&name, rr_type, deadline).await
+    }
+
+    /// Resolve the records of `rr_type` of a fully qualified name by asking
+    /// name servers, even if they are cached
+    async fn resolve_uncached(
+        &self,
+        name: &str,
+        rr_type: u16,
+        deadline: Instant,
+    ) -> Result<Vec<ResourceRecord>, ResolveError> {
+        match &self.config().mode {
+            ResolverMode::Iterative => {
+                if self.resolver.claim_priming() {
+                    let priming =
+                        Priming::new(&self.resolver, Resolver::priming_deadline(deadline));
+                    let result = self.run(priming).await;
+                    self.resolver.finish_priming(&result);
+                }
+                self.run(Iteration::new(&self.resolver, name, rr_type, 0, deadline))
+                    .await
+            }
+            ResolverMode::Stub(name_servers) => {
+                self.run(StubLookup::new(
+                    &self.resolver,
+                    name,
+                    rr_type,
+                    name_servers,
+                    deadline,
+                ))
+                .await
+            }
+        }
+    }
+
+    /// Refresh the cached records of `rr_type` of `name` in a background
+    /// task when they are popular and about to expire, like
+    /// `DnsClient::prefetch`
+    fn prefetch(&self, name: &str, rr_type: u16) {
+        if !self.resolver.prefetch_due(name, rr_type) {
+            return;
+        }
+
+        debug!("Prefetching {}", name);
+        let prefetcher = self.prefetcher();
+        let name = name.to_string();
+        tokio::spawn(async move {
+            let deadline = Instant::now() + prefetcher.config().deadline;
+            if let Err(err) = prefetcher.resolve_uncached(&name, rr_type, deadline).await {
+                info!("Prefetch of {} failed: {}", name, err);
+                prefetcher.resolver.prefetch_failed(&name, rr_type);
+            }
+        });
+    }
+
+    /// Resolver sharing the caches, root servers and sockets of this one,
+    /// to resolve names in a background task
+    fn prefetcher(&self) -> AsyncResolver {
+        AsyncResolver {
+            resolver: self.resolver.background(),
+            sockets_v4: self.sockets_v4.clone(),
+            sockets_v6: self.sockets_v6.clone(),
+        }
+    }
+
+    /// Send the queries a resolution asks for until it is over
+    async fn run<R: Resolution>(&self, mut resolution: R) -> Result<R::Output, ResolveError> {
+        loop {
+            match resolution.next(&self.resolver) {
+                Step::Query(query) => {
+                    let result = self.query(&query).await;
+                    resolution.handle(&self.resolver, query, result);
+                }
+                Step::Delegate(delegation) => {
+                    let auth_servers = self.resolve_name_servers(&delegation).await;
+                    resolution.delegated(&self.resolver, auth_servers);
+                }
+                Step::Done(result) => return result,
+            }
+        }
+    }
+
+    /// Look up the addresses of name servers that came without glue. The
+    /// lookups nest resolutions, so the future is boxed
+    fn resolve_name_servers<'a>(
+        &'a self,
+        delegation: &'a Delegation,
+    ) -> Pin<Box<dyn Future<Output = Vec<String>> + Send + 'a>> {
+        Box::pin(async move {
+            for ns_name in delegation.lookups() {
+                let records = match self.resolver.peek_records(ns_name, rr::A) {
+                    Ok((_, Some(records))) => Ok(records),
+                    Ok((name, None)) => self.run(delegation.lookup(&self.resolver, &name)).await,
+                    Err(err) => Err(err),
+                };
+                match records {
+                    Ok(records) => return Resolver::record_values(&records, rr::A),
+                    Err(err) => debug!("Can't resolve name server {}: {}", ns_name, err),
+                }
+            }
+
+            vec![]
+        })
+    }
+
+    /// Send a question to a name server and wait for its response, giving
+    /// up when the timeout of this attempt or the deadline passes. Truncated
+    /// responses are asked again over TCP
+    async fn query(&self, query: &Query) -> Result<DnsMessage, ResolveError> {
+        let wait = self.resolver.wait(query);
+        if wait.is_zero() {
+            return Err(query.timeout());
+        }
+        let server_addr = self.resolver.server_addr(query)?;
+
+        let mut dns_question = self.resolver.question(query);
+        if self.config().force_tcp {
+            return self
+                .query_tcp(&dns_question, server_addr, query, wait)
+                .await;
+        }
+
+        let dns_response = self
+            .query_udp(&mut dns_question, server_addr, query, wait)
+            .await?;
+        if Resolver::is_truncated(&dns_response, query) {
+            return self
+                .query_tcp(&dns_question, server_addr, query, self.resolver.wait(query))
+                .await;
+        }
+
+        Ok(dns_response)
+    }
+
+    /// Send a question over the shared UDP socket and wait for a matching
+    /// response until `wait` has passed. The transaction ID of the question
+    /// is replaced by one not in flight to the same server
+    async fn query_udp(
+        &self,
+        dns_question: &mut DnsMessage,
+        server_addr: SocketAddr,
+        query: &Query,
+        wait: Duration,
+    ) -> Result<DnsMessage, ResolveError> {
+        let socket = match self.socket(server_addr) {
+            Ok(socket) => socket,
+            Err(err) => {
+                debug!("Can't open socket for {}: {}", query.server, err);
+                return Err(query.timeout());
+            }
+        };
+        let mut waiting = socket.register(server_addr);
+        dns_question.header.id = waiting.id();
+        info!("Querying {} for {} over UDP", query.server, query.name);
+        if let Err(err) = socket.send(&dns_question.to_be_bytes(), server_addr).await {
+            debug!("Can't send to {}: {}", query.server, err);
+            return Err(query.timeout());
+        }
+
+        let wait_until = Instant::now() + wait;
+        let mut malformed = None;
+        loop {
+            let remaining = wait_until.saturating_duration_since(Instant::now());
+            let bytes = match time::timeout(remaining, waiting.receive()).await {
+                Ok(Some(bytes)) => bytes,
+                _ => {
+                    debug!("No response from {} within {:?}", query.server, wait);
+                    break;
+                }
+            };
+            let matched =
+                self.resolver
+                    .match_datagram(&bytes, dns_question, server_addr, &mut malformed);
+            if let Some(dns_response) = matched {
+                return Ok(dns_response);
+            }
+        }
+
+        Err(Resolver::udp_failure(query, malformed))
+    }
+
+    /// Send a question over a new TCP connection and read its response
+    async fn query_tcp(
+        &self,
+        dns_question: &DnsMessage,
+        server_addr: SocketAddr,
+        query: &Query,
+        wait: Duration,
+    ) -> Result<DnsMessage, ResolveError> {
+        if wait.is_zero() {
+            return Err(query.timeout());
+        }
+
+        info!("Querying {} for {} over TCP", query.server, query.name);
+        let msg = dns_question.to_be_bytes();
+        let exchange = async {
+            let mut stream = TcpStream::connect(server_addr).await?;
+            tcp::write_message_async(&mut stream, &msg).await?;
+            tcp::read_message_async(&mut stream).await
+        };
+        let bytes = match time::timeout(wait, exchange).await {
+            Ok(Ok(bytes)) => bytes,
+            Ok(Err(err)) => {
+                debug!("TCP exchange with {} failed: {}", query.server, err);
+                return Err(query.timeout());
+            }
+            Err(_) => {
+                debug!("No response from {} within {:?}", query.server, wait);
+                return Err(query.timeout());
+            }
+        };
+
+        self.resolver.stream_response(&bytes, dns_question, query)
+    }
+
+    /// Shared socket of the address family of a server to send a query from
+    fn socket(&self, server_addr: SocketAddr) -> io::Result<Arc<SharedSocket>> {
+        match server_addr {
+            SocketAddr::V6(_) => self.sockets_v6.take(),
+            SocketAddr::V4(_) => self.sockets_v4.take(),
+        }
+    }
+}
+
+/// Shared sockets of an address family, opened on first use. Queries take
+/// the sockets in turn, and a socket is replaced by one on another random
+/// port once it has carried `SOCKET_QUERIES` queries or stopped receiving,
+/// so the source port keeps changing and is hard to guess (RFC 5452). A
+/// replaced socket lives on until the queries still waiting on it are done
+struct SocketPool {
+    local_ip: IpAddr,
+    slots: Vec<Mutex<Slot>>,
+    next: AtomicUsize,
+}
+
+impl SocketPool {
+    fn new(local_ip: IpAddr) -> SocketPool {
+        SocketPool {
+            local_ip,
+            slots: (0..POOL_SIZE).map(|_| Mutex::new(None)).collect(),
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    /// Take the next socket to send a query from
+    fn take(&self) -> io::Result<Arc<SharedSocket>> {
+        let next = self.next.fetch_add(1, Ordering::Relaxed);
+        let mut slot = self.slots[next % POOL_SIZE].lock().unwrap();
+        match &mut *slot {
+            Some((socket, queries)) if *queries < SOCKET_QUERIES && !socket.is_closed() => {
+                *queries += 1;
+                Ok(socket.clone())
+            }
+            _ => {
+                let socket = Arc::new(SharedSocket::bind(self.local_ip)?);
+                *slot = Some((socket.clone(), 1));
+                Ok(socket)
+            }
+        }
+    }
+}
+
+/// UDP socket shared by the queries in flight. A background task hands
+/// each datagram received to the query waiting for its source and
+/// transaction ID, and stops when the socket is dropped or fails
+struct SharedSocket {
+    socket: Arc<UdpSocket>,
+    pending: Pending,
+    receiver: JoinHandle<()>,
+}
+
+impl SharedSocket {
+    /// Open a socket on a random local port and start receiving on it
+    fn bind(local_ip: IpAddr) -> io::Result<SharedSocket> {
+        let socket = UdpTransport::bind(local_ip)?;
+        socket.set_nonblocking(true)?;
+        let socket = UdpSocket::from_std(socket)?;
+        debug!(
+            "Initialize shared socket at address: {}",
+            socket.local_addr()?
+        );
+
+        let socket = Arc::new(socket);
+        let pending = Arc::new(Mutex::new(Some(Senders::new())));
+        let receiver = tokio::spawn(SharedSocket::receive(socket.clone(), pending.clone()));
+        Ok(SharedSocket {
+            socket,
+            pending,
+            receiver,
+        })
+    }
+
+    /// Hand the datagrams received to the queries waiting for them. When
+    /// the socket fails, the queries waiting on it are told no response
+    /// will come
+    async fn receive(socket: Arc<UdpSocket>, pending: Pending) {
+        let mut buffer = [0; EDNS_PAYLOAD_SIZE as usize];
+        loop {
+            let (number_of_bytes, source) = match socket.recv_from(&mut buffer).await {
+                Ok(received) => received,
+                // An ICMP error about an earlier datagram or an interrupted
+                // call leaves the socket usable
+                Err(err)
+                    if matches!(
+                        err.kind(),
+                        io::ErrorKind::ConnectionRefused
+                            | io::ErrorKind::ConnectionReset
+                            | io::ErrorKind::WouldBlock
+                            | io::ErrorKind::Interrupted
+                    ) =>
+                {
+                    debug!("Can't receive on shared socket: {}", err);
+                    continue;
+                }
+                Err(err) => {
+                    info!("Shared socket stopped receiving: {}", err);
+                    // Dropping the senders ends the wait of the queries
+                    pending.lock().unwrap().take();
+                    return;
+                }
+            };
+            debug!("Received: {} bytes from {}", number_of_bytes, source);
+            if number_of_bytes < 2 {
+                continue;
+            }
+            let id = u16::from_be_bytes([buffer[0], buffer[1]]);
+            let waiting = pending
+                .lock()
+                .unwrap()
+                .as_ref()
+                .and_then(|senders| senders.get(&(source, id)).cloned());
+            match waiting {
+                Some(sender) => {
+                    let _ = sender.send(buffer[..number_of_bytes].to_vec());
+                }
+                None => debug!("Discard unexpected response {} from {}", id, source),
+            }
+        }
+    }
+
+    /// Whether the socket stopped receiving
+    fn is_closed(&self) -> bool {
+        self.pending.lock().unwrap().is_none()
+    }
+
+    /// Wait for responses from a server with a random transaction ID that
+    /// no other query to it is waiting for. The wait ends at once when the
+    /// socket stopped receiving
+    fn register(&self, server_addr: SocketAddr) -> Waiting {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let mut pending = self.pending.lock().unwrap();
+        let mut id = rand::random::<u16>();
+        if let Some(senders) = pending.as_mut() {
+            while senders.contains_key(&(server_addr, id)) {
+                id = rand::random::<u16>();
+            }
+            senders.insert((server_addr, id), sender);
+        }
+
+        Waiting {
+            key: (server_addr, id),
+            pending: self.pending.clone(),
+            receiver,
+        }
+    }
+
+    /// Send a message to a remote address
+    async fn send(&self, msg: &[u8], remote_addr: SocketAddr) -> io::Result<usize> {
+        let number_of_bytes = self.socket.send_to(msg, remote_addr).await?;
+        debug!(
+            "Send a {}-byte message to address: {}",
+            number_of_bytes, remote_addr
+        );
+
+        Ok(number_of_bytes)
+    }
+}
+
+impl Drop for SharedSocket {
+    fn drop(&mut self) {
+        self.receiver.abort();
+    }
+}
+
+/// A query waiting for responses on a shared socket, until it is dropped
+struct Waiting {
+    key: (SocketAddr, u16),
+    pending: Pending,
+    receiver: mpsc::UnboundedReceiver<Vec<u8>>,
+}
+
+impl Waiting {
+    /// Transaction ID the query must be sent with
+    fn id(&self) -> u16 {
+        self.key.1
+    }
+
+    /// Next datagram received from the server with the transaction ID
+    async fn receive(&mut self) -> Option<Vec<u8>> {
+        self.receiver.recv().await
+    }
+}
+
+impl Drop for Waiting {
+    fn drop(&mut self) {
+        if let Some(senders) = self.pending.lock().unwrap().as_mut() {
+            senders.remove(&self.key);
+        }
+    }
+}
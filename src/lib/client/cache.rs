@@ -0,0 +1,673 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::{self, Cursor};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use byteorder::{BigEndian, ReadBytesExt};
+
+use crate::client::lru::LruMap;
+use crate::client::message::DnsMessage;
+use crate::client::rr::{self, ResourceRecord, Soa};
+use crate::client::utility;
+
+/// First bytes of a cache file, followed by the format version
+const FILE_MAGIC: &[u8; 4] = b"DNSC";
+const FILE_VERSION: u8 = 1;
+
+/// Bytes accounted for each entry on top of its names and records, for
+/// the bookkeeping around it
+const ENTRY_OVERHEAD: usize = 64;
+
+/// TTL given to records served after they expired (RFC 8767)
+const STALE_TTL: u32 = 30;
+
+/// Name, type and class identifying a cached RRset
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CacheKey {
+    /// Lowercase owner name without the trailing dot
+    pub name: String,
+    pub rr_type: u16,
+    pub rr_class: u16,
+}
+
+impl CacheKey {
+    pub fn new(name: &str, rr_type: u16, rr_class: u16) -> CacheKey {
+        CacheKey {
+            name: name.trim_end_matches('.').to_ascii_lowercase(),
+            rr_type,
+            rr_class,
+        }
+    }
+}
+
+/// Key of an entry in the cache
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum EntryKey {
+    RRset(CacheKey),
+    /// Lowercase name that does not exist
+    NxDomain(String),
+    NoData(CacheKey),
+}
+
+/// Cached RRset or negative answer
+enum Entry {
+    RRset(RRsetEntry),
+    Negative(NegativeEntry),
+}
+
+/// Records of an RRset and when they expire
+struct RRsetEntry {
+    records: Vec<ResourceRecord>,
+    expires: Instant,
+    /// Time the RRset was stored for
+    ttl: Duration,
+    /// Number of lookups that found the RRset
+    hits: u64,
+    /// Whether a refresh of the RRset has been handed out
+    prefetching: bool,
+}
+
+impl RRsetEntry {
+    /// Bytes accounted for the entry
+    fn size(&self) -> usize {
+        let records = self
+            .records
+            .iter()
+            .map(|rr| rr.rr_name.len() + 10 + rr.rr_rdata.len())
+            .sum::<usize>();
+        ENTRY_OVERHEAD + records
+    }
+}
+
+/// Negative answer and when it expires
+struct NegativeEntry {
+    server: String,
+    soa: Soa,
+    expires: Instant,
+}
+
+impl NegativeEntry {
+    /// Bytes accounted for the entry
+    fn size(&self) -> usize {
+        let soa = &self.soa;
+        ENTRY_OVERHEAD + self.server.len() + soa.zone.len() + soa.m_name.len() + soa.r_name.len()
+    }
+}
+
+/// Kind of negative answer
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NegativeKind {
+    /// The name does not exist for any type
+    NxDomain,
+    /// The name exists but has no record of the type
+    NoData,
+}
+
+/// A cached answer saying a name or record does not exist
+#[derive(Debug, Clone, PartialEq)]
+pub struct NegativeAnswer {
+    pub kind: NegativeKind,
+    /// Name the answer was given for, an ancestor of the name looked up
+    /// when the name is below a name that does not exist
+    pub owner: String,
+    /// Name server that gave the answer
+    pub server: String,
+    /// SOA of the zone that gave the answer, with its TTL lowered by the
+    /// time it has spent in the cache
+    pub soa: Soa,
+}
+
+/// Name servers of the deepest zone cut known for a name
+#[derive(Debug, Clone, PartialEq)]
+pub struct Delegation {
+    /// Zone the servers are authoritative for
+    pub zone: String,
+    /// Host names of the name servers
+    pub name_servers: Vec<String>,
+    /// Cached addresses of the name servers
+    pub addresses: Vec<String>,
+}
+
+/// Counters of what happened in a cache since it was created
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct CacheStats {
+    /// Lookups of an RRset or negative answer that found it
+    pub hits: u64,
+    /// Lookups that found nothing
+    pub misses: u64,
+    /// RRsets and negative answers stored
+    pub insertions: u64,
+    /// Entries removed to stay within the byte budget
+    pub evictions: u64,
+    /// Entries found expired and removed
+    pub expirations: u64,
+    /// Refreshes of popular RRsets handed out before they expired
+    pub prefetches: u64,
+    /// Lookups answered with expired records
+    pub stale_hits: u64,
+    /// Entries currently held
+    pub entries: usize,
+    /// Bytes currently taken by the entries
+    pub bytes: usize,
+    /// Most bytes the entries may take
+    pub budget: usize,
+}
+
+/// Entries and counters, behind the lock of the cache
+struct CacheState {
+    entries: LruMap<EntryKey, Entry>,
+    stats: CacheStats,
+    /// Time expired entries are kept to be served stale
+    max_stale: Duration,
+}
+
+impl CacheState {
+    /// Store an entry, counting the entries evicted for it
+    fn insert(&mut self, key: EntryKey, entry: Entry, size: usize) {
+        self.stats.evictions += self.entries.insert(key, entry, size) as u64;
+        self.stats.insertions += 1;
+    }
+
+    /// Get an unexpired entry with the time it has left. An expired entry
+    /// is removed, once it can't be served stale anymore for an RRset
+    fn fresh(&mut self, key: &EntryKey) -> Option<(&Entry, Duration)> {
+        let (expires, max_stale) = match self.entries.get(key)? {
+            Entry::RRset(entry) => (entry.expires, self.max_stale),
+            Entry::Negative(entry) => (entry.expires, Duration::ZERO),
+        };
+        let now = Instant::now();
+        let remaining = expires.saturating_duration_since(now);
+        if remaining.is_zero() {
+            if now >= expires + max_stale {
+                self.entries.remove(key);
+                self.stats.expirations += 1;
+            }
+            return None;
+        }
+
+        self.entries.peek(key).map(|entry| (entry, remaining))
+    }
+
+    /// Count a lookup that found the RRset of `key` if any, which counts
+    /// towards prefetching it, or else a negative answer
+    fn count_hit(&mut self, key: Option<&CacheKey>) {
+        self.stats.hits += 1;
+        let entry = key.and_then(|key| self.entries.peek_mut(&EntryKey::RRset(key.clone())));
+        if let Some(Entry::RRset(entry)) = entry {
+            entry.hits += 1;
+        }
+    }
+
+    /// Get the records of an RRset with their TTL lowered by the time they
+    /// have spent in the cache, without counting the lookup
+    fn peek_rrset(&mut self, key: &CacheKey) -> Option<Vec<ResourceRecord>> {
+        let key = EntryKey::RRset(key.clone());
+        let (_, remaining) = self.fresh(&key)?;
+        match self.entries.get_mut(&key)? {
+            Entry::RRset(entry) => {
+                let ttl = remaining.as_secs() as u32;
+                let records = entry
+                    .records
+                    .iter()
+                    .map(|record| ResourceRecord {
+                        rr_ttl: ttl,
+                        ..record.clone()
+                    })
+                    .collect();
+                Some(records)
+            }
+            _ => None,
+        }
+    }
+
+    /// Get the records of an RRset that expired less than `max_stale` ago,
+    /// or that has not expired yet, with a short TTL
+    fn stale_rrset(&mut self, key: &CacheKey) -> Option<Vec<ResourceRecord>> {
+        match self.entries.get(&EntryKey::RRset(key.clone()))? {
+            Entry::RRset(entry) if Instant::now() < entry.expires + self.max_stale => {
+                let records = entry
+                    .records
+                    .iter()
+                    .map(|record| ResourceRecord {
+                        rr_ttl: STALE_TTL,
+                        ..record.clone()
+                    })
+                    .collect();
+                Some(records)
+            }
+            _ => None,
+        }
+    }
+
+    /// Get a negative answer for an RRset without counting the lookup. A
+    /// name below a name that does not exist does not exist either
+    /// (RFC 8020)
+    fn peek_negative(&mut self, key: &CacheKey) -> Option<NegativeAnswer> {
+        let mut owner = key.name.as_str();
+        while !owner.is_empty() {
+            if let Some(answer) = self.negative(&EntryKey::NxDomain(owner.to_string()), owner) {
+                return Some(answer);
+            }
+            owner = owner
+                .split_once('.')
+                .map(|(_, parent)| parent)
+                .unwrap_or("");
+        }
+
+        self.negative(&EntryKey::NoData(key.clone()), &key.name)
+    }
+
+    /// Get an unexpired negative answer
+    fn negative(&mut self, key: &EntryKey, owner: &str) -> Option<NegativeAnswer> {
+        let kind = match key {
+            EntryKey::NxDomain(_) => NegativeKind::NxDomain,
+            _ => NegativeKind::NoData,
+        };
+        match self.fresh(key)? {
+            (Entry::Negative(entry), remaining) => Some(NegativeAnswer {
+                kind,
+                owner: owner.to_string(),
+                server: entry.server.clone(),
+                soa: Soa {
+                    ttl: remaining.as_secs() as u32,
+                    ..entry.soa.clone()
+                },
+            }),
+            _ => None,
+        }
+    }
+
+    /// Name servers of a zone and their cached addresses
+    fn zone_servers(&mut self, zone: &str) -> Option<Delegation> {
+        let name_servers = self
+            .peek_rrset(&CacheKey::new(zone, rr::NS, rr::IN))?
+            .iter()
+            .map(|ns| ns.get_domain_name())
+            .collect::<Vec<_>>();
+        let addresses = name_servers
+            .iter()
+            .filter_map(|ns| self.peek_rrset(&CacheKey::new(ns, rr::A, rr::IN)))
+            .flatten()
+            .filter(|rr| rr.is_host_addr())
+            .map(|rr| rr.get_ip_addr())
+            .collect::<Vec<_>>();
+        if addresses.is_empty() {
+            return None;
+        }
+
+        Some(Delegation {
+            zone: zone.to_string(),
+            name_servers,
+            addresses,
+        })
+    }
+
+    /// Number of entries holding an RRset
+    fn rrset_count(&self) -> usize {
+        self.entries
+            .iter()
+            .filter(|(_, entry)| matches!(entry, Entry::RRset(_)))
+            .count()
+    }
+}
+
+/// Thread-safe cache of RRsets and negative answers, kept for their TTL
+/// within a byte budget. The least recently used entries are evicted when
+/// the budget is reached
+pub struct Cache {
+    state: Mutex<CacheState>,
+    /// Shortest time an RRset is kept
+    min_ttl: Duration,
+    /// Longest time an RRset is kept
+    max_ttl: Duration,
+    /// Longest time a negative answer is kept
+    negative_max_ttl: Duration,
+}
+
+impl Cache {
+    /// Create an empty cache keeping RRsets for their TTL, raised to
+    /// `min_ttl` and lowered to `max_ttl`, and then `max_stale` longer to
+    /// be served stale. Negative answers are kept at most
+    /// `negative_max_ttl`. Entries take at most `budget` bytes
+    pub fn new(
+        min_ttl: Duration,
+        max_ttl: Duration,
+        negative_max_ttl: Duration,
+        max_stale: Duration,
+        budget: usize,
+    ) -> Cache {
+        Cache {
+            state: Mutex::new(CacheState {
+                entries: LruMap::new(budget),
+                stats: CacheStats::default(),
+                max_stale,
+            }),
+            min_ttl,
+            max_ttl: max_ttl.max(min_ttl),
+            negative_max_ttl,
+        }
+    }
+
+    /// Store the records of an RRset, replacing what was cached for it. The
+    /// RRset is kept for the lowest TTL of its records
+    pub fn insert(&self, key: CacheKey, records: Vec<ResourceRecord>) {
+        let ttl = match records.iter().map(|rr| rr.rr_ttl).min() {
+            Some(ttl) => Duration::from_secs(ttl as u64).clamp(self.min_ttl, self.max_ttl),
+            None => return,
+        };
+        if ttl.is_zero() {
+            return;
+        }
+
+        let entry = RRsetEntry {
+            records,
+            expires: Instant::now() + ttl,
+            ttl,
+            hits: 0,
+            prefetching: false,
+        };
+        let size = entry.size();
+        let mut state = self.state.lock().unwrap();
+        state.insert(EntryKey::RRset(key), Entry::RRset(entry), size);
+    }
+
+    /// Store every RRset found in a section of a response, skipping
+    /// records whose owner is outside `zone`
+    pub fn insert_records(&self, records: &[ResourceRecord], zone: &str) {
+        let mut rrsets: HashMap<CacheKey, Vec<ResourceRecord>> = HashMap::new();
+        for record in records {
+            let owner = DnsMessage::decode_address(&record.rr_name);
+            if record.rr_type == rr::OPT || !DnsMessage::is_subdomain(&owner, zone) {
+                continue;
+            }
+            rrsets
+                .entry(CacheKey::new(&owner, record.rr_type, record.rr_class))
+                .or_default()
+                .push(record.clone());
+        }
+        for (key, records) in rrsets {
+            self.insert(key, records);
+        }
+    }
+
+    /// Get the records of an RRset with their TTL lowered by the time they
+    /// have spent in the cache
+    pub fn get(&self, name: &str, rr_type: u16, rr_class: u16) -> Option<Vec<ResourceRecord>> {
+        let key = CacheKey::new(name, rr_type, rr_class);
+        let mut state = self.state.lock().unwrap();
+        let records = state.peek_rrset(&key);
+        match records {
+            Some(_) => state.count_hit(Some(&key)),
+            None => state.stats.misses += 1,
+        }
+
+        records
+    }
+
+    /// Get the records of an RRset like `get` without counting the lookup,
+    /// for lookups made of several steps that count their outcome once with
+    /// `count_hit` or `count_miss`
+    pub fn peek(&self, name: &str, rr_type: u16, rr_class: u16) -> Option<Vec<ResourceRecord>> {
+        let mut state = self.state.lock().unwrap();
+        state.peek_rrset(&CacheKey::new(name, rr_type, rr_class))
+    }
+
+    /// Count a lookup that found the RRset of `key`, or a negative answer
+    /// when there is no key. Only RRsets found count towards prefetching
+    pub fn count_hit(&self, key: Option<&CacheKey>) {
+        self.state.lock().unwrap().count_hit(key);
+    }
+
+    /// Count a lookup that found nothing
+    pub fn count_miss(&self) {
+        self.state.lock().unwrap().stats.misses += 1;
+    }
+
+    /// Get the records of an RRset even if they expired, as long as they
+    /// expired less than the max stale time ago. The records get a TTL of
+    /// 30 seconds (RFC 8767)
+    pub fn get_stale(
+        &self,
+        name: &str,
+        rr_type: u16,
+        rr_class: u16,
+    ) -> Option<Vec<ResourceRecord>> {
+        let mut state = self.state.lock().unwrap();
+        let records = state.stale_rrset(&CacheKey::new(name, rr_type, rr_class));
+        if records.is_some() {
+            state.stats.stale_hits += 1;
+        }
+
+        records
+    }
+
+    /// Whether an RRset should be refreshed before it expires: it has been
+    /// found at least `min_hits` times and has less than `ttl_fraction` of
+    /// its TTL left. A refresh is handed out once per stored RRset
+    pub fn claim_prefetch(&self, key: &CacheKey, ttl_fraction: f64, min_hits: u64) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let claimed = match state.entries.peek_mut(&EntryKey::RRset(key.clone())) {
+            Some(Entry::RRset(entry)) => {
+                let remaining = entry.expires.saturating_duration_since(Instant::now());
+                let due = !entry.prefetching
+                    && entry.hits >= min_hits
+                    && !remaining.is_zero()
+                    && remaining <= entry.ttl.mul_f64(ttl_fraction.clamp(0.0, 1.0));
+                entry.prefetching |= due;
+                due
+            }
+            _ => false,
+        };
+        if claimed {
+            state.stats.prefetches += 1;
+        }
+
+        claimed
+    }
+
+    /// Hand out the refresh of an RRset again, after the one claimed with
+    /// `claim_prefetch` failed
+    pub fn release_prefetch(&self, key: &CacheKey) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(Entry::RRset(entry)) = state.entries.peek_mut(&EntryKey::RRset(key.clone())) {
+            entry.prefetching = false;
+        }
+    }
+
+    /// Store that `name` does not exist, as said by `server` with the SOA
+    /// of its zone (RFC 2308)
+    pub fn insert_nxdomain(&self, name: &str, server: &str, soa: &Soa) {
+        if let Some(entry) = self.negative_entry(server, soa) {
+            let name = name.trim_end_matches('.').to_ascii_lowercase();
+            let size = entry.size() + name.len();
+            let mut state = self.state.lock().unwrap();
+            state.insert(EntryKey::NxDomain(name), Entry::Negative(entry), size);
+        }
+    }
+
+    /// Store that `name` has no record of a type, as said by `server` with
+    /// the SOA of its zone
+    pub fn insert_nodata(&self, key: CacheKey, server: &str, soa: &Soa) {
+        if let Some(entry) = self.negative_entry(server, soa) {
+            let size = entry.size() + key.name.len();
+            let mut state = self.state.lock().unwrap();
+            state.insert(EntryKey::NoData(key), Entry::Negative(entry), size);
+        }
+    }
+
+    /// Get a negative answer for an RRset. A name below a name that does
+    /// not exist does not exist either (RFC 8020)
+    pub fn get_negative(&self, name: &str, rr_type: u16, rr_class: u16) -> Option<NegativeAnswer> {
+        let mut state = self.state.lock().unwrap();
+        let answer = state.peek_negative(&CacheKey::new(name, rr_type, rr_class));
+        match answer {
+            Some(_) => state.count_hit(None),
+            None => state.stats.misses += 1,
+        }
+
+        answer
+    }
+
+    /// Get a negative answer for an RRset like `get_negative` without
+    /// counting the lookup
+    pub fn peek_negative(&self, name: &str, rr_type: u16, rr_class: u16) -> Option<NegativeAnswer> {
+        let mut state = self.state.lock().unwrap();
+        state.peek_negative(&CacheKey::new(name, rr_type, rr_class))
+    }
+
+    /// Find the deepest zone enclosing `name` whose name servers and at
+    /// least one of their addresses are cached
+    pub fn delegation(&self, name: &str) -> Option<Delegation> {
+        let name = name.trim_end_matches('.').to_ascii_lowercase();
+        let mut state = self.state.lock().unwrap();
+        let mut zone = name.as_str();
+        while !zone.is_empty() {
+            if let Some(delegation) = state.zone_servers(zone) {
+                return Some(delegation);
+            }
+            zone = zone.split_once('.').map(|(_, parent)| parent).unwrap_or("");
+        }
+
+        None
+    }
+
+    /// Write the unexpired RRsets to a file with their absolute expiry
+    /// times, giving the number of RRsets written
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<usize> {
+        let now = Instant::now();
+        let wall_clock = SystemTime::now();
+        let mut bytes = vec![];
+        let mut count = 0;
+        let state = self.state.lock().unwrap();
+        for (_, entry) in state.entries.iter() {
+            let entry = match entry {
+                Entry::RRset(entry) => entry,
+                Entry::Negative(_) => continue,
+            };
+            let remaining = entry.expires.saturating_duration_since(now);
+            if remaining.is_zero() {
+                continue;
+            }
+            let expires = (wall_clock + remaining)
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default();
+            bytes.extend(expires.as_secs().to_be_bytes());
+            bytes.extend((entry.records.len() as u16).to_be_bytes());
+            for record in &entry.records {
+                bytes.extend(record.to_be_bytes());
+            }
+            count += 1;
+        }
+        drop(state);
+        utility::write_versioned(path.as_ref(), FILE_MAGIC, FILE_VERSION, &bytes)?;
+
+        Ok(count)
+    }
+
+    /// Read RRsets saved by `save`, skipping those that have expired since
+    /// and those already cached. Gives the number of RRsets loaded
+    pub fn load(&self, path: impl AsRef<Path>) -> Result<usize, Box<dyn Error>> {
+        let bytes = utility::read_versioned(path.as_ref(), FILE_MAGIC, FILE_VERSION)?;
+        let mut reader = Cursor::new(bytes.as_slice());
+
+        let mut rrsets = vec![];
+        while (reader.position() as usize) < bytes.len() {
+            let expires = UNIX_EPOCH + Duration::from_secs(reader.read_u64::<BigEndian>()?);
+            let mut records = vec![];
+            let mut offset = reader.position() as usize + 2;
+            for _ in 0..reader.read_u16::<BigEndian>()? {
+                let (next, record) = ResourceRecord::parse(&bytes, offset)?;
+                records.push(record);
+                offset = next;
+            }
+            reader.set_position(offset as u64);
+            rrsets.push((expires, records));
+        }
+
+        let now = Instant::now();
+        let wall_clock = SystemTime::now();
+        let mut state = self.state.lock().unwrap();
+        let mut count = 0;
+        for (expires, records) in rrsets {
+            let remaining = match expires.duration_since(wall_clock) {
+                Ok(remaining) if !remaining.is_zero() => remaining,
+                _ => continue,
+            };
+            let key = match records.first() {
+                Some(record) => EntryKey::RRset(CacheKey::new(
+                    &DnsMessage::decode_address(&record.rr_name),
+                    record.rr_type,
+                    record.rr_class,
+                )),
+                None => continue,
+            };
+            if state.entries.peek(&key).is_some() {
+                continue;
+            }
+            let entry = RRsetEntry {
+                records,
+                expires: now + remaining,
+                ttl: remaining,
+                hits: 0,
+                prefetching: false,
+            };
+            let size = entry.size();
+            state.insert(key, Entry::RRset(entry), size);
+            count += 1;
+        }
+
+        Ok(count)
+    }
+
+    /// Number of cached RRsets, including expired ones not yet removed
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap().rrset_count()
+    }
+
+    /// Number of cached negative answers, including expired ones not yet
+    /// removed
+    pub fn negative_len(&self) -> usize {
+        let state = self.state.lock().unwrap();
+        state.entries.len() - state.rrset_count()
+    }
+
+    /// Whether nothing is cached
+    pub fn is_empty(&self) -> bool {
+        self.state.lock().unwrap().entries.is_empty()
+    }
+
+    /// Remove every cached entry
+    pub fn clear(&self) {
+        self.state.lock().unwrap().entries.clear();
+    }
+
+    /// Counters of the cache with its current size
+    pub fn stats(&self) -> CacheStats {
+        let state = self.state.lock().unwrap();
+        CacheStats {
+            entries: state.entries.len(),
+            bytes: state.entries.bytes(),
+            budget: state.entries.budget(),
+            ..state.stats
+        }
+    }
+
+    /// Build a negative entry kept for the lower of the TTL and the MINIMUM
+    /// field of the SOA
+    fn negative_entry(&self, server: &str, soa: &Soa) -> Option<NegativeEntry> {
+        let ttl = Duration::from_secs(soa.ttl.min(soa.minimum) as u64)
+            .max(self.min_ttl)
+            .min(self.negative_max_ttl);
+        if ttl.is_zero() {
+            return None;
+        }
+
+        Some(NegativeEntry {
+            server: server.to_string(),
+            soa: soa.clone(),
+            expires: Instant::now() + ttl,
+        })
+    }
+}
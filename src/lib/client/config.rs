@@ -0,0 +1,198 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::client::hosts::HOSTS_PATH;
+use crate::client::root_hints::RootHints;
+
+/// UDP payload size advertised with EDNS(0)
+pub const EDNS_PAYLOAD_SIZE: u16 = 1232;
+/// Port name servers listen on
+pub const DNS_PORT: u16 = 53;
+
+/// How names are resolved
+#[derive(Debug, Clone, PartialEq)]
+pub enum ResolverMode {
+    /// Walk the DNS tree from the root servers down to the name
+    Iterative,
+    /// Ask recursive name servers, tried in order, to resolve the name
+    Stub(Vec<String>),
+}
+
+/// Settings that control how a DNS client resolves names
+#[derive(Debug, Clone)]
+pub struct ResolverConfig {
+    /// Whether to resolve names iteratively or through recursive servers
+    pub mode: ResolverMode,
+    /// Time to wait for the response to the first attempt of a query
+    pub query_timeout: Duration,
+    /// Upper bound of the wait after back-off has been applied
+    pub max_query_timeout: Duration,
+    /// Number of extra attempts made against a server that timed out. Other
+    /// servers of the same zone are tried before a server is retried
+    pub retries: u32,
+    /// Time allowed for the whole resolution of a name
+    pub deadline: Duration,
+    /// Randomise the letter case of query names (DNS 0x20) and only accept
+    /// responses that echo it exactly. Servers that do not preserve case
+    /// will then time out
+    pub use_0x20: bool,
+    /// Send every query over TCP instead of only retrying truncated
+    /// responses over TCP
+    pub force_tcp: bool,
+    /// In stub mode, start each resolution at the next name server instead
+    /// of always asking the first one
+    pub rotate: bool,
+    /// Advertise that responses up to `EDNS_PAYLOAD_SIZE` bytes can be
+    /// received over UDP with an EDNS(0) OPT record
+    pub edns0: bool,
+    /// Domains tried in order for relative names
+    pub search: Vec<String>,
+    /// Number of dots a relative name needs to be tried as is before the
+    /// search list rather than after it
+    pub ndots: usize,
+    /// Hosts file consulted before asking name servers
+    pub hosts_path: Option<PathBuf>,
+    /// Servers iterative resolution starts from
+    pub root_hints: RootHints,
+    /// Port name servers are asked on when their address doesn't give one,
+    /// as for addresses learnt from referrals
+    pub port: u16,
+    /// Refresh the root servers with a priming query before the first
+    /// iterative resolution
+    pub prime_roots: bool,
+    /// Time to wait before priming again after priming failed, doubling
+    /// after each further failure
+    pub prime_retry: Duration,
+    /// Reveal to the servers of each zone only one label more of a name
+    /// than the zone has (RFC 9156), asking for the full name instead when
+    /// a server denies that a name without records of its own exists
+    pub qname_minimisation: bool,
+    /// Most minimised queries sent for a name, after which the full name
+    /// is asked for
+    pub max_minimise_count: usize,
+    /// Number of minimised queries adding a single label each. Later ones
+    /// add several so that deep names stay within `max_minimise_count`
+    pub minimise_one_label: usize,
+    /// Probability of asking another name server of a zone first than the
+    /// one with the lowest round trip time
+    pub rtt_exploration: f64,
+    /// Time it takes for the penalty of a name server that failed to halve
+    pub penalty_half_life: Duration,
+    /// Keep answers and delegations for their TTL and reuse them
+    pub cache: bool,
+    /// Shortest time a cached RRset is kept, raising lower TTLs
+    pub cache_min_ttl: Duration,
+    /// Longest time a cached RRset is kept, lowering higher TTLs
+    pub cache_max_ttl: Duration,
+    /// Longest time a cached NXDOMAIN or NODATA answer is kept
+    pub cache_negative_max_ttl: Duration,
+    /// File the cached RRsets are loaded from when the client is created
+    /// and saved to by `DnsClient::save_cache`
+    pub cache_path: Option<PathBuf>,
+    /// Most bytes the cached RRsets and negative answers may take
+    pub cache_size: usize,
+    /// Most bytes the round trip statistics of name servers may take
+    pub infra_cache_size: usize,
+    /// Answer with expired cached records when no name server gives a
+    /// usable answer (RFC 8767)
+    pub serve_stale: bool,
+    /// Longest time after expiry a record may be served stale
+    pub max_stale: Duration,
+    /// Refresh cached addresses in the background when they are looked up
+    /// shortly before they expire
+    pub prefetch: bool,
+    /// Fraction of its TTL an RRset has left when a lookup refreshes it
+    pub prefetch_ttl_fraction: f64,
+    /// Number of lookups an RRset needs before it is worth refreshing
+    pub prefetch_min_hits: u64,
+}
+
+impl ResolverConfig {
+    /// Names to try in order when looking up `name`. A name ending with a
+    /// dot is absolute and only tried as is. Other names are tried as is
+    /// first when they have at least `ndots` dots, and last otherwise
+    pub fn search_candidates(&self, name: &str) -> Vec<String> {
+        if name.ends_with('.') {
+            return vec![name.trim_end_matches('.').to_string()];
+        }
+
+        let mut candidates = self
+            .search
+            .iter()
+            .map(|domain| format!("{}.{}", name, domain.trim_matches('.')))
+            .collect::<Vec<_>>();
+        if name.matches('.').count() >= self.ndots {
+            candidates.insert(0, name.to_string());
+        } else {
+            candidates.push(name.to_string());
+        }
+
+        candidates
+    }
+
+    /// Number of labels of a name of `total` labels to ask for in the next
+    /// minimised query, after `count` minimised queries revealed `revealed`
+    /// labels (RFC 9156 section 2.3)
+    pub fn minimised_labels(&self, total: usize, revealed: usize, count: usize) -> usize {
+        if count >= self.max_minimise_count {
+            return total;
+        }
+        if count < self.minimise_one_label {
+            return (revealed + 1).min(total);
+        }
+
+        let remaining = total.saturating_sub(revealed);
+        let queries_left = self.max_minimise_count - count;
+        (revealed + remaining.div_ceil(queries_left)).min(total)
+    }
+
+    /// Time to wait for a response on the given attempt, starting from 0,
+    /// doubling for each retry
+    pub fn timeout_for_attempt(&self, attempt: u32) -> Duration {
+        let factor = 1u32.checked_shl(attempt).unwrap_or(u32::MAX);
+        self.query_timeout
+            .checked_mul(factor)
+            .unwrap_or(self.max_query_timeout)
+            .min(self.max_query_timeout)
+    }
+}
+
+impl Default for ResolverConfig {
+    fn default() -> Self {
+        ResolverConfig {
+            mode: ResolverMode::Iterative,
+            query_timeout: Duration::from_millis(800),
+            max_query_timeout: Duration::from_secs(4),
+            retries: 2,
+            deadline: Duration::from_secs(15),
+            use_0x20: false,
+            force_tcp: false,
+            rotate: false,
+            edns0: false,
+            search: vec![],
+            ndots: 1,
+            hosts_path: Some(PathBuf::from(HOSTS_PATH)),
+            root_hints: RootHints::builtin(),
+            port: DNS_PORT,
+            prime_roots: true,
+            prime_retry: Duration::from_secs(30),
+            qname_minimisation: true,
+            max_minimise_count: 10,
+            minimise_one_label: 4,
+            rtt_exploration: 0.05,
+            penalty_half_life: Duration::from_secs(60),
+            cache: true,
+            cache_min_ttl: Duration::ZERO,
+            cache_max_ttl: Duration::from_secs(86400),
+            cache_negative_max_ttl: Duration::from_secs(10800),
+            cache_path: None,
+            cache_size: 16 << 20,
+            infra_cache_size: 1 << 20,
+            serve_stale: false,
+            max_stale: Duration::from_secs(86400),
+            prefetch: false,
+            prefetch_ttl_fraction: 0.1,
+            prefetch_min_hits: 2,
+        }
+    }
+}
@@ -0,0 +1,207 @@
+use std::error::Error;
+use std::fmt;
+
+use crate::client::rr::Soa;
+
+/// Reasons a host name could not be resolved. `server` is the address of
+/// the name server that produced the failure and `step` is the number of
+/// queries sent during the resolution when it happened
+#[derive(Debug, Clone, PartialEq)]
+pub enum ResolveError {
+    /// The name does not exist, with the SOA of the zone that said so
+    NxDomain {
+        name: String,
+        server: String,
+        step: usize,
+        soa: Option<Box<Soa>>,
+    },
+    /// The name exists but has no record of the requested type
+    NoData {
+        name: String,
+        server: String,
+        step: usize,
+        soa: Option<Box<Soa>>,
+    },
+    /// The server failed to process the query
+    ServFail {
+        name: String,
+        server: String,
+        step: usize,
+    },
+    /// The server refused to answer the query
+    Refused {
+        name: String,
+        server: String,
+        step: usize,
+    },
+    /// The server did not answer in time
+    Timeout {
+        name: String,
+        server: String,
+        step: usize,
+    },
+    /// The server was delegated `zone` but is not authoritative for it
+    LameDelegation {
+        name: String,
+        server: String,
+        step: usize,
+        zone: String,
+    },
+    /// The response could not be parsed or did not make sense
+    Malformed {
+        server: String,
+        step: usize,
+        reason: String,
+    },
+    /// The resolution went around in circles through referrals or aliases
+    LoopDetected {
+        name: String,
+        step: usize,
+        reason: String,
+    },
+    /// DNSSEC validation of the answer failed
+    Bogus {
+        name: String,
+        server: String,
+        step: usize,
+        reason: String,
+    },
+}
+
+impl ResolveError {
+    /// Exit code of the CLI for this error
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            ResolveError::NxDomain { .. } => 2,
+            ResolveError::NoData { .. } => 3,
+            ResolveError::ServFail { .. } => 4,
+            ResolveError::Refused { .. } => 5,
+            ResolveError::Timeout { .. } => 6,
+            ResolveError::LameDelegation { .. } => 7,
+            ResolveError::Malformed { .. } => 8,
+            ResolveError::LoopDetected { .. } => 9,
+            ResolveError::Bogus { .. } => 10,
+        }
+    }
+
+    /// Whether the error is a definitive answer about the name, so asking
+    /// another server will not change the outcome
+    pub fn is_final(&self) -> bool {
+        matches!(
+            self,
+            ResolveError::NxDomain { .. }
+                | ResolveError::NoData { .. }
+                | ResolveError::LoopDetected { .. }
+                | ResolveError::Bogus { .. }
+        )
+    }
+
+    /// Server that produced the error, if any
+    pub fn server(&self) -> Option<&str> {
+        match self {
+            ResolveError::NxDomain { server, .. }
+            | ResolveError::NoData { server, .. }
+            | ResolveError::ServFail { server, .. }
+            | ResolveError::Refused { server, .. }
+            | ResolveError::Timeout { server, .. }
+            | ResolveError::LameDelegation { server, .. }
+            | ResolveError::Malformed { server, .. }
+            | ResolveError::Bogus { server, .. } => Some(server),
+            ResolveError::LoopDetected { .. } => None,
+        }
+    }
+}
+
+impl fmt::Display for ResolveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ResolveError::NxDomain {
+                name,
+                server,
+                step,
+                soa,
+            } => {
+                write!(
+                    f,
+                    "{} does not exist (NXDOMAIN from {} at step {}",
+                    name, server, step
+                )?;
+                if let Some(soa) = soa {
+                    write!(f, ", zone {}", soa.zone)?;
+                }
+                write!(f, ")")
+            }
+            ResolveError::NoData {
+                name,
+                server,
+                step,
+                soa,
+            } => {
+                write!(
+                    f,
+                    "{} has no such record (NODATA from {} at step {}",
+                    name, server, step
+                )?;
+                if let Some(soa) = soa {
+                    write!(f, ", zone {}", soa.zone)?;
+                }
+                write!(f, ")")
+            }
+            ResolveError::ServFail { name, server, step } => {
+                write!(
+                    f,
+                    "{} failed resolving {} (SERVFAIL at step {})",
+                    server, name, step
+                )
+            }
+            ResolveError::Refused { name, server, step } => {
+                write!(
+                    f,
+                    "{} refused to resolve {} (REFUSED at step {})",
+                    server, name, step
+                )
+            }
+            ResolveError::Timeout { name, server, step } => {
+                write!(f, "{} timed out resolving {} (step {})", server, name, step)
+            }
+            ResolveError::LameDelegation {
+                name,
+                server,
+                step,
+                zone,
+            } => write!(
+                f,
+                "{} is lame for zone {} while resolving {} (step {})",
+                server, zone, name, step
+            ),
+            ResolveError::Malformed {
+                server,
+                step,
+                reason,
+            } => write!(
+                f,
+                "Malformed response from {} at step {}: {}",
+                server, step, reason
+            ),
+            ResolveError::LoopDetected { name, step, reason } => {
+                write!(
+                    f,
+                    "Loop detected resolving {} at step {}: {}",
+                    name, step, reason
+                )
+            }
+            ResolveError::Bogus {
+                name,
+                server,
+                step,
+                reason,
+            } => write!(
+                f,
+                "DNSSEC validation of {} from {} failed at step {}: {}",
+                name, server, step, reason
+            ),
+        }
+    }
+}
+
+impl Error for ResolveError {}
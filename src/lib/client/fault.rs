@@ -0,0 +1,228 @@
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use spdlog::prelude::*;
+use std::collections::VecDeque;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::client::transport::{Connection, Transport};
+
+/// Chances, between 0 and 1, of each fault hitting a packet. All are zero by
+/// default
+#[derive(Debug, Clone)]
+pub struct Faults {
+    /// Chance of losing a query or a response
+    pub drop: f64,
+    /// Chance of holding a response back for up to `max_delay`
+    pub delay: f64,
+    /// Longest time a delayed response is held back
+    pub max_delay: Duration,
+    /// Chance of a response being held back and delivered to the next query
+    /// to the same server, ahead of that query's own response
+    pub reorder: f64,
+    /// Chance of a response arriving twice
+    pub duplicate: f64,
+    /// Chance of a response being cut short
+    pub truncate: f64,
+    /// Chance of a bit of a response being flipped
+    pub corrupt: f64,
+}
+
+impl Default for Faults {
+    fn default() -> Self {
+        Faults {
+            drop: 0.0,
+            delay: 0.0,
+            max_delay: Duration::ZERO,
+            reorder: 0.0,
+            duplicate: 0.0,
+            truncate: 0.0,
+            corrupt: 0.0,
+        }
+    }
+}
+
+/// Fault injected into the traffic of a `FaultyTransport`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Fault {
+    /// A query to the server was lost
+    DropQuery(SocketAddr),
+    /// A response from the server was lost
+    DropResponse(SocketAddr),
+    /// A response was held back for the given time
+    Delay(SocketAddr, Duration),
+    /// A response was held back for the next query to the server
+    Reorder(SocketAddr),
+    /// A response was delivered twice
+    Duplicate(SocketAddr),
+    /// A response was cut to the given length
+    Truncate(SocketAddr, usize),
+    /// The given bit of a response was flipped
+    Corrupt(SocketAddr, usize),
+}
+
+/// Responses held back by reordering, with the server they came from
+type Held = Arc<Mutex<VecDeque<(SocketAddr, Vec<u8>)>>>;
+
+/// Wraps a transport to drop, delay, reorder, duplicate, truncate or
+/// corrupt the packets it carries. Faults are drawn from a random generator
+/// seeded at creation, so a seed always gives the same faults for the same
+/// traffic. Clones share the generator, the log of faults and the responses
+/// held back
+#[derive(Clone)]
+pub struct FaultyTransport<T> {
+    inner: T,
+    faults: Arc<Faults>,
+    rng: Arc<Mutex<StdRng>>,
+    injected: Arc<Mutex<Vec<Fault>>>,
+    held: Held,
+}
+
+impl<T: Transport> FaultyTransport<T> {
+    /// Wrap `inner`, injecting `faults` drawn from a generator seeded with
+    /// `seed`
+    pub fn new(inner: T, faults: Faults, seed: u64) -> FaultyTransport<T> {
+        FaultyTransport {
+            inner,
+            faults: Arc::new(faults),
+            rng: Arc::new(Mutex::new(StdRng::seed_from_u64(seed))),
+            injected: Arc::new(Mutex::new(Vec::new())),
+            held: Held::default(),
+        }
+    }
+
+    /// Faults injected so far, in order
+    pub fn injected(&self) -> Vec<Fault> {
+        self.injected.lock().unwrap().clone()
+    }
+}
+
+impl<T: Transport> Transport for FaultyTransport<T> {
+    type Connection = FaultyConnection<T::Connection>;
+
+    fn connect(&self, server_addr: SocketAddr, deadline: Instant) -> io::Result<Self::Connection> {
+        Ok(FaultyConnection {
+            inner: self.inner.connect(server_addr, deadline)?,
+            server_addr,
+            faults: self.faults.clone(),
+            rng: self.rng.clone(),
+            injected: self.injected.clone(),
+            held: self.held.clone(),
+            pending: VecDeque::new(),
+        })
+    }
+}
+
+/// Connection of a `FaultyTransport`
+pub struct FaultyConnection<C> {
+    inner: C,
+    server_addr: SocketAddr,
+    faults: Arc<Faults>,
+    rng: Arc<Mutex<StdRng>>,
+    injected: Arc<Mutex<Vec<Fault>>>,
+    held: Held,
+    /// Copies of duplicated responses still to be delivered
+    pending: VecDeque<Vec<u8>>,
+}
+
+impl<C: Connection> FaultyConnection<C> {
+    /// Whether a fault with the given chance hits
+    fn hits(&self, chance: f64) -> bool {
+        chance > 0.0 && self.rng.lock().unwrap().gen_bool(chance.min(1.0))
+    }
+
+    fn inject(&self, fault: Fault) {
+        debug!("Inject fault: {:?}", fault);
+        self.injected.lock().unwrap().push(fault);
+    }
+
+    /// Hold a response back, losing it when it would arrive past `deadline`
+    fn delay(&self, deadline: Instant) -> io::Result<()> {
+        let max_delay = self.faults.max_delay.as_micros() as u64;
+        let delay = Duration::from_micros(self.rng.lock().unwrap().gen_range(0..=max_delay));
+        self.inject(Fault::Delay(self.server_addr, delay));
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        thread::sleep(delay.min(remaining));
+        if delay > remaining {
+            return Err(io::ErrorKind::TimedOut.into());
+        }
+
+        Ok(())
+    }
+
+    /// Take the oldest response held back from the server for an earlier
+    /// query
+    fn take_held(&self) -> Option<Vec<u8>> {
+        let mut held = self.held.lock().unwrap();
+        let position = held
+            .iter()
+            .position(|(server_addr, _)| *server_addr == self.server_addr)?;
+        held.remove(position).map(|(_, response)| response)
+    }
+
+    /// Damage a response by cutting it short or flipping one of its bits
+    fn damage(&self, mut response: Vec<u8>) -> Vec<u8> {
+        if !response.is_empty() && self.hits(self.faults.truncate) {
+            let len = self.rng.lock().unwrap().gen_range(0..response.len());
+            self.inject(Fault::Truncate(self.server_addr, len));
+            response.truncate(len);
+        }
+        if !response.is_empty() && self.hits(self.faults.corrupt) {
+            let bit = self.rng.lock().unwrap().gen_range(0..response.len() * 8);
+            self.inject(Fault::Corrupt(self.server_addr, bit));
+            response[bit / 8] ^= 1 << (bit % 8);
+        }
+
+        response
+    }
+}
+
+impl<C: Connection> Connection for FaultyConnection<C> {
+    fn send(&mut self, msg: &[u8]) -> io::Result<()> {
+        if self.hits(self.faults.drop) {
+            self.inject(Fault::DropQuery(self.server_addr));
+            return Ok(());
+        }
+
+        self.inner.send(msg)
+    }
+
+    fn receive(&mut self, deadline: Instant) -> io::Result<Vec<u8>> {
+        if let Some(response) = self.pending.pop_front() {
+            return Ok(response);
+        }
+        // Responses to earlier queries overtaken by this one arrive first
+        if let Some(response) = self.take_held() {
+            return Ok(response);
+        }
+
+        loop {
+            let response = self.inner.receive(deadline)?;
+            if self.hits(self.faults.drop) {
+                self.inject(Fault::DropResponse(self.server_addr));
+                continue;
+            }
+            if self.hits(self.faults.delay) {
+                self.delay(deadline)?;
+            }
+            let response = self.damage(response);
+            if self.hits(self.faults.reorder) {
+                self.inject(Fault::Reorder(self.server_addr));
+                self.held
+                    .lock()
+                    .unwrap()
+                    .push_back((self.server_addr, response));
+                continue;
+            }
+            if self.hits(self.faults.duplicate) {
+                self.inject(Fault::Duplicate(self.server_addr));
+                self.pending.push_back(response.clone());
+            }
+
+            return Ok(response);
+        }
+    }
+}
@@ -115,7 +115,8 @@ impl Header {
     }
 
     /// Parse a vector of bytes to DNS header
-    pub fn parse(message: &Vec<u8>, start: usize) -> Result<(usize, Header), Box<dyn Error>> {
+    pub fn parse(message: &[u8], start: usize) -> Result<(usize, Header), Box<dyn Error>> {
+        utility::get_slice(message, start, start + 12)?;
         let id = utility::to_u16(&message[start..start + 2]);
         let flags = Flag::parse(&message[start + 2..start + 4]);
         let qd_cnt = utility::to_u16(&message[start + 4..start + 6]);
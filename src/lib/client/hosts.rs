@@ -0,0 +1,123 @@
+use std::collections::HashMap;
+use std::fs;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use spdlog::prelude::*;
+
+/// Location of the system hosts file
+pub const HOSTS_PATH: &str = "/etc/hosts";
+
+/// Static host name to address mappings in the /etc/hosts format
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Hosts {
+    /// Addresses of each lowercase host name, in file order
+    by_name: HashMap<String, Vec<IpAddr>>,
+    /// Names of each address, canonical name first
+    by_addr: HashMap<IpAddr, Vec<String>>,
+}
+
+impl Hosts {
+    /// Parse the content of a hosts file. Each line holds an address
+    /// followed by a canonical name and any aliases, `#` starts a comment
+    /// and lines with an invalid address are skipped
+    pub fn parse(content: &str) -> Hosts {
+        let mut hosts = Hosts::default();
+        for line in content.lines() {
+            let line = line.split('#').next().unwrap_or_default();
+            let mut words = line.split_whitespace();
+            let ip_addr = match words.next().map(|word| word.parse::<IpAddr>()) {
+                Some(Ok(ip_addr)) => ip_addr,
+                Some(Err(_)) => {
+                    debug!("Skip invalid hosts line: {}", line);
+                    continue;
+                }
+                None => continue,
+            };
+            for name in words {
+                let name = name.trim_end_matches('.');
+                let ip_addrs = hosts.by_name.entry(name.to_ascii_lowercase()).or_default();
+                if !ip_addrs.contains(&ip_addr) {
+                    ip_addrs.push(ip_addr);
+                }
+                let names = hosts.by_addr.entry(ip_addr).or_default();
+                if !names.iter().any(|n| n.eq_ignore_ascii_case(name)) {
+                    names.push(name.to_string());
+                }
+            }
+        }
+
+        hosts
+    }
+
+    /// Get the addresses of a host name
+    pub fn lookup(&self, name: &str) -> Option<Vec<IpAddr>> {
+        let name = name.trim_end_matches('.').to_ascii_lowercase();
+        self.by_name.get(&name).cloned()
+    }
+
+    /// Get the host names of an address, canonical name first
+    pub fn reverse(&self, ip_addr: IpAddr) -> Option<Vec<String>> {
+        self.by_addr.get(&ip_addr).cloned()
+    }
+}
+
+/// A hosts file on disk, parsed again whenever it changes
+pub struct HostsFile {
+    path: PathBuf,
+    /// Modification time of the file when it was last parsed
+    state: Mutex<(Option<SystemTime>, Hosts)>,
+}
+
+impl HostsFile {
+    /// Create a hosts file source reading from `path`
+    pub fn new(path: impl AsRef<Path>) -> HostsFile {
+        HostsFile {
+            path: path.as_ref().to_path_buf(),
+            state: Mutex::new((None, Hosts::default())),
+        }
+    }
+
+    /// Create a hosts file source reading the system hosts file
+    pub fn system() -> HostsFile {
+        HostsFile::new(HOSTS_PATH)
+    }
+
+    /// Path of the file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Get the addresses of a host name
+    pub fn lookup(&self, name: &str) -> Option<Vec<IpAddr>> {
+        self.refreshed(|hosts| hosts.lookup(name))
+    }
+
+    /// Get the host names of an address, canonical name first
+    pub fn reverse(&self, ip_addr: IpAddr) -> Option<Vec<String>> {
+        self.refreshed(|hosts| hosts.reverse(ip_addr))
+    }
+
+    /// Run `f` on the entries, parsing the file again first if it has been
+    /// modified. A file that can't be read has no entries
+    fn refreshed<T>(&self, f: impl FnOnce(&Hosts) -> T) -> T {
+        let mut state = self.state.lock().unwrap();
+        let modified = fs::metadata(&self.path)
+            .and_then(|metadata| metadata.modified())
+            .ok();
+        if modified.is_none() || modified != state.0 {
+            state.1 = match fs::read_to_string(&self.path) {
+                Ok(content) => Hosts::parse(&content),
+                Err(err) => {
+                    debug!("Can't read {}: {}", self.path.display(), err);
+                    Hosts::default()
+                }
+            };
+            state.0 = modified;
+        }
+
+        f(&state.1)
+    }
+}
@@ -0,0 +1,179 @@
+use std::mem;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use rand::Rng;
+
+use crate::client::lru::LruMap;
+
+/// Weight of a new round trip time sample in the smoothed round trip time
+const RTT_GAIN: f64 = 0.3;
+/// Upper bound of the penalty of a server that keeps failing
+const MAX_PENALTY: Duration = Duration::from_secs(30);
+/// Bytes accounted for each server on top of its address
+const ENTRY_OVERHEAD: usize = 64 + mem::size_of::<ServerStats>();
+
+/// Round trip statistics of a name server
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServerStats {
+    /// Smoothed round trip time of the responses
+    pub srtt: Duration,
+    /// Time added for failures, halved every half-life since the last one
+    pub penalty: Duration,
+    /// Number of failures since the last response
+    pub failures: u32,
+    /// Number of responses received
+    pub responses: u64,
+    /// When the penalty was last raised
+    pub last_failure: Option<Instant>,
+}
+
+impl ServerStats {
+    /// Round trip time used to rank the server, the smoothed round trip
+    /// time plus what is left of the penalty
+    pub fn effective_rtt(&self, half_life: Duration) -> Duration {
+        let penalty = match self.last_failure {
+            Some(last_failure) if !half_life.is_zero() => {
+                let half_lives = last_failure.elapsed().as_secs_f64() / half_life.as_secs_f64();
+                self.penalty.mul_f64(0.5f64.powf(half_lives))
+            }
+            _ => self.penalty,
+        };
+        self.srtt + penalty
+    }
+}
+
+/// Infrastructure cache of how well each name server has been answering,
+/// used to ask the fastest server of a zone first. The least recently used
+/// servers are forgotten when the byte budget is reached
+pub struct InfraCache {
+    servers: Mutex<LruMap<String, ServerStats>>,
+    /// Probability of asking another server than the fastest one first
+    exploration: f64,
+    /// Time it takes for the penalty of a failing server to halve
+    half_life: Duration,
+}
+
+impl InfraCache {
+    /// Create an empty infrastructure cache taking at most `budget` bytes
+    pub fn new(exploration: f64, half_life: Duration, budget: usize) -> InfraCache {
+        InfraCache {
+            servers: Mutex::new(LruMap::new(budget)),
+            exploration: exploration.clamp(0.0, 1.0),
+            half_life,
+        }
+    }
+
+    /// Record the round trip time of a response from a server
+    pub fn record_rtt(&self, server: &str, rtt: Duration) {
+        let mut servers = self.servers.lock().unwrap();
+        match servers.get_mut(server) {
+            Some(stats) => {
+                stats.srtt = if stats.responses == 0 {
+                    rtt
+                } else {
+                    stats.srtt.mul_f64(1.0 - RTT_GAIN) + rtt.mul_f64(RTT_GAIN)
+                };
+                stats.responses += 1;
+                stats.failures = 0;
+            }
+            None => {
+                let stats = ServerStats {
+                    srtt: rtt,
+                    penalty: Duration::ZERO,
+                    failures: 0,
+                    responses: 1,
+                    last_failure: None,
+                };
+                servers.insert(server.to_string(), stats, server.len() + ENTRY_OVERHEAD);
+            }
+        }
+    }
+
+    /// Record that a server did not answer within `timeout`
+    pub fn record_failure(&self, server: &str, timeout: Duration) {
+        let mut servers = self.servers.lock().unwrap();
+        if servers.peek(server).is_none() {
+            let stats = ServerStats {
+                srtt: Duration::ZERO,
+                penalty: Duration::ZERO,
+                failures: 0,
+                responses: 0,
+                last_failure: None,
+            };
+            servers.insert(server.to_string(), stats, server.len() + ENTRY_OVERHEAD);
+        }
+        let stats = match servers.get_mut(server) {
+            Some(stats) => stats,
+            // The server does not fit in the budget
+            None => return,
+        };
+        let penalty = stats.effective_rtt(self.half_life) - stats.srtt;
+        stats.penalty = (penalty + timeout).min(MAX_PENALTY);
+        stats.failures += 1;
+        stats.last_failure = Some(Instant::now());
+    }
+
+    /// Statistics of a server, if it has been asked before
+    pub fn get(&self, server: &str) -> Option<ServerStats> {
+        self.servers.lock().unwrap().peek(server).cloned()
+    }
+
+    /// Round trip time used to rank a server. Servers that have never been
+    /// asked rank first so that each one gets measured
+    pub fn effective_rtt(&self, server: &str) -> Duration {
+        self.get(server)
+            .map(|stats| stats.effective_rtt(self.half_life))
+            .unwrap_or(Duration::ZERO)
+    }
+
+    /// Order servers from fastest to slowest. Now and then another server
+    /// is moved to the front so that recovered servers get noticed
+    pub fn order(&self, servers: &[String]) -> Vec<String> {
+        let mut ordered = servers
+            .iter()
+            .map(|server| (self.effective_rtt(server), server.clone()))
+            .collect::<Vec<_>>();
+        ordered.sort_by_key(|(rtt, _)| *rtt);
+        let mut ordered = ordered
+            .into_iter()
+            .map(|(_, server)| server)
+            .collect::<Vec<_>>();
+
+        let mut rng = rand::thread_rng();
+        if ordered.len() > 1 && rng.gen_bool(self.exploration) {
+            let explored = ordered.remove(rng.gen_range(1..ordered.len()));
+            ordered.insert(0, explored);
+        }
+
+        ordered
+    }
+
+    /// Statistics of every known server, fastest first
+    pub fn servers(&self) -> Vec<(String, ServerStats)> {
+        let mut servers = self
+            .servers
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(server, stats)| (server.clone(), stats.clone()))
+            .collect::<Vec<_>>();
+        servers.sort_by_key(|(_, stats)| stats.effective_rtt(self.half_life));
+        servers
+    }
+
+    /// Number of servers known
+    pub fn len(&self) -> usize {
+        self.servers.lock().unwrap().len()
+    }
+
+    /// Whether no server is known
+    pub fn is_empty(&self) -> bool {
+        self.servers.lock().unwrap().is_empty()
+    }
+
+    /// Bytes taken by the statistics of the servers
+    pub fn bytes(&self) -> usize {
+        self.servers.lock().unwrap().bytes()
+    }
+}
@@ -0,0 +1,15 @@
+/// Outcome of a successful lookup of a host name
+#[derive(Debug, Clone, PartialEq)]
+pub struct LookupResult {
+    /// Fully qualified name that was found, after search list expansion
+    pub name: String,
+    /// Addresses of the host
+    pub ip_addrs: Vec<String>,
+    /// The addresses come from expired cached records because the name
+    /// servers could not be reached (RFC 8767)
+    pub stale: bool,
+    /// Seconds the addresses may still be used before looking the name up
+    /// again: the lowest TTL of the records, as long as they have left in
+    /// the cache, or the short TTL given to stale records
+    pub ttl: u32,
+}
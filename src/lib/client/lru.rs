@@ -0,0 +1,193 @@
+use std::borrow::Borrow;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+
+/// A value with its accounted size and when it was last used
+struct Slot<V> {
+    value: V,
+    size: usize,
+    tick: u64,
+}
+
+/// Map holding at most `budget` bytes of values, evicting the least
+/// recently used ones to make room. Sizes are given by the caller
+pub struct LruMap<K, V> {
+    slots: HashMap<K, Slot<V>>,
+    /// Keys by the tick they were last used at, oldest first
+    order: BTreeMap<u64, K>,
+    tick: u64,
+    bytes: usize,
+    budget: usize,
+}
+
+impl<K: Clone + Eq + Hash, V> LruMap<K, V> {
+    /// Create an empty map holding at most `budget` bytes
+    pub fn new(budget: usize) -> LruMap<K, V> {
+        LruMap {
+            slots: HashMap::new(),
+            order: BTreeMap::new(),
+            tick: 0,
+            bytes: 0,
+            budget,
+        }
+    }
+
+    /// Get a value, marking it as the most recently used
+    pub fn get<Q>(&mut self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.touch(key)?;
+        self.slots.get(key).map(|slot| &slot.value)
+    }
+
+    /// Get a value to change it in place, marking it as the most recently
+    /// used. Its size is not updated
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.touch(key)?;
+        self.slots.get_mut(key).map(|slot| &mut slot.value)
+    }
+
+    /// Get a value without marking it as used
+    pub fn peek<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.slots.get(key).map(|slot| &slot.value)
+    }
+
+    /// Get a value to change it in place without marking it as used. Its
+    /// size is not updated
+    pub fn peek_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.slots.get_mut(key).map(|slot| &mut slot.value)
+    }
+
+    /// Insert a value taking `size` bytes, replacing the value of the key.
+    /// Least recently used values are evicted until it fits and their
+    /// number is given. A value larger than the whole budget is not kept
+    pub fn insert(&mut self, key: K, value: V, size: usize) -> usize {
+        self.remove(&key);
+        if size > self.budget {
+            return 0;
+        }
+
+        let mut evicted = 0;
+        while self.bytes + size > self.budget {
+            match self.order.keys().next().copied() {
+                Some(tick) => {
+                    let oldest = self.order.remove(&tick).unwrap();
+                    if let Some(slot) = self.slots.remove(&oldest) {
+                        self.bytes -= slot.size;
+                        evicted += 1;
+                    }
+                }
+                None => break,
+            }
+        }
+
+        self.tick += 1;
+        self.order.insert(self.tick, key.clone());
+        self.slots.insert(
+            key,
+            Slot {
+                value,
+                size,
+                tick: self.tick,
+            },
+        );
+        self.bytes += size;
+
+        evicted
+    }
+
+    /// Remove a value
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        let slot = self.slots.remove(key)?;
+        self.order.remove(&slot.tick);
+        self.bytes -= slot.size;
+        Some(slot.value)
+    }
+
+    /// Values from the least to the most recently used
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        self.order
+            .values()
+            .filter_map(|key| self.slots.get_key_value(key))
+            .map(|(key, slot)| (key, &slot.value))
+    }
+
+    /// Number of values
+    pub fn len(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// Whether the map holds no value
+    pub fn is_empty(&self) -> bool {
+        self.slots.is_empty()
+    }
+
+    /// Bytes taken by the values
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    /// Most bytes the values may take
+    pub fn budget(&self) -> usize {
+        self.budget
+    }
+
+    /// Remove every value
+    pub fn clear(&mut self) {
+        self.slots.clear();
+        self.order.clear();
+        self.bytes = 0;
+    }
+
+    /// Mark a value as the most recently used
+    fn touch<Q>(&mut self, key: &Q) -> Option<()>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        let slot = self.slots.get_mut(key)?;
+        let owned = self.order.remove(&slot.tick)?;
+        self.tick += 1;
+        slot.tick = self.tick;
+        self.order.insert(self.tick, owned);
+        Some(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn evict_least_recently_used() {
+        let mut map = LruMap::new(30);
+        map.insert("a", 1, 10);
+        map.insert("b", 2, 10);
+        map.insert("c", 3, 10);
+        map.get(&"a");
+
+        assert_eq!(map.insert("d", 4, 15), 2);
+        assert_eq!(map.iter().map(|(k, _)| *k).collect::<Vec<_>>(), ["a", "d"]);
+        assert_eq!(map.bytes(), 25);
+        assert_eq!(map.insert("e", 5, 31), 0);
+        assert!(map.peek(&"e").is_none());
+    }
+}
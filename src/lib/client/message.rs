@@ -1,6 +1,7 @@
 use crate::client::header::{Flag, Header};
 use crate::client::question::Question;
-use crate::client::rr::ResourceRecord;
+use crate::client::rr::{self, ResourceRecord};
+use rand::Rng;
 use std::error::Error;
 
 /// DNS message
@@ -18,8 +19,13 @@ pub struct DnsMessage {
 }
 
 impl DnsMessage {
-    /// Create a new DNS message
+    /// Create a new DNS message asking for the host address of `address`
     pub fn new(address: &str) -> DnsMessage {
+        DnsMessage::new_query(address, rr::A)
+    }
+
+    /// Create a new DNS message asking for records of type `q_type`
+    pub fn new_query(address: &str, q_type: u16) -> DnsMessage {
         let dns_flags = Flag {
             qr: 0,
             op_code: 0,
@@ -42,8 +48,8 @@ impl DnsMessage {
 
         let dns_question = Question {
             q_name: DnsMessage::encode_address(address),
-            q_type: 1,
-            q_class: 1,
+            q_type,
+            q_class: rr::IN,
         };
 
         let dns_msg = DnsMessage {
@@ -96,33 +102,26 @@ impl DnsMessage {
     }
 
     /// Parse a vector of bytes into a DNS message
-    pub fn parse(message: &Vec<u8>) -> Result<DnsMessage, Box<dyn Error>> {
-        let mut start = 0;
-        let parsed_value= Header::parse(&message, start)?;
-        start = parsed_value.0;
-        let header = parsed_value.1;
-
-        let parsed_value = Question::parse(&message, start)?;
-        start = parsed_value.0;
-        let question = parsed_value.1;
+    pub fn parse(message: &[u8]) -> Result<DnsMessage, Box<dyn Error>> {
+        let (mut start, header, question) = DnsMessage::parse_question(message)?;
 
         let mut answers = vec![];
         for _ in 0..header.an_cnt {
-            let answer = ResourceRecord::parse(&message, start)?;
+            let answer = ResourceRecord::parse(message, start)?;
             answers.push(answer.1);
             start = answer.0;
         }
 
         let mut authorities = vec![];
         for _ in 0..header.ns_cnt {
-            let authority = ResourceRecord::parse(&message, start)?;
+            let authority = ResourceRecord::parse(message, start)?;
             authorities.push(authority.1);
             start = authority.0;
         }
 
         let mut additionals = vec![];
         for _ in 0..header.ar_cnt {
-            let additional = ResourceRecord::parse(&message, start)?;
+            let additional = ResourceRecord::parse(message, start)?;
             additionals.push(additional.1);
             start = additional.0;
         }
@@ -147,27 +146,135 @@ impl DnsMessage {
             .collect::<Vec<_>>();
         for seg in segs {
             encoded_addr.push(seg.len() as u8);
-            for j in 0..seg.len() {
-                encoded_addr.push(seg.chars().nth(j).unwrap() as u8);
-            }
+            encoded_addr.extend_from_slice(seg.as_bytes());
         }
         encoded_addr.push(0);
 
         encoded_addr
     }
 
+    /// Parse only the header and question of a message, leaving its
+    /// sections empty. This is enough to match a response to its query when
+    /// the rest of it was cut short, as in truncated responses
+    pub fn parse_head(message: &[u8]) -> Result<DnsMessage, Box<dyn Error>> {
+        let (_, mut header, question) = DnsMessage::parse_question(message)?;
+        header.an_cnt = 0;
+        header.ns_cnt = 0;
+        header.ar_cnt = 0;
+
+        Ok(DnsMessage {
+            header,
+            question,
+            answers: vec![],
+            authorities: vec![],
+            additionals: vec![],
+        })
+    }
+
+    /// Parse the header and the single question of a message, giving the
+    /// position of the answer section
+    fn parse_question(message: &[u8]) -> Result<(usize, Header, Question), Box<dyn Error>> {
+        let (start, header) = Header::parse(message, 0)?;
+        if header.qd_cnt != 1 {
+            return Err(format!("Expected one question, got {}", header.qd_cnt).into());
+        }
+        let (start, question) = Question::parse(message, start)?;
+
+        Ok((start, header, question))
+    }
+
     /// Decode an address in DNS message
-    pub fn decode_address(bytes: &Vec<u8>) -> String {
+    pub fn decode_address(bytes: &[u8]) -> String {
         let mut segments = vec![];
         let mut i = 0;
-        while bytes[i] != 0 {
+        while i < bytes.len() && bytes[i] != 0 {
             let f_seg_len = bytes[i] as usize;
-            if f_seg_len != 0 {
-                let seg = String::from_utf8(bytes[i + 1..i + 1 + f_seg_len].to_vec()).unwrap();
-                segments.push(seg);
-            }
+            let end = usize::min(i + 1 + f_seg_len, bytes.len());
+            segments.push(String::from_utf8_lossy(&bytes[i + 1..end]).to_string());
             i += f_seg_len + 1;
         }
         segments.join(".")
     }
+
+    /// Whether `name` is equal to or below `zone`, ignoring case. The root
+    /// zone is written as the empty string
+    pub fn is_subdomain(name: &str, zone: &str) -> bool {
+        let name = name.trim_end_matches('.').to_ascii_lowercase();
+        let zone = zone.trim_end_matches('.').to_ascii_lowercase();
+        zone.is_empty() || name == zone || name.ends_with(&format!(".{}", zone))
+    }
+
+    /// Add an EDNS(0) OPT record advertising the UDP payload size this
+    /// client can receive
+    pub fn add_edns(&mut self, payload_size: u16) {
+        self.additionals.push(ResourceRecord {
+            rr_name: vec![0],
+            rr_type: rr::OPT,
+            rr_class: payload_size,
+            rr_ttl: 0,
+            rr_rdlength: 0,
+            rr_rdata: vec![],
+        });
+        self.header.ar_cnt = self.additionals.len() as u16;
+    }
+
+    /// Randomise the letter case of the question name (DNS 0x20), making a
+    /// response harder to forge without seeing the query
+    pub fn randomize_case(&mut self) {
+        let mut rng = rand::thread_rng();
+        let q_name = &mut self.question.q_name;
+        let mut i = 0;
+        while i < q_name.len() && q_name[i] != 0 {
+            let len = q_name[i] as usize;
+            for byte in q_name.iter_mut().skip(i + 1).take(len) {
+                if byte.is_ascii_alphabetic() && rng.gen::<bool>() {
+                    *byte ^= 0x20;
+                }
+            }
+            i += len + 1;
+        }
+    }
+
+    /// Check that this message is an acceptable response to `query`: same
+    /// transaction ID, QR set and an identical question. Names are compared
+    /// ignoring case unless `exact_case` is set
+    pub fn validate_response(
+        &self,
+        query: &DnsMessage,
+        exact_case: bool,
+    ) -> Result<(), Box<dyn Error>> {
+        if self.header.flags.qr != 1 {
+            return Err("Message is not a response".into());
+        }
+        if self.header.id != query.header.id {
+            return Err(format!(
+                "Transaction ID {} does not match query ID {}",
+                self.header.id, query.header.id
+            )
+            .into());
+        }
+        if self.header.flags.op_code != query.header.flags.op_code {
+            return Err("Response has a different op code".into());
+        }
+        let same_name = if exact_case {
+            self.question.q_name == query.question.q_name
+        } else {
+            self.question
+                .q_name
+                .eq_ignore_ascii_case(&query.question.q_name)
+        };
+        if !same_name
+            || self.question.q_type != query.question.q_type
+            || self.question.q_class != query.question.q_class
+        {
+            return Err(format!(
+                "Question for {} does not match query for {}",
+                DnsMessage::decode_address(&self.question.q_name),
+                DnsMessage::decode_address(&query.question.q_name)
+            )
+            .into());
+        }
+
+        Ok(())
+    }
 }
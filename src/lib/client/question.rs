@@ -27,11 +27,11 @@ impl Question {
     }
 
     /// Parse a vector of bytes to DNS question
-    pub fn parse(message: &Vec<u8>, start: usize) -> Result<(usize, Question), Box<dyn Error>> {
-        let null_pos = utility::find_first_null(&message[start..])?;
-        let q_name = message[start..start + null_pos + 1].to_vec();
-        let q_type = utility::to_u16(&message[start + null_pos + 1..start + null_pos + 3]);
-        let q_class = utility::to_u16(&message[start + null_pos + 3..start + null_pos + 5]);
+    pub fn parse(message: &[u8], start: usize) -> Result<(usize, Question), Box<dyn Error>> {
+        let (offset, q_name) = utility::read_name(message, start)?;
+        let fields = utility::get_slice(message, offset, offset + 4)?;
+        let q_type = utility::to_u16(&fields[0..2]);
+        let q_class = utility::to_u16(&fields[2..4]);
 
         let q = Question {
             q_name,
@@ -39,6 +39,6 @@ impl Question {
             q_class,
         };
 
-        Ok((start + null_pos + 5, q))
+        Ok((offset + 4, q))
     }
 }
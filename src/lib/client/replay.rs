@@ -0,0 +1,345 @@
+use byteorder::{BigEndian, ReadBytesExt};
+use spdlog::prelude::*;
+use std::collections::VecDeque;
+use std::error::Error;
+use std::io::{self, Cursor, Read};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::client::transport::{Connection, Transport};
+use crate::client::utility;
+
+/// First bytes of a recording file, followed by the format version
+const FILE_MAGIC: &[u8; 4] = b"DNSR";
+const FILE_VERSION: u8 = 1;
+
+/// Protocol a query was sent over
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    Udp,
+    Tcp,
+}
+
+/// A query sent to a name server and the responses it got back
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Exchange {
+    pub server_addr: SocketAddr,
+    pub protocol: Protocol,
+    /// Query in wire format
+    pub query: Vec<u8>,
+    /// Responses in wire format, each with the time it took to arrive after
+    /// the query was sent. Empty when the query timed out
+    pub responses: Vec<(Duration, Vec<u8>)>,
+}
+
+/// Queries and responses exchanged with name servers, in the order the
+/// queries were sent. Clones share the exchanges
+#[derive(Debug, Clone, Default)]
+pub struct Recording {
+    exchanges: Arc<Mutex<Vec<Exchange>>>,
+}
+
+impl Recording {
+    /// Create an empty recording
+    pub fn new() -> Recording {
+        Recording::default()
+    }
+
+    /// Exchanges recorded so far
+    pub fn exchanges(&self) -> Vec<Exchange> {
+        self.exchanges.lock().unwrap().clone()
+    }
+
+    /// Wrap `inner`, recording what is exchanged over it as sent over
+    /// `protocol`
+    pub fn record<T: Transport>(&self, inner: T, protocol: Protocol) -> RecordingTransport<T> {
+        RecordingTransport {
+            inner,
+            protocol,
+            exchanges: self.exchanges.clone(),
+        }
+    }
+
+    /// Transport serving back the responses recorded over `protocol`
+    pub fn replay(&self, protocol: Protocol) -> ReplayTransport {
+        let exchanges = self
+            .exchanges()
+            .into_iter()
+            .filter(|exchange| exchange.protocol == protocol)
+            .collect();
+        ReplayTransport {
+            remaining: Arc::new(Mutex::new(exchanges)),
+        }
+    }
+
+    /// Write the exchanges to a fixture file, giving the number written
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<usize> {
+        let mut bytes = vec![];
+        let exchanges = self.exchanges();
+        for exchange in &exchanges {
+            match exchange.server_addr.ip() {
+                IpAddr::V4(ip) => {
+                    bytes.push(4);
+                    bytes.extend(ip.octets());
+                }
+                IpAddr::V6(ip) => {
+                    bytes.push(6);
+                    bytes.extend(ip.octets());
+                }
+            }
+            bytes.extend(exchange.server_addr.port().to_be_bytes());
+            bytes.push(match exchange.protocol {
+                Protocol::Udp => 0,
+                Protocol::Tcp => 1,
+            });
+            bytes.extend((exchange.query.len() as u16).to_be_bytes());
+            bytes.extend(&exchange.query);
+            bytes.extend((exchange.responses.len() as u16).to_be_bytes());
+            for (elapsed, response) in &exchange.responses {
+                bytes.extend((elapsed.as_nanos() as u64).to_be_bytes());
+                bytes.extend((response.len() as u16).to_be_bytes());
+                bytes.extend(response);
+            }
+        }
+        utility::write_versioned(path.as_ref(), FILE_MAGIC, FILE_VERSION, &bytes)?;
+
+        Ok(exchanges.len())
+    }
+
+    /// Read exchanges written by `save`
+    pub fn load(path: impl AsRef<Path>) -> Result<Recording, Box<dyn Error>> {
+        let bytes = utility::read_versioned(path.as_ref(), FILE_MAGIC, FILE_VERSION)?;
+        let mut reader = Cursor::new(bytes.as_slice());
+
+        let mut exchanges = vec![];
+        while (reader.position() as usize) < bytes.len() {
+            let ip = match reader.read_u8()? {
+                4 => {
+                    let mut octets = [0; 4];
+                    reader.read_exact(&mut octets)?;
+                    IpAddr::V4(Ipv4Addr::from(octets))
+                }
+                6 => {
+                    let mut octets = [0; 16];
+                    reader.read_exact(&mut octets)?;
+                    IpAddr::V6(Ipv6Addr::from(octets))
+                }
+                family => return Err(format!("Unknown address family {}", family).into()),
+            };
+            let server_addr = SocketAddr::new(ip, reader.read_u16::<BigEndian>()?);
+            let protocol = match reader.read_u8()? {
+                0 => Protocol::Udp,
+                1 => Protocol::Tcp,
+                protocol => return Err(format!("Unknown protocol {}", protocol).into()),
+            };
+            let query = read_message(&mut reader)?;
+            let mut responses = vec![];
+            for _ in 0..reader.read_u16::<BigEndian>()? {
+                let elapsed = Duration::from_nanos(reader.read_u64::<BigEndian>()?);
+                responses.push((elapsed, read_message(&mut reader)?));
+            }
+            exchanges.push(Exchange {
+                server_addr,
+                protocol,
+                query,
+                responses,
+            });
+        }
+
+        Ok(Recording {
+            exchanges: Arc::new(Mutex::new(exchanges)),
+        })
+    }
+}
+
+/// Read a message prefixed with its length
+fn read_message(reader: &mut Cursor<&[u8]>) -> io::Result<Vec<u8>> {
+    let mut message = vec![0; reader.read_u16::<BigEndian>()? as usize];
+    reader.read_exact(&mut message)?;
+    Ok(message)
+}
+
+/// Question section of a message in wire format, without decompressing
+/// its name
+fn question(message: &[u8]) -> Option<&[u8]> {
+    let mut pos = 12;
+    loop {
+        let len = *message.get(pos)? as usize;
+        pos += 1;
+        if len == 0 {
+            break;
+        }
+        if len & 0xC0 != 0 {
+            return None;
+        }
+        pos += len;
+    }
+
+    message.get(12..pos + 4)
+}
+
+/// Wraps a transport to record the queries sent over it and the responses
+/// received into a `Recording`
+#[derive(Debug, Clone)]
+pub struct RecordingTransport<T> {
+    inner: T,
+    protocol: Protocol,
+    exchanges: Arc<Mutex<Vec<Exchange>>>,
+}
+
+impl<T: Transport> Transport for RecordingTransport<T> {
+    type Connection = RecordingConnection<T::Connection>;
+
+    fn connect(&self, server_addr: SocketAddr, deadline: Instant) -> io::Result<Self::Connection> {
+        Ok(RecordingConnection {
+            inner: self.inner.connect(server_addr, deadline)?,
+            server_addr,
+            protocol: self.protocol,
+            exchanges: self.exchanges.clone(),
+            current: None,
+        })
+    }
+}
+
+/// Connection of a `RecordingTransport`
+pub struct RecordingConnection<C> {
+    inner: C,
+    server_addr: SocketAddr,
+    protocol: Protocol,
+    exchanges: Arc<Mutex<Vec<Exchange>>>,
+    /// Position of the exchange of the last query sent and when it was sent
+    current: Option<(usize, Instant)>,
+}
+
+impl<C: Connection> Connection for RecordingConnection<C> {
+    fn send(&mut self, msg: &[u8]) -> io::Result<()> {
+        self.inner.send(msg)?;
+        let mut exchanges = self.exchanges.lock().unwrap();
+        exchanges.push(Exchange {
+            server_addr: self.server_addr,
+            protocol: self.protocol,
+            query: msg.to_vec(),
+            responses: vec![],
+        });
+        self.current = Some((exchanges.len() - 1, Instant::now()));
+
+        Ok(())
+    }
+
+    fn receive(&mut self, deadline: Instant) -> io::Result<Vec<u8>> {
+        let response = self.inner.receive(deadline)?;
+        if let Some((index, sent)) = self.current {
+            self.exchanges.lock().unwrap()[index]
+                .responses
+                .push((sent.elapsed(), response.clone()));
+        }
+
+        Ok(response)
+    }
+}
+
+/// Serves recorded responses back without touching the network. A query is
+/// answered by the first unused exchange for the same question, preferring
+/// the same server, with the transaction ID and the letter case of the
+/// question rewritten to match it. Responses arrive as long after the
+/// query as they did when recorded, so timeouts and retries play out the
+/// same way. Queries nothing was recorded for time out at once
+#[derive(Debug, Clone)]
+pub struct ReplayTransport {
+    remaining: Arc<Mutex<Vec<Exchange>>>,
+}
+
+impl ReplayTransport {
+    /// Number of recorded exchanges not replayed yet
+    pub fn remaining(&self) -> usize {
+        self.remaining.lock().unwrap().len()
+    }
+
+    /// Take the exchange answering `query` sent to `server_addr`
+    fn take(&self, server_addr: SocketAddr, query: &[u8]) -> Option<Exchange> {
+        let asked = question(query)?;
+        let mut remaining = self.remaining.lock().unwrap();
+        let same_question = |exchange: &Exchange| {
+            question(&exchange.query).is_some_and(|recorded| recorded.eq_ignore_ascii_case(asked))
+        };
+        let index = remaining
+            .iter()
+            .position(|exchange| exchange.server_addr == server_addr && same_question(exchange))
+            .or_else(|| remaining.iter().position(same_question))?;
+
+        Some(remaining.remove(index))
+    }
+}
+
+impl Transport for ReplayTransport {
+    type Connection = ReplayConnection;
+
+    fn connect(&self, server_addr: SocketAddr, _: Instant) -> io::Result<ReplayConnection> {
+        Ok(ReplayConnection {
+            transport: self.clone(),
+            server_addr,
+            sent: Instant::now(),
+            responses: VecDeque::new(),
+        })
+    }
+}
+
+/// Connection of a `ReplayTransport`
+pub struct ReplayConnection {
+    transport: ReplayTransport,
+    server_addr: SocketAddr,
+    /// When the last query was sent
+    sent: Instant,
+    /// Responses to the last query, each with when it arrived after the
+    /// query was sent
+    responses: VecDeque<(Duration, Vec<u8>)>,
+}
+
+impl Connection for ReplayConnection {
+    fn send(&mut self, msg: &[u8]) -> io::Result<()> {
+        self.sent = Instant::now();
+        let exchange = match self.transport.take(self.server_addr, msg) {
+            Some(exchange) => exchange,
+            None => {
+                debug!("Nothing recorded for query to {}", self.server_addr);
+                return Ok(());
+            }
+        };
+        for (elapsed, mut response) in exchange.responses {
+            if response.len() >= 2 && msg.len() >= 2 {
+                response[..2].copy_from_slice(&msg[..2]);
+            }
+            if let Some(asked) = question(msg) {
+                if question(&response).is_some_and(|answered| answered.eq_ignore_ascii_case(asked))
+                {
+                    response[12..12 + asked.len()].copy_from_slice(asked);
+                }
+            }
+            self.responses.push_back((elapsed, response));
+        }
+
+        Ok(())
+    }
+
+    fn receive(&mut self, deadline: Instant) -> io::Result<Vec<u8>> {
+        let arrival = match self.responses.front() {
+            Some((elapsed, _)) => self.sent + *elapsed,
+            None => return Err(io::ErrorKind::TimedOut.into()),
+        };
+        thread::sleep(
+            arrival
+                .min(deadline)
+                .saturating_duration_since(Instant::now()),
+        );
+        // A response recorded past this deadline stays queued for a later
+        // receive with a longer one
+        if arrival > deadline {
+            return Err(io::ErrorKind::TimedOut.into());
+        }
+
+        Ok(self.responses.pop_front().unwrap().1)
+    }
+}
@@ -0,0 +1,159 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::client::config::{ResolverConfig, ResolverMode};
+
+/// Location of the system resolver configuration
+pub const RESOLV_CONF_PATH: &str = "/etc/resolv.conf";
+
+/// Maximum number of name servers used, as in the system resolver
+const MAX_NAME_SERVERS: usize = 3;
+/// Maximum number of search domains used
+const MAX_SEARCH_DOMAINS: usize = 6;
+/// Upper bounds of the options, as in the system resolver
+const MAX_NDOTS: u32 = 15;
+const MAX_TIMEOUT: u64 = 30;
+const MAX_ATTEMPTS: u32 = 5;
+
+/// Resolver settings read from a resolv.conf file
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResolvConf {
+    /// Addresses of the recursive name servers, in order of preference
+    pub name_servers: Vec<String>,
+    /// Domains appended to names with fewer than `ndots` dots
+    pub search: Vec<String>,
+    /// Number of dots a name needs to be tried as is before the search list
+    pub ndots: u32,
+    /// Time to wait for a response from a name server
+    pub timeout: Duration,
+    /// Number of times each name server is tried
+    pub attempts: u32,
+    /// Spread queries over the name servers instead of always asking the
+    /// first one
+    pub rotate: bool,
+    /// Advertise a larger UDP payload size with EDNS(0)
+    pub edns0: bool,
+    /// Send queries over TCP
+    pub use_vc: bool,
+}
+
+impl ResolvConf {
+    /// Parse the content of a resolv.conf file. Unknown keywords and
+    /// options are ignored like the system resolver does
+    pub fn parse(content: &str) -> ResolvConf {
+        let mut resolv_conf = ResolvConf::default();
+        let mut name_servers = vec![];
+        for line in content.lines() {
+            let line = line.trim();
+            if line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+            let mut words = line.split_whitespace();
+            match words.next() {
+                Some("nameserver") => {
+                    if let Some(address) = words.next() {
+                        if name_servers.len() < MAX_NAME_SERVERS {
+                            name_servers.push(ResolvConf::strip_comment(address).to_string());
+                        }
+                    }
+                }
+                // The last of domain and search wins
+                Some("domain") => {
+                    resolv_conf.search = words
+                        .next()
+                        .map(|domain| vec![domain.trim_end_matches('.').to_string()])
+                        .unwrap_or_default();
+                }
+                Some("search") => {
+                    resolv_conf.search = words
+                        .take_while(|word| !word.starts_with('#') && !word.starts_with(';'))
+                        .map(|domain| domain.trim_end_matches('.').to_string())
+                        .filter(|domain| !domain.is_empty())
+                        .take(MAX_SEARCH_DOMAINS)
+                        .collect();
+                }
+                Some("options") => {
+                    for option in words {
+                        resolv_conf.set_option(option);
+                    }
+                }
+                _ => {}
+            }
+        }
+        if !name_servers.is_empty() {
+            resolv_conf.name_servers = name_servers;
+        }
+
+        resolv_conf
+    }
+
+    /// Read and parse a resolv.conf file
+    pub fn read(path: impl AsRef<Path>) -> io::Result<ResolvConf> {
+        let content = fs::read_to_string(path)?;
+        Ok(ResolvConf::parse(&content))
+    }
+
+    /// Read the system resolver configuration
+    pub fn read_system() -> io::Result<ResolvConf> {
+        ResolvConf::read(RESOLV_CONF_PATH)
+    }
+
+    /// Build a stub resolver configuration asking the name servers
+    pub fn to_config(&self) -> ResolverConfig {
+        let defaults = ResolverConfig::default();
+        ResolverConfig {
+            mode: ResolverMode::Stub(self.name_servers.clone()),
+            query_timeout: self.timeout,
+            max_query_timeout: defaults.max_query_timeout.max(self.timeout),
+            retries: self.attempts.saturating_sub(1),
+            force_tcp: self.use_vc,
+            rotate: self.rotate,
+            edns0: self.edns0,
+            search: self.search.clone(),
+            ndots: self.ndots as usize,
+            ..defaults
+        }
+    }
+
+    /// Apply a single word of an options line
+    fn set_option(&mut self, option: &str) {
+        let (name, value) = match option.split_once(':') {
+            Some((name, value)) => (name, Some(value)),
+            None => (option, None),
+        };
+        let value = value.and_then(|value| value.parse::<u32>().ok());
+        match (name, value) {
+            ("ndots", Some(ndots)) => self.ndots = ndots.min(MAX_NDOTS),
+            ("timeout", Some(timeout)) => {
+                self.timeout = Duration::from_secs((timeout as u64).clamp(1, MAX_TIMEOUT))
+            }
+            ("attempts", Some(attempts)) => self.attempts = attempts.clamp(1, MAX_ATTEMPTS),
+            ("rotate", None) => self.rotate = true,
+            ("edns0", None) => self.edns0 = true,
+            ("use-vc", None) => self.use_vc = true,
+            _ => {}
+        }
+    }
+
+    /// Remove a comment directly following a value
+    fn strip_comment(word: &str) -> &str {
+        word.split(['#', ';']).next().unwrap_or(word)
+    }
+}
+
+impl Default for ResolvConf {
+    fn default() -> Self {
+        ResolvConf {
+            name_servers: vec![String::from("127.0.0.1")],
+            search: vec![],
+            ndots: 1,
+            timeout: Duration::from_secs(5),
+            attempts: 2,
+            rotate: false,
+            edns0: false,
+            use_vc: false,
+        }
+    }
+}
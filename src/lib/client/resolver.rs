@@ -0,0 +1,1523 @@
+use spdlog::prelude::*;
+use std::{
+    collections::{HashMap, VecDeque},
+    error::Error,
+    io,
+    net::{IpAddr, SocketAddr},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::client::cache::{Cache, CacheKey, NegativeAnswer, NegativeKind};
+use crate::client::config::{ResolverConfig, ResolverMode, EDNS_PAYLOAD_SIZE};
+use crate::client::error::ResolveError;
+use crate::client::hosts::HostsFile;
+use crate::client::infra::InfraCache;
+use crate::client::lookup::LookupResult;
+use crate::client::message::DnsMessage;
+use crate::client::root_hints::RootHints;
+use crate::client::rr::{self, ResourceRecord, Soa};
+use crate::client::trace::{Choice, Outcome, TraceHook, TraceStep};
+use crate::client::{
+    MAX_CNAMES, MAX_DEPTH, MAX_STEPS, NAME_ERROR, NO_ERROR, REFUSED, SERVER_FAILURE,
+};
+
+/// TTL of addresses from the hosts file: like other local answers they
+/// aren't cached, so they are looked up again on every use
+const HOSTS_TTL: u32 = 0;
+/// Priming may take at most this share of the time left for the lookup it
+/// runs before, leaving the rest to the lookup
+const PRIME_SHARE: u32 = 3;
+/// Longest wait before priming again after it failed
+const MAX_PRIME_RETRY: Duration = Duration::from_secs(3600);
+
+/// The part of a resolver that doesn't depend on how messages travel:
+/// settings, hosts file, root servers and caches, and the handling of the
+/// responses of name servers. `DnsClient` and `AsyncResolver` drive the
+/// resolutions built here, sending the queries they ask for
+pub(crate) struct Resolver {
+    pub(crate) config: ResolverConfig,
+    pub(crate) hosts: Option<HostsFile>,
+    root_servers: Arc<RwLock<Vec<String>>>,
+    /// When the root servers may be primed next with the number of failed
+    /// attempts so far, or nothing once priming succeeded
+    priming: Mutex<Option<(Instant, u32)>>,
+    next_server: AtomicUsize,
+    pub(crate) infra: Arc<InfraCache>,
+    pub(crate) cache: Arc<Cache>,
+    pub(crate) trace: Option<Arc<TraceHook>>,
+}
+
+impl Resolver {
+    /// Create a resolver with the given settings, loading the cache file
+    /// of the configuration if any
+    pub(crate) fn new(config: ResolverConfig) -> Resolver {
+        let resolver = Resolver {
+            hosts: config.hosts_path.as_ref().map(HostsFile::new),
+            root_servers: Arc::new(RwLock::new(config.root_hints.addresses())),
+            priming: Mutex::new(Some((Instant::now(), 0))),
+            next_server: AtomicUsize::new(0),
+            infra: Arc::new(InfraCache::new(
+                config.rtt_exploration,
+                config.penalty_half_life,
+                config.infra_cache_size,
+            )),
+            cache: Arc::new(Cache::new(
+                config.cache_min_ttl,
+                config.cache_max_ttl,
+                config.cache_negative_max_ttl,
+                if config.serve_stale {
+                    config.max_stale
+                } else {
+                    Duration::ZERO
+                },
+                config.cache_size,
+            )),
+            config,
+            trace: None,
+        };
+        if let Some(path) = &resolver.config.cache_path {
+            match resolver.cache.load(path) {
+                Ok(count) => debug!("Loaded {} RRsets from {}", count, path.display()),
+                Err(err) => info!("Can't load cache from {}: {}", path.display(), err),
+            }
+        }
+
+        resolver
+    }
+
+    /// Resolver sharing the caches and root servers of this one, to resolve
+    /// names in the background
+    pub(crate) fn background(&self) -> Resolver {
+        Resolver {
+            config: ResolverConfig {
+                hosts_path: None,
+                prime_roots: false,
+                cache_path: None,
+                ..self.config.clone()
+            },
+            hosts: None,
+            root_servers: self.root_servers.clone(),
+            priming: Mutex::new(None),
+            next_server: AtomicUsize::new(self.next_server.load(Ordering::Relaxed)),
+            infra: self.infra.clone(),
+            cache: self.cache.clone(),
+            trace: None,
+        }
+    }
+
+    /// Save the cached RRsets to the cache file of the configuration, if
+    /// any, giving the number of RRsets saved
+    pub(crate) fn save_cache(&self) -> io::Result<usize> {
+        match &self.config.cache_path {
+            Some(path) => self.cache.save(path),
+            None => Ok(0),
+        }
+    }
+
+    /// Get all root servers address
+    pub(crate) fn root_servers(&self) -> Vec<String> {
+        self.root_servers.read().unwrap().clone()
+    }
+
+    /// Whether the root servers are due for priming before a lookup. The
+    /// attempt is claimed so concurrent lookups don't prime too: they use
+    /// the root servers known so far
+    pub(crate) fn claim_priming(&self) -> bool {
+        if !self.config.prime_roots {
+            return false;
+        }
+
+        let mut priming = self.priming.lock().unwrap();
+        match *priming {
+            Some((due, failures)) if due <= Instant::now() => {
+                let retry = self
+                    .config
+                    .prime_retry
+                    .checked_mul(1u32.checked_shl(failures).unwrap_or(u32::MAX))
+                    .unwrap_or(MAX_PRIME_RETRY)
+                    .min(MAX_PRIME_RETRY);
+                *priming = Some((Instant::now() + retry, failures + 1));
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Deadline of priming before a lookup that has until `deadline`
+    pub(crate) fn priming_deadline(deadline: Instant) -> Instant {
+        let now = Instant::now();
+        now + deadline.saturating_duration_since(now) / PRIME_SHARE
+    }
+
+    /// Use the root servers given by a priming query from now on, or keep
+    /// the ones known so far until priming is tried again when it failed
+    pub(crate) fn finish_priming(&self, result: &Result<RootHints, ResolveError>) {
+        match result {
+            Ok(root_hints) => {
+                info!("Primed {} root servers", root_hints.servers.len());
+                *self.root_servers.write().unwrap() = root_hints.addresses();
+                *self.priming.lock().unwrap() = None;
+            }
+            Err(err) => info!("Priming failed, using root hints: {}", err),
+        }
+    }
+
+    /// Addresses of a host name in the hosts file
+    pub(crate) fn hosts_lookup(&self, host_name: &str) -> Option<LookupResult> {
+        let hosts = self.hosts.as_ref()?;
+        let ip_addrs = hosts.lookup(host_name)?;
+        debug!("Found {} in {}", host_name, hosts.path().display());
+
+        Some(LookupResult {
+            name: host_name.trim_end_matches('.').to_string(),
+            ip_addrs: ip_addrs.iter().map(|ip| ip.to_string()).collect(),
+            stale: false,
+            ttl: HOSTS_TTL,
+        })
+    }
+
+    /// Host names of an address in the hosts file
+    pub(crate) fn hosts_reverse(&self, ip: IpAddr) -> Option<Vec<String>> {
+        let hosts = self.hosts.as_ref()?;
+        let host_names = hosts.reverse(ip)?;
+        debug!("Found {} in {}", ip, hosts.path().display());
+
+        Some(host_names)
+    }
+
+    /// Error for a search that had no candidate to try
+    pub(crate) fn no_candidate(host_name: &str) -> ResolveError {
+        ResolveError::NxDomain {
+            name: host_name.to_string(),
+            server: String::new(),
+            step: 0,
+            soa: None,
+        }
+    }
+
+    /// Follow cached aliases of `name` and get its cached RRset of
+    /// `rr_type`, or the cached negative answer for it. The last name
+    /// reached is given for resolving when nothing is cached for it. The
+    /// lookup counts once in the cache statistics, however many aliases it
+    /// follows
+    pub(crate) fn cached_records(
+        &self,
+        name: &str,
+        rr_type: u16,
+    ) -> Result<(String, Option<Vec<ResourceRecord>>), ResolveError> {
+        let result = self.peek_records(name, rr_type);
+        match &result {
+            Ok((name, Some(_))) => {
+                self.cache
+                    .count_hit(Some(&CacheKey::new(name, rr_type, rr::IN)))
+            }
+            Ok((_, None)) => self.cache.count_miss(),
+            Err(_) => self.cache.count_hit(None),
+        }
+
+        result
+    }
+
+    /// Look up `name` like `cached_records` without counting the lookup,
+    /// for lookups the resolver makes for itself such as the addresses of
+    /// name servers, which must not make them look popular for prefetching
+    pub(crate) fn peek_records(
+        &self,
+        name: &str,
+        rr_type: u16,
+    ) -> Result<(String, Option<Vec<ResourceRecord>>), ResolveError> {
+        let mut name = name.to_string();
+        for _ in 0..=MAX_CNAMES {
+            if let Some(records) = self.cache.peek(&name, rr_type, rr::IN) {
+                debug!("Found {} in cache", name);
+                return Ok((name, Some(records)));
+            }
+            if let Some(answer) = self.cache.peek_negative(&name, rr_type, rr::IN) {
+                debug!(
+                    "Found negative answer for {} at {} in cache",
+                    name, answer.owner
+                );
+                return Err(Resolver::negative_error(&name, answer));
+            }
+            match self.cache.peek(&name, rr::CNAME, rr::IN) {
+                Some(records) if !records.is_empty() => name = records[0].get_domain_name(),
+                _ => break,
+            }
+        }
+
+        Ok((name, None))
+    }
+
+    /// Expired cached records of `rr_type` of `name` to answer with when
+    /// resolving it failed because no name server gave a usable answer:
+    /// they timed out, failed, refused, weren't authoritative or sent
+    /// garbage (RFC 8767). Answers saying the name or type doesn't exist
+    /// replace the records instead, and neither a resolution going in
+    /// circles nor a failed validation is fixed by serving old data
+    pub(crate) fn stale_records(
+        &self,
+        name: &str,
+        rr_type: u16,
+        err: &ResolveError,
+    ) -> Option<Vec<ResourceRecord>> {
+        if !self.config.serve_stale
+            || !matches!(
+                err,
+                ResolveError::Timeout { .. }
+                    | ResolveError::ServFail { .. }
+                    | ResolveError::Refused { .. }
+                    | ResolveError::LameDelegation { .. }
+                    | ResolveError::Malformed { .. }
+            )
+        {
+            return None;
+        }
+
+        let mut name = name.to_string();
+        for _ in 0..=MAX_CNAMES {
+            if let Some(records) = self.cache.get_stale(&name, rr_type, rr::IN) {
+                return Some(records);
+            }
+            name = self
+                .cache
+                .get_stale(&name, rr::CNAME, rr::IN)?
+                .first()?
+                .get_domain_name();
+        }
+
+        None
+    }
+
+    /// Whether the cached records of `rr_type` of `name` are popular and
+    /// about to expire, and should be refreshed in the background. Only
+    /// the first caller is told so
+    pub(crate) fn prefetch_due(&self, name: &str, rr_type: u16) -> bool {
+        self.config.prefetch
+            && self.cache.claim_prefetch(
+                &CacheKey::new(name, rr_type, rr::IN),
+                self.config.prefetch_ttl_fraction,
+                self.config.prefetch_min_hits,
+            )
+    }
+
+    /// Let the records of `rr_type` of `name` be prefetched again after
+    /// their refresh failed
+    pub(crate) fn prefetch_failed(&self, name: &str, rr_type: u16) {
+        self.cache
+            .release_prefetch(&CacheKey::new(name, rr_type, rr::IN));
+    }
+
+    /// Message asking the question of a query
+    pub(crate) fn question(&self, query: &Query) -> DnsMessage {
+        let mut dns_question = DnsMessage::new_query(&query.name, query.q_type);
+        if let ResolverMode::Stub(_) = self.config.mode {
+            dns_question.header.flags.rd = 1;
+        }
+        if self.config.use_0x20 {
+            dns_question.randomize_case();
+        }
+        if self.config.edns0 {
+            dns_question.add_edns(EDNS_PAYLOAD_SIZE);
+        }
+
+        dns_question
+    }
+
+    /// How long to wait for the response to a query from now: the timeout
+    /// of its attempt, cut short by its deadline
+    pub(crate) fn wait(&self, query: &Query) -> Duration {
+        self.config
+            .timeout_for_attempt(query.attempt)
+            .min(query.deadline.saturating_duration_since(Instant::now()))
+    }
+
+    /// Socket address of the server of a query
+    pub(crate) fn server_addr(&self, query: &Query) -> Result<SocketAddr, ResolveError> {
+        Resolver::parse_server_addr(&query.server, self.config.port).map_err(|err| {
+            debug!("Invalid server address {}: {}", query.server, err);
+            query.timeout()
+        })
+    }
+
+    /// Parse a datagram received for a question, giving it when it is the
+    /// response to the question. Other datagrams are discarded, keeping
+    /// why the last unparsable one couldn't be parsed in `malformed`. The
+    /// sections of a truncated response are left out unparsed, as they may
+    /// be cut short
+    pub(crate) fn match_datagram(
+        &self,
+        bytes: &[u8],
+        dns_question: &DnsMessage,
+        server_addr: SocketAddr,
+        malformed: &mut Option<String>,
+    ) -> Option<DnsMessage> {
+        let discard_unparsable = |malformed: &mut Option<String>, err: Box<dyn Error>| {
+            debug!("Discard unparsable response from {}: {}", server_addr, err);
+            *malformed = Some(err.to_string());
+        };
+        let head = match DnsMessage::parse_head(bytes) {
+            Ok(head) => head,
+            Err(err) => {
+                discard_unparsable(malformed, err);
+                return None;
+            }
+        };
+        if let Err(err) = head.validate_response(dns_question, self.config.use_0x20) {
+            debug!("Discard response from {}: {}", server_addr, err);
+            return None;
+        }
+        if head.header.flags.tc == 1 {
+            return Some(head);
+        }
+
+        match DnsMessage::parse(bytes) {
+            Ok(dns_response) => Some(dns_response),
+            Err(err) => {
+                discard_unparsable(malformed, err);
+                None
+            }
+        }
+    }
+
+    /// Parse the response to a question read from a stream connection
+    pub(crate) fn stream_response(
+        &self,
+        bytes: &[u8],
+        dns_question: &DnsMessage,
+        query: &Query,
+    ) -> Result<DnsMessage, ResolveError> {
+        let malformed = |reason: String| ResolveError::Malformed {
+            server: query.server.clone(),
+            step: query.step,
+            reason,
+        };
+        let dns_response = DnsMessage::parse(bytes).map_err(|err| malformed(err.to_string()))?;
+        dns_response
+            .validate_response(dns_question, self.config.use_0x20)
+            .map_err(|err| malformed(err.to_string()))?;
+
+        Ok(dns_response)
+    }
+
+    /// Whether a response over UDP was cut short and has to be asked for
+    /// again over TCP
+    pub(crate) fn is_truncated(dns_response: &DnsMessage, query: &Query) -> bool {
+        if dns_response.header.flags.tc == 0 {
+            return false;
+        }
+        info!(
+            "Response from {} is truncated, retrying over TCP",
+            query.server
+        );
+
+        true
+    }
+
+    /// Error of a query that got no response to its question over UDP,
+    /// given the reason the last unparsable datagram was discarded
+    pub(crate) fn udp_failure(query: &Query, malformed: Option<String>) -> ResolveError {
+        match malformed {
+            Some(reason) => ResolveError::Malformed {
+                server: query.server.clone(),
+                step: query.step,
+                reason,
+            },
+            None => query.timeout(),
+        }
+    }
+
+    /// Check that a query may still be sent
+    fn check_query(&self, query: &Query) -> Result<(), ResolveError> {
+        if query.step > MAX_STEPS {
+            return Err(ResolveError::LoopDetected {
+                name: query.name.clone(),
+                step: query.step,
+                reason: format!("gave up after {} queries", MAX_STEPS),
+            });
+        }
+        if Instant::now() >= query.deadline {
+            return Err(query.timeout());
+        }
+
+        Ok(())
+    }
+
+    /// Account for the outcome of a query to one server out of the set
+    /// being tried and check the response code. A server that timed out is
+    /// put back at the end of the set until it runs out of retries
+    fn finish_query(
+        &self,
+        query: &Query,
+        result: Result<DnsMessage, ResolveError>,
+        dns_servers: &mut ServerSet,
+    ) -> Result<DnsMessage, ResolveError> {
+        let dns_response = match result {
+            Ok(dns_response) => {
+                self.infra
+                    .record_rtt(&query.server, query.sent_at.elapsed());
+                dns_response
+            }
+            Err(err) => {
+                debug!("{}", err);
+                if let ResolveError::Timeout { .. } = err {
+                    self.infra.record_failure(
+                        &query.server,
+                        self.config.timeout_for_attempt(query.attempt),
+                    );
+                    dns_servers.retry(&query.server, self.config.retries);
+                }
+                return Err(err);
+            }
+        };
+
+        Resolver::check_response_code(&dns_response, &query.name, &query.server, query.step)?;
+
+        Ok(dns_response)
+    }
+
+    /// Zone and name servers to start resolving `name` from: the deepest
+    /// cached delegation, or the root
+    fn start_servers(&self, name: &str, root_dns_servers: &[String]) -> (String, ServerSet) {
+        match self.cache.delegation(name) {
+            Some(delegation) => {
+                debug!("Start {} at cached zone {}", name, delegation.zone);
+                (
+                    delegation.zone,
+                    ServerSet::new(self.infra.order(&delegation.addresses)),
+                )
+            }
+            None => (
+                String::new(),
+                ServerSet::new(self.infra.order(root_dns_servers)),
+            ),
+        }
+    }
+
+    /// Cache the RRsets of a response section given by a server of `zone`
+    fn cache_records(&self, records: &[ResourceRecord], zone: &str) {
+        if self.config.cache {
+            self.cache.insert_records(records, zone);
+        }
+    }
+
+    /// Cache an NXDOMAIN or NODATA answer for an RRset of `rr_type`, given
+    /// by a server of `zone`. Answers without a SOA of an enclosing zone
+    /// below `zone` are not cached (RFC 2308)
+    fn cache_negative(&self, err: &ResolveError, rr_type: u16, zone: &str) {
+        if !self.config.cache {
+            return;
+        }
+        match err {
+            ResolveError::NxDomain {
+                name,
+                server,
+                soa: Some(soa),
+                ..
+            } if Resolver::is_negative_authority(name, soa, zone) => {
+                self.cache.insert_nxdomain(name, server, soa)
+            }
+            ResolveError::NoData {
+                name,
+                server,
+                soa: Some(soa),
+                ..
+            } if Resolver::is_negative_authority(name, soa, zone) => {
+                let key = CacheKey::new(name, rr_type, rr::IN);
+                self.cache.insert_nodata(key, server, soa)
+            }
+            _ => {}
+        }
+    }
+
+    /// Result of looking up the addresses of `name`, IPv4 before IPv6,
+    /// valid for the lowest TTL of its records
+    pub(crate) fn address_result(
+        name: String,
+        records: &[ResourceRecord],
+        stale: bool,
+    ) -> LookupResult {
+        LookupResult {
+            ip_addrs: [rr::A, rr::AAAA]
+                .into_iter()
+                .flat_map(|rr_type| Resolver::record_values(records, rr_type))
+                .collect(),
+            ttl: records.iter().map(|rr| rr.rr_ttl).min().unwrap_or(0),
+            name,
+            stale,
+        }
+    }
+
+    /// Give records of `rr_type` as text: addresses for address records and
+    /// domain names for the others
+    pub(crate) fn record_values<'a>(
+        records: impl IntoIterator<Item = &'a ResourceRecord>,
+        rr_type: u16,
+    ) -> Vec<String> {
+        records
+            .into_iter()
+            .filter(|rr| rr.rr_type == rr_type)
+            .filter_map(|rr| match rr_type {
+                rr::A => rr.is_host_addr().then(|| rr.get_ip_addr()),
+                rr::AAAA => (rr.rr_rdata.len() == 16).then(|| rr.get_ip_addr()),
+                _ => Some(rr.get_domain_name()),
+            })
+            .collect()
+    }
+
+    /// Get the records of `rr_type` for `name` in the answer section,
+    /// leaving out address records without an address
+    fn answer_records(dns_response: &DnsMessage, name: &str, rr_type: u16) -> Vec<ResourceRecord> {
+        dns_response
+            .answers
+            .iter()
+            .filter(|an| {
+                an.rr_type == rr_type
+                    && (rr_type != rr::A || an.is_host_addr())
+                    && DnsMessage::decode_address(&an.rr_name).eq_ignore_ascii_case(name)
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// Name whose records of `rr_type` answer a question for `name`: the
+    /// end of its chain of aliases, or the name itself when aliases are
+    /// asked for
+    fn canonical_name(
+        dns_response: &DnsMessage,
+        host_name: &str,
+        name: &str,
+        rr_type: u16,
+        aliases: &mut Vec<String>,
+        step: usize,
+    ) -> Result<String, ResolveError> {
+        match rr_type {
+            rr::CNAME => Ok(name.to_string()),
+            _ => Resolver::follow_aliases(dns_response, host_name, name, aliases, step),
+        }
+    }
+
+    /// Follow the chain of aliases for `name` in the answer section, giving
+    /// the canonical name at the end of it
+    fn follow_aliases(
+        dns_response: &DnsMessage,
+        host_name: &str,
+        name: &str,
+        aliases: &mut Vec<String>,
+        step: usize,
+    ) -> Result<String, ResolveError> {
+        let mut name = name.to_string();
+        while let Some(cname) = dns_response.answers.iter().find(|an| {
+            an.rr_type == rr::CNAME
+                && DnsMessage::decode_address(&an.rr_name).eq_ignore_ascii_case(&name)
+        }) {
+            let target = cname.get_domain_name();
+            if target.eq_ignore_ascii_case(host_name)
+                || aliases.iter().any(|a| a.eq_ignore_ascii_case(&target))
+                || aliases.len() >= MAX_CNAMES
+            {
+                return Err(ResolveError::LoopDetected {
+                    name: host_name.to_string(),
+                    step,
+                    reason: format!("alias chain through {}", target),
+                });
+            }
+            debug!("{} is an alias for {}", name, target);
+            aliases.push(target.clone());
+            name = target;
+        }
+
+        Ok(name)
+    }
+
+    /// Error for a cached negative answer about `name`
+    fn negative_error(name: &str, answer: NegativeAnswer) -> ResolveError {
+        let soa = Some(Box::new(answer.soa));
+        match answer.kind {
+            NegativeKind::NxDomain => ResolveError::NxDomain {
+                name: name.to_string(),
+                server: answer.server,
+                step: 0,
+                soa,
+            },
+            NegativeKind::NoData => ResolveError::NoData {
+                name: name.to_string(),
+                server: answer.server,
+                step: 0,
+                soa,
+            },
+        }
+    }
+
+    /// Whether the SOA of a negative answer is for a zone enclosing `name`
+    /// that a server of `zone` may speak for
+    fn is_negative_authority(name: &str, soa: &Soa, zone: &str) -> bool {
+        DnsMessage::is_subdomain(name, &soa.zone) && DnsMessage::is_subdomain(&soa.zone, zone)
+    }
+
+    /// Find the SOA record in the authority section of a response
+    fn find_soa(dns_response: &DnsMessage) -> Option<Box<Soa>> {
+        dns_response
+            .authorities
+            .iter()
+            .find_map(|rr| rr.get_soa())
+            .map(Box::new)
+    }
+
+    /// Turn the response code of a response to a query for `name` into the
+    /// matching error
+    fn check_response_code(
+        dns_response: &DnsMessage,
+        name: &str,
+        dns_server: &str,
+        step: usize,
+    ) -> Result<(), ResolveError> {
+        let error = match dns_response.header.flags.r_code {
+            NO_ERROR => return Ok(()),
+            NAME_ERROR => ResolveError::NxDomain {
+                name: name.to_string(),
+                server: dns_server.to_string(),
+                step,
+                soa: Resolver::find_soa(dns_response),
+            },
+            SERVER_FAILURE => ResolveError::ServFail {
+                name: name.to_string(),
+                server: dns_server.to_string(),
+                step,
+            },
+            REFUSED => ResolveError::Refused {
+                name: name.to_string(),
+                server: dns_server.to_string(),
+                step,
+            },
+            r_code => ResolveError::Malformed {
+                server: dns_server.to_string(),
+                step,
+                reason: format!("unexpected response code {}", r_code),
+            },
+        };
+        debug!("{}", error);
+
+        Err(error)
+    }
+
+    /// Number of labels of a name, 0 for the root
+    fn label_count(name: &str) -> usize {
+        name.split('.').filter(|label| !label.is_empty()).count()
+    }
+
+    /// The name made of the last `count` labels of `name`
+    fn last_labels(name: &str, count: usize) -> String {
+        let labels = name
+            .split('.')
+            .filter(|label| !label.is_empty())
+            .collect::<Vec<_>>();
+        labels[labels.len() - count..].join(".")
+    }
+
+    /// Why the servers of `zone` given by `start_servers` are asked
+    fn start_choice(zone: &str) -> Choice {
+        if zone.is_empty() {
+            Choice::Root
+        } else {
+            Choice::Cached(zone.to_string())
+        }
+    }
+
+    /// Get the socket address of a name server, given as an IP address
+    /// optionally followed by a port, which defaults to `port`
+    fn parse_server_addr(dns_server: &str, port: u16) -> io::Result<SocketAddr> {
+        if let Ok(addr) = dns_server.parse::<SocketAddr>() {
+            return Ok(addr);
+        }
+        dns_server
+            .parse::<IpAddr>()
+            .map(|ip| SocketAddr::new(ip, port))
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))
+    }
+
+    /// Error for a resolution that ran out of servers to ask
+    fn give_up(name: &str, step: usize, last_error: Option<ResolveError>) -> ResolveError {
+        last_error.unwrap_or(ResolveError::LoopDetected {
+            name: name.to_string(),
+            step,
+            reason: String::from("ran out of servers to ask"),
+        })
+    }
+}
+
+/// A question a resolution wants sent to a name server
+pub(crate) struct Query {
+    pub(crate) name: String,
+    pub(crate) q_type: u16,
+    pub(crate) server: String,
+    pub(crate) step: usize,
+    /// Number of times the server has timed out before
+    pub(crate) attempt: u32,
+    pub(crate) deadline: Instant,
+    sent_at: Instant,
+    /// False when `check_query` kept the query from being sent
+    sent: bool,
+}
+
+impl Query {
+    fn new(
+        name: String,
+        q_type: u16,
+        server: String,
+        step: usize,
+        dns_servers: &ServerSet,
+        deadline: Instant,
+    ) -> Query {
+        Query {
+            name,
+            q_type,
+            attempt: dns_servers.attempts(&server),
+            server,
+            step,
+            deadline,
+            sent_at: Instant::now(),
+            sent: true,
+        }
+    }
+
+    /// Error for the query getting no response in time
+    pub(crate) fn timeout(&self) -> ResolveError {
+        ResolveError::Timeout {
+            name: self.name.clone(),
+            server: self.server.clone(),
+            step: self.step,
+        }
+    }
+}
+
+/// Name servers of a zone referred to without glue, whose addresses have to
+/// be looked up before a resolution can go on
+#[derive(Clone)]
+pub(crate) struct Delegation {
+    pub(crate) zone: String,
+    pub(crate) ns_names: Vec<String>,
+    /// Nesting of the resolution that was referred
+    pub(crate) depth: usize,
+    pub(crate) deadline: Instant,
+    /// Server that referred to the zone and the step it was asked at
+    server: String,
+    step: usize,
+}
+
+impl Delegation {
+    /// Names of the name servers to look up. A name server inside its own
+    /// zone can only be reached with glue
+    pub(crate) fn lookups(&self) -> impl Iterator<Item = &String> {
+        self.ns_names
+            .iter()
+            .filter(|ns_name| !DnsMessage::is_subdomain(ns_name, &self.zone))
+    }
+
+    /// Resolution of the addresses of a name server, nested in the one
+    /// that was referred
+    pub(crate) fn lookup(&self, resolver: &Resolver, ns_name: &str) -> Iteration {
+        Iteration::new(resolver, ns_name, rr::A, self.depth + 1, self.deadline)
+    }
+}
+
+/// What a resolution needs next
+pub(crate) enum Step<T> {
+    /// Send the query and hand its outcome to `Resolution::handle`
+    Query(Query),
+    /// Look up the addresses of the name servers and hand them to
+    /// `Resolution::delegated`
+    Delegate(Delegation),
+    /// The resolution is over
+    Done(Result<T, ResolveError>),
+}
+
+/// A resolution that asks for queries to be sent and is handed what came of
+/// them, without sending anything itself
+pub(crate) trait Resolution {
+    type Output;
+
+    /// What the resolution needs next
+    fn next(&mut self, resolver: &Resolver) -> Step<Self::Output>;
+
+    /// Go on with the response to the query, or the error it failed with
+    fn handle(
+        &mut self,
+        resolver: &Resolver,
+        query: Query,
+        result: Result<DnsMessage, ResolveError>,
+    );
+
+    /// Go on with the addresses of the name servers of a delegation, empty
+    /// when none could be looked up. Only iterative resolutions delegate
+    fn delegated(&mut self, _: &Resolver, _: Vec<String>) {}
+}
+
+/// Resolution of a name from the deepest cached zone, or the root servers
+/// when none is known, following referrals down. Servers of a zone that
+/// time out are retried after the other servers of the zone have been tried
+pub(crate) struct Iteration {
+    host_name: String,
+    rr_type: u16,
+    depth: usize,
+    deadline: Instant,
+    root_dns_servers: Vec<String>,
+    /// Name being resolved, the end of the aliases followed so far
+    name: String,
+    aliases: Vec<String>,
+    zone: String,
+    dns_servers: ServerSet,
+    choice: Choice,
+    minimise: bool,
+    /// Labels of the name known to be at or above a zone cut
+    revealed: usize,
+    minimise_count: usize,
+    /// Labels of the name asked for in the query in flight
+    labels: usize,
+    step: usize,
+    last_error: Option<ResolveError>,
+    delegation: Option<Delegation>,
+    done: Option<Result<Vec<ResourceRecord>, ResolveError>>,
+}
+
+impl Iteration {
+    /// Resolve the records of `rr_type` of a fully qualified name at the
+    /// given nesting of name server lookups
+    pub(crate) fn new(
+        resolver: &Resolver,
+        host_name: &str,
+        rr_type: u16,
+        depth: usize,
+        deadline: Instant,
+    ) -> Iteration {
+        let root_dns_servers = resolver.root_servers();
+        let (zone, dns_servers) = resolver.start_servers(host_name, &root_dns_servers);
+        Iteration {
+            host_name: host_name.to_string(),
+            rr_type,
+            depth,
+            deadline,
+            root_dns_servers,
+            name: host_name.to_string(),
+            aliases: vec![],
+            choice: Resolver::start_choice(&zone),
+            minimise: resolver.config.qname_minimisation,
+            revealed: Resolver::label_count(&zone),
+            minimise_count: 0,
+            labels: 0,
+            zone,
+            dns_servers,
+            step: 0,
+            last_error: None,
+            delegation: None,
+            done: None,
+        }
+    }
+
+    /// Whether the query in flight asks for fewer labels than the name has
+    fn minimised(&self) -> bool {
+        self.labels < Resolver::label_count(&self.name)
+    }
+
+    /// Tell the trace hook what a query that was sent led to
+    fn trace(&self, resolver: &Resolver, query: &Query, name: &str, outcome: Outcome) {
+        if !query.sent {
+            return;
+        }
+        if let Some(hook) = &resolver.trace {
+            hook(&TraceStep {
+                depth: self.depth,
+                step: query.step,
+                name: name.to_string(),
+                zone: self.zone.clone(),
+                server: query.server.clone(),
+                choice: self.choice.clone(),
+                elapsed: query.sent_at.elapsed(),
+                outcome,
+            });
+        }
+    }
+
+    /// Go on with a query that failed or was answered
+    fn handle_result(
+        &mut self,
+        resolver: &Resolver,
+        query: Query,
+        result: Result<DnsMessage, ResolveError>,
+    ) {
+        let minimised = self.minimised();
+        let dns_response = match result {
+            Ok(dns_response) => dns_response,
+            Err(err)
+                if minimised
+                    && (err.is_final()
+                        || matches!(
+                            err,
+                            ResolveError::ServFail { .. } | ResolveError::Refused { .. }
+                        )) =>
+            {
+                // Some servers deny that names without records of their
+                // own exist, or fail or refuse to answer for them, so ask
+                // them for the full name instead (RFC 9156 section 3)
+                self.trace(resolver, &query, &query.name, Outcome::Error(err.clone()));
+                self.minimise = false;
+                self.dns_servers.ask_again(query.server);
+                self.choice = Choice::Relaxed;
+                return;
+            }
+            Err(err) => {
+                self.trace(resolver, &query, &query.name, Outcome::Error(err.clone()));
+                if err.is_final() {
+                    resolver.cache_negative(&err, self.rr_type, &self.zone);
+                    self.done = Some(Err(err));
+                    return;
+                }
+                self.last_error = Some(err);
+                self.choice = Choice::Fallback;
+                return;
+            }
+        };
+
+        let referral = dns_response.header.flags.aa == 0
+            && dns_response
+                .authorities
+                .iter()
+                .any(|rr| rr.rr_type == rr::NS);
+        if minimised && !referral {
+            let alias = dns_response
+                .answers
+                .iter()
+                .any(|an| an.rr_type == rr::CNAME);
+            let no_cut = dns_response.header.an_cnt > 0
+                || dns_response.header.flags.aa == 1
+                || Resolver::find_soa(&dns_response).is_some();
+            if alias {
+                // The rest of the name can't be below an alias
+                self.trace(
+                    resolver,
+                    &query,
+                    &query.name,
+                    Outcome::Alias(query.name.clone()),
+                );
+                self.minimise = false;
+                self.dns_servers.ask_again(query.server);
+                self.choice = Choice::Relaxed;
+                return;
+            }
+            if no_cut {
+                self.trace(resolver, &query, &query.name, Outcome::NoCut);
+                self.revealed = self.labels;
+                self.dns_servers.ask_again(query.server);
+                self.choice = Choice::Longer;
+                return;
+            }
+        }
+
+        if dns_response.header.an_cnt > 0 {
+            resolver.cache_records(&dns_response.answers, &self.zone);
+            let alias = self.name.clone();
+            let target = Resolver::canonical_name(
+                &dns_response,
+                &self.host_name,
+                &self.name,
+                self.rr_type,
+                &mut self.aliases,
+                query.step,
+            );
+            let target = match target {
+                Ok(target) => target,
+                Err(err) => {
+                    self.trace(resolver, &query, &alias, Outcome::Error(err.clone()));
+                    self.done = Some(Err(err));
+                    return;
+                }
+            };
+            let records = Resolver::answer_records(&dns_response, &target, self.rr_type);
+            if !records.is_empty() {
+                let values = Resolver::record_values(&records, self.rr_type);
+                self.trace(resolver, &query, &alias, Outcome::Answer(values));
+                self.done = Some(Ok(records));
+                return;
+            }
+
+            if alias != target {
+                self.trace(resolver, &query, &alias, Outcome::Alias(target.clone()));
+                // Restart from the closest known zone of the target
+                self.name = target;
+                (self.zone, self.dns_servers) =
+                    resolver.start_servers(&self.name, &self.root_dns_servers);
+                self.choice = Resolver::start_choice(&self.zone);
+                self.minimise = resolver.config.qname_minimisation;
+                self.minimise_count = 0;
+                self.revealed = Resolver::label_count(&self.zone);
+                self.last_error = None;
+                return;
+            }
+        }
+
+        let ns_records = dns_response
+            .authorities
+            .iter()
+            .filter(|rr| rr.rr_type == rr::NS)
+            .collect::<Vec<_>>();
+        if !ns_records.is_empty() && dns_response.header.flags.aa == 0 {
+            let child = DnsMessage::decode_address(&ns_records[0].rr_name);
+            if !DnsMessage::is_subdomain(&self.name, &child)
+                || !DnsMessage::is_subdomain(&child, &self.zone)
+                || child.eq_ignore_ascii_case(&self.zone)
+            {
+                debug!(
+                    "{} referred {} to {} from zone {}",
+                    query.server, self.name, child, self.zone
+                );
+                let err = ResolveError::LameDelegation {
+                    name: self.name.clone(),
+                    server: query.server.clone(),
+                    step: query.step,
+                    zone: self.zone.clone(),
+                };
+                self.trace(resolver, &query, &query.name, Outcome::Error(err.clone()));
+                self.last_error = Some(err);
+                self.choice = Choice::Fallback;
+                return;
+            }
+
+            let ns_names = ns_records
+                .iter()
+                .map(|rr| rr.get_domain_name())
+                .collect::<Vec<_>>();
+            let glue = dns_response
+                .additionals
+                .iter()
+                .filter(|ar| {
+                    ar.is_host_addr()
+                        && ns_names.iter().any(|ns| {
+                            ns.eq_ignore_ascii_case(&DnsMessage::decode_address(&ar.rr_name))
+                        })
+                })
+                .cloned()
+                .collect::<Vec<_>>();
+            let ns_records = ns_records
+                .into_iter()
+                .filter(|rr| DnsMessage::decode_address(&rr.rr_name).eq_ignore_ascii_case(&child))
+                .cloned()
+                .collect::<Vec<_>>();
+            resolver.cache_records(&ns_records, &self.zone);
+            resolver.cache_records(&glue, &self.zone);
+            self.trace(
+                resolver,
+                &query,
+                &query.name,
+                Outcome::Referral {
+                    zone: child.clone(),
+                    name_servers: ns_names.clone(),
+                },
+            );
+            let delegation = Delegation {
+                zone: child,
+                ns_names,
+                depth: self.depth,
+                deadline: self.deadline,
+                server: query.server,
+                step: query.step,
+            };
+            let auth_servers = glue.iter().map(|ar| ar.get_ip_addr()).collect::<Vec<_>>();
+            if !auth_servers.is_empty() {
+                self.refer(resolver, delegation, auth_servers);
+            } else if self.depth >= MAX_DEPTH {
+                self.done = Some(Err(ResolveError::LoopDetected {
+                    name: delegation.zone,
+                    step: delegation.step,
+                    reason: String::from("name servers nested too deeply"),
+                }));
+            } else {
+                self.delegation = Some(delegation);
+            }
+            return;
+        }
+
+        let soa = Resolver::find_soa(&dns_response);
+        if dns_response.header.flags.aa == 1 || soa.is_some() {
+            let err = ResolveError::NoData {
+                name: self.name.clone(),
+                server: query.server.clone(),
+                step: query.step,
+                soa,
+            };
+            self.trace(resolver, &query, &query.name, Outcome::Error(err.clone()));
+            resolver.cache_negative(&err, self.rr_type, &self.zone);
+            self.done = Some(Err(err));
+            return;
+        }
+
+        let err = ResolveError::LameDelegation {
+            name: self.name.clone(),
+            server: query.server.clone(),
+            step: query.step,
+            zone: self.zone.clone(),
+        };
+        self.trace(resolver, &query, &query.name, Outcome::Error(err.clone()));
+        self.last_error = Some(err);
+        self.choice = Choice::Fallback;
+    }
+
+    /// Go on asking the servers a zone was delegated to
+    fn refer(&mut self, resolver: &Resolver, delegation: Delegation, auth_servers: Vec<String>) {
+        if auth_servers.is_empty() {
+            self.last_error = Some(ResolveError::LameDelegation {
+                name: self.name.clone(),
+                server: delegation.server,
+                step: delegation.step,
+                zone: delegation.zone,
+            });
+            self.choice = Choice::Fallback;
+            return;
+        }
+
+        info!(
+            "{} delegated {} to {:?}",
+            delegation.server, delegation.zone, delegation.ns_names
+        );
+        self.choice = Choice::Referral(delegation.zone.clone());
+        self.revealed = Resolver::label_count(&delegation.zone);
+        self.zone = delegation.zone;
+        self.dns_servers = ServerSet::new(resolver.infra.order(&auth_servers));
+        self.last_error = None;
+    }
+}
+
+impl Resolution for Iteration {
+    type Output = Vec<ResourceRecord>;
+
+    fn next(&mut self, resolver: &Resolver) -> Step<Vec<ResourceRecord>> {
+        loop {
+            if let Some(result) = self.done.take() {
+                return Step::Done(result);
+            }
+            if let Some(delegation) = &self.delegation {
+                return Step::Delegate(delegation.clone());
+            }
+            let dns_server = match self.dns_servers.next() {
+                Some(dns_server) => dns_server,
+                None => {
+                    let err = Resolver::give_up(&self.name, self.step, self.last_error.take());
+                    return Step::Done(Err(err));
+                }
+            };
+
+            self.step += 1;
+            let total = Resolver::label_count(&self.name);
+            self.labels = if self.minimise {
+                resolver
+                    .config
+                    .minimised_labels(total, self.revealed, self.minimise_count)
+            } else {
+                total
+            };
+            let (qname, qtype) = if self.minimised() {
+                self.minimise_count += 1;
+                (Resolver::last_labels(&self.name, self.labels), rr::A)
+            } else {
+                (self.name.clone(), self.rr_type)
+            };
+            let mut query = Query::new(
+                qname,
+                qtype,
+                dns_server,
+                self.step,
+                &self.dns_servers,
+                self.deadline,
+            );
+            match resolver.check_query(&query) {
+                Ok(()) => return Step::Query(query),
+                Err(err) => {
+                    query.sent = false;
+                    self.handle_result(resolver, query, Err(err));
+                }
+            }
+        }
+    }
+
+    fn handle(
+        &mut self,
+        resolver: &Resolver,
+        query: Query,
+        result: Result<DnsMessage, ResolveError>,
+    ) {
+        let result = resolver.finish_query(&query, result, &mut self.dns_servers);
+        self.handle_result(resolver, query, result);
+    }
+
+    fn delegated(&mut self, resolver: &Resolver, auth_servers: Vec<String>) {
+        if let Some(delegation) = self.delegation.take() {
+            self.refer(resolver, delegation, auth_servers);
+        }
+    }
+}
+
+/// Resolution of a name by asking recursive name servers, moving to the
+/// next server when one fails or doesn't offer recursion
+pub(crate) struct StubLookup {
+    name: String,
+    rr_type: u16,
+    deadline: Instant,
+    dns_servers: ServerSet,
+    step: usize,
+    last_error: Option<ResolveError>,
+    done: Option<Result<Vec<ResourceRecord>, ResolveError>>,
+}
+
+impl StubLookup {
+    /// Resolve the records of `rr_type` of a fully qualified name, asking
+    /// `name_servers` in order, or from the next one in turn when rotating
+    pub(crate) fn new(
+        resolver: &Resolver,
+        name: &str,
+        rr_type: u16,
+        name_servers: &[String],
+        deadline: Instant,
+    ) -> StubLookup {
+        let mut name_servers = name_servers.to_vec();
+        if resolver.config.rotate && !name_servers.is_empty() {
+            let next_server = resolver.next_server.fetch_add(1, Ordering::Relaxed);
+            let len = name_servers.len();
+            name_servers.rotate_left(next_server % len);
+        }
+
+        StubLookup {
+            name: name.to_string(),
+            rr_type,
+            deadline,
+            dns_servers: ServerSet::new(name_servers),
+            step: 0,
+            last_error: None,
+            done: None,
+        }
+    }
+
+    /// Go on with a query that failed or was answered
+    fn handle_result(
+        &mut self,
+        resolver: &Resolver,
+        query: Query,
+        result: Result<DnsMessage, ResolveError>,
+    ) {
+        let dns_response = match result {
+            Ok(dns_response) => dns_response,
+            Err(err) if err.is_final() => {
+                resolver.cache_negative(&err, self.rr_type, "");
+                self.done = Some(Err(err));
+                return;
+            }
+            Err(err) => {
+                self.last_error = Some(err);
+                return;
+            }
+        };
+
+        if dns_response.header.flags.ra == 0 {
+            debug!("{} does not offer recursion", query.server);
+            self.last_error = Some(ResolveError::Refused {
+                name: self.name.clone(),
+                server: query.server,
+                step: query.step,
+            });
+            return;
+        }
+
+        resolver.cache_records(&dns_response.answers, "");
+        let name = match Resolver::canonical_name(
+            &dns_response,
+            &self.name,
+            &self.name,
+            self.rr_type,
+            &mut vec![],
+            query.step,
+        ) {
+            Ok(name) => name,
+            Err(err) => {
+                self.done = Some(Err(err));
+                return;
+            }
+        };
+        let records = Resolver::answer_records(&dns_response, &name, self.rr_type);
+        if !records.is_empty() {
+            self.done = Some(Ok(records));
+            return;
+        }
+
+        let err = ResolveError::NoData {
+            name,
+            server: query.server,
+            step: query.step,
+            soa: Resolver::find_soa(&dns_response),
+        };
+        resolver.cache_negative(&err, self.rr_type, "");
+        self.done = Some(Err(err));
+    }
+}
+
+impl Resolution for StubLookup {
+    type Output = Vec<ResourceRecord>;
+
+    fn next(&mut self, resolver: &Resolver) -> Step<Vec<ResourceRecord>> {
+        loop {
+            if let Some(result) = self.done.take() {
+                return Step::Done(result);
+            }
+            let dns_server = match self.dns_servers.next() {
+                Some(dns_server) => dns_server,
+                None => {
+                    let err = Resolver::give_up(&self.name, self.step, self.last_error.take());
+                    return Step::Done(Err(err));
+                }
+            };
+
+            self.step += 1;
+            let query = Query::new(
+                self.name.clone(),
+                self.rr_type,
+                dns_server,
+                self.step,
+                &self.dns_servers,
+                self.deadline,
+            );
+            match resolver.check_query(&query) {
+                Ok(()) => return Step::Query(query),
+                Err(err) => self.handle_result(resolver, query, Err(err)),
+            }
+        }
+    }
+
+    fn handle(
+        &mut self,
+        resolver: &Resolver,
+        query: Query,
+        result: Result<DnsMessage, ResolveError>,
+    ) {
+        let result = resolver.finish_query(&query, result, &mut self.dns_servers);
+        self.handle_result(resolver, query, result);
+    }
+}
+
+/// Priming query sent to the root servers in turn until one gives a usable
+/// root NS set (RFC 8109)
+pub(crate) struct Priming {
+    deadline: Instant,
+    dns_servers: ServerSet,
+    step: usize,
+    last_error: Option<ResolveError>,
+    done: Option<RootHints>,
+}
+
+impl Priming {
+    /// Ask the current root servers, fastest first
+    pub(crate) fn new(resolver: &Resolver, deadline: Instant) -> Priming {
+        Priming {
+            deadline,
+            dns_servers: ServerSet::new(resolver.infra.order(&resolver.root_servers())),
+            step: 0,
+            last_error: None,
+            done: None,
+        }
+    }
+}
+
+impl Resolution for Priming {
+    type Output = RootHints;
+
+    fn next(&mut self, resolver: &Resolver) -> Step<RootHints> {
+        loop {
+            if let Some(root_hints) = self.done.take() {
+                return Step::Done(Ok(root_hints));
+            }
+            let dns_server = match self.dns_servers.next() {
+                Some(dns_server) => dns_server,
+                None => {
+                    let err = Resolver::give_up(".", self.step, self.last_error.take());
+                    return Step::Done(Err(err));
+                }
+            };
+
+            self.step += 1;
+            let query = Query::new(
+                String::from("."),
+                rr::NS,
+                dns_server,
+                self.step,
+                &self.dns_servers,
+                self.deadline,
+            );
+            match resolver.check_query(&query) {
+                Ok(()) => return Step::Query(query),
+                Err(err) => self.last_error = Some(err),
+            }
+        }
+    }
+
+    fn handle(
+        &mut self,
+        resolver: &Resolver,
+        query: Query,
+        result: Result<DnsMessage, ResolveError>,
+    ) {
+        match resolver.finish_query(&query, result, &mut self.dns_servers) {
+            Ok(dns_response) => match RootHints::from_response(&dns_response) {
+                Some(root_hints) => self.done = Some(root_hints),
+                None => {
+                    self.last_error = Some(ResolveError::Malformed {
+                        server: query.server,
+                        step: query.step,
+                        reason: String::from("no root servers in priming response"),
+                    })
+                }
+            },
+            Err(err) => self.last_error = Some(err),
+        }
+    }
+}
+
+/// Name servers waiting to be asked, with the number of times each one has
+/// timed out
+struct ServerSet {
+    servers: VecDeque<String>,
+    attempts: HashMap<String, u32>,
+}
+
+impl ServerSet {
+    /// Create a set asking the servers in order
+    fn new(servers: Vec<String>) -> ServerSet {
+        ServerSet {
+            servers: VecDeque::from(servers),
+            attempts: HashMap::new(),
+        }
+    }
+
+    /// Take the next server to ask
+    fn next(&mut self) -> Option<String> {
+        self.servers.pop_front()
+    }
+
+    /// Number of times a server has timed out
+    fn attempts(&self, server: &str) -> u32 {
+        self.attempts.get(server).copied().unwrap_or(0)
+    }
+
+    /// Ask a server again before the others
+    fn ask_again(&mut self, server: String) {
+        self.servers.push_front(server);
+    }
+
+    /// Ask a server that timed out again after the others, unless it has
+    /// run out of retries
+    fn retry(&mut self, server: &str, retries: u32) {
+        let attempts = self.attempts.entry(server.to_string()).or_insert(0);
+        *attempts += 1;
+        if *attempts <= retries {
+            self.servers.push_back(server.to_string());
+        }
+    }
+}
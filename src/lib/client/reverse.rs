@@ -0,0 +1,74 @@
+use std::error::Error;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+/// Zone holding the reverse names of IPv4 addresses
+pub const IN_ADDR_ARPA: &str = "in-addr.arpa";
+/// Zone holding the reverse names of IPv6 addresses
+pub const IP6_ARPA: &str = "ip6.arpa";
+
+/// Name the host names of `ip` are found at: the octets in reverse under
+/// `in-addr.arpa` (RFC 1035) or the nibbles in reverse under `ip6.arpa`
+/// (RFC 3596)
+pub fn reverse_name(ip: IpAddr) -> String {
+    let mut labels = match ip {
+        IpAddr::V4(ip) => ip
+            .octets()
+            .iter()
+            .rev()
+            .map(|octet| octet.to_string())
+            .collect::<Vec<_>>(),
+        IpAddr::V6(ip) => ip
+            .octets()
+            .iter()
+            .rev()
+            .flat_map(|octet| [octet & 0x0F, octet >> 4])
+            .map(|nibble| format!("{:x}", nibble))
+            .collect(),
+    };
+    labels.push(String::from(match ip {
+        IpAddr::V4(_) => IN_ADDR_ARPA,
+        IpAddr::V6(_) => IP6_ARPA,
+    }));
+
+    labels.join(".")
+}
+
+/// Get the address a full reverse name made by `reverse_name` stands for.
+/// Letter case and a trailing dot are ignored
+pub fn parse_reverse_name(name: &str) -> Result<IpAddr, Box<dyn Error>> {
+    let name = name.trim_end_matches('.').to_ascii_lowercase();
+    if let Some(labels) = name.strip_suffix(&format!(".{}", IN_ADDR_ARPA)) {
+        let labels = labels.split('.').collect::<Vec<_>>();
+        if labels.len() != 4 {
+            return Err(format!("Expected 4 octets in {}, got {}", name, labels.len()).into());
+        }
+        let mut octets = [0; 4];
+        for (octet, label) in octets.iter_mut().rev().zip(labels) {
+            if label.is_empty() || !label.bytes().all(|b| b.is_ascii_digit()) {
+                return Err(format!("Invalid octet {:?} in {}", label, name).into());
+            }
+            *octet = label.parse()?;
+        }
+        return Ok(IpAddr::V4(Ipv4Addr::from(octets)));
+    }
+
+    if let Some(labels) = name.strip_suffix(&format!(".{}", IP6_ARPA)) {
+        let labels = labels.split('.').collect::<Vec<_>>();
+        if labels.len() != 32 {
+            return Err(format!("Expected 32 nibbles in {}, got {}", name, labels.len()).into());
+        }
+        let mut octets = [0; 16];
+        for (i, label) in labels.iter().rev().enumerate() {
+            let nibble = match label.as_bytes() {
+                [digit] => (*digit as char)
+                    .to_digit(16)
+                    .ok_or_else(|| format!("Invalid nibble {:?} in {}", label, name))?,
+                _ => return Err(format!("Invalid nibble {:?} in {}", label, name).into()),
+            };
+            octets[i / 2] |= (nibble as u8) << if i % 2 == 0 { 4 } else { 0 };
+        }
+        return Ok(IpAddr::V6(Ipv6Addr::from(octets)));
+    }
+
+    Err(format!("{} is not under {} or {}", name, IN_ADDR_ARPA, IP6_ARPA).into())
+}
@@ -0,0 +1,178 @@
+use std::error::Error;
+use std::fs;
+use std::net::IpAddr;
+use std::path::Path;
+
+use crate::client::message::DnsMessage;
+use crate::client::rr;
+
+/// Addresses of the root servers as published by IANA in named.root
+const BUILTIN_ROOT_SERVERS: [(&str, &str, &str); 13] = [
+    ("a.root-servers.net", "198.41.0.4", "2001:503:ba3e::2:30"),
+    ("b.root-servers.net", "170.247.170.2", "2801:1b8:10::b"),
+    ("c.root-servers.net", "192.33.4.12", "2001:500:2::c"),
+    ("d.root-servers.net", "199.7.91.13", "2001:500:2d::d"),
+    ("e.root-servers.net", "192.203.230.10", "2001:500:a8::e"),
+    ("f.root-servers.net", "192.5.5.241", "2001:500:2f::f"),
+    ("g.root-servers.net", "192.112.36.4", "2001:500:12::d0d"),
+    ("h.root-servers.net", "198.97.190.53", "2001:500:1::53"),
+    ("i.root-servers.net", "192.36.148.17", "2001:7fe::53"),
+    ("j.root-servers.net", "192.58.128.30", "2001:503:c27::2:30"),
+    ("k.root-servers.net", "193.0.14.129", "2001:7fd::1"),
+    ("l.root-servers.net", "199.7.83.42", "2001:500:9f::42"),
+    ("m.root-servers.net", "202.12.27.33", "2001:dc3::35"),
+];
+
+/// A name server of the root zone
+#[derive(Debug, Clone, PartialEq)]
+pub struct RootServer {
+    /// Host name of the server
+    pub name: String,
+    /// IPv4 and IPv6 addresses of the server
+    pub ip_addrs: Vec<String>,
+}
+
+/// Name servers to start iterative resolution from
+#[derive(Debug, Clone, PartialEq)]
+pub struct RootHints {
+    pub servers: Vec<RootServer>,
+}
+
+impl RootHints {
+    /// Root servers compiled into the resolver
+    pub fn builtin() -> RootHints {
+        let servers = BUILTIN_ROOT_SERVERS
+            .iter()
+            .map(|(name, ipv4, ipv6)| RootServer {
+                name: name.to_string(),
+                ip_addrs: vec![ipv4.to_string(), ipv6.to_string()],
+            })
+            .collect();
+
+        RootHints { servers }
+    }
+
+    /// Create root hints from servers given only by address, such as the
+    /// root of a private DNS tree. An address may include a port
+    pub fn from_addresses(ip_addrs: &[String]) -> RootHints {
+        let servers = ip_addrs
+            .iter()
+            .map(|ip_addr| RootServer {
+                name: ip_addr.clone(),
+                ip_addrs: vec![ip_addr.clone()],
+            })
+            .collect();
+
+        RootHints { servers }
+    }
+
+    /// Parse root hints in the zone file format of named.root: NS records
+    /// of the root zone and A or AAAA records of the servers they name.
+    /// TTL and class are optional and `;` starts a comment
+    pub fn parse(content: &str) -> Result<RootHints, Box<dyn Error>> {
+        let mut names = vec![];
+        let mut addresses = vec![];
+        for (number, line) in content.lines().enumerate() {
+            let line = line.split(';').next().unwrap_or_default();
+            let words = line.split_whitespace().collect::<Vec<_>>();
+            if words.is_empty() {
+                continue;
+            }
+            let fields = words[1..]
+                .iter()
+                .skip_while(|word| word.parse::<u32>().is_ok() || word.eq_ignore_ascii_case("IN"))
+                .collect::<Vec<_>>();
+            let (rr_type, rdata) = match fields.as_slice() {
+                [rr_type, rdata] => (rr_type.to_ascii_uppercase(), rdata),
+                _ => return Err(format!("Invalid root hint on line {}", number + 1).into()),
+            };
+            let owner = words[0].trim_end_matches('.').to_ascii_lowercase();
+            match rr_type.as_str() {
+                "NS" if owner.is_empty() => {
+                    names.push(rdata.trim_end_matches('.').to_ascii_lowercase())
+                }
+                "A" | "AAAA" => {
+                    let ip_addr = rdata.parse::<IpAddr>().map_err(|err| {
+                        format!("Invalid address on line {}: {}", number + 1, err)
+                    })?;
+                    addresses.push((owner, ip_addr.to_string()));
+                }
+                _ => {}
+            }
+        }
+
+        let root_hints = RootHints::from_records(&names, &addresses);
+        if root_hints.addresses().is_empty() {
+            return Err("No root server addresses in root hints".into());
+        }
+        Ok(root_hints)
+    }
+
+    /// Read root hints from a named.root file
+    pub fn read(path: impl AsRef<Path>) -> Result<RootHints, Box<dyn Error>> {
+        let content = fs::read_to_string(path)?;
+        RootHints::parse(&content)
+    }
+
+    /// Get root hints from the response to a priming query, the NS records
+    /// of the root zone in the answer section and their addresses in the
+    /// additional section
+    pub fn from_response(dns_response: &DnsMessage) -> Option<RootHints> {
+        let names = dns_response
+            .answers
+            .iter()
+            .filter(|an| an.rr_type == rr::NS && an.rr_name == [0])
+            .map(|an| an.get_domain_name().to_ascii_lowercase())
+            .collect::<Vec<_>>();
+        let addresses = dns_response
+            .additionals
+            .iter()
+            .filter(|ar| ar.rr_type == rr::A || ar.rr_type == rr::AAAA)
+            .map(|ar| {
+                (
+                    DnsMessage::decode_address(&ar.rr_name).to_ascii_lowercase(),
+                    ar.get_ip_addr(),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        let root_hints = RootHints::from_records(&names, &addresses);
+        if root_hints.addresses().is_empty() {
+            return None;
+        }
+        Some(root_hints)
+    }
+
+    /// Addresses of the servers to send IPv4 queries to
+    pub fn addresses(&self) -> Vec<String> {
+        self.servers
+            .iter()
+            .flat_map(|server| server.ip_addrs.iter())
+            .filter(|ip_addr| !matches!(ip_addr.parse::<IpAddr>(), Ok(IpAddr::V6(_))))
+            .cloned()
+            .collect()
+    }
+
+    /// Pair the names of the root servers with their addresses
+    fn from_records(names: &[String], addresses: &[(String, String)]) -> RootHints {
+        let servers = names
+            .iter()
+            .map(|name| RootServer {
+                name: name.clone(),
+                ip_addrs: addresses
+                    .iter()
+                    .filter(|(owner, _)| owner == name)
+                    .map(|(_, ip_addr)| ip_addr.clone())
+                    .collect(),
+            })
+            .collect();
+
+        RootHints { servers }
+    }
+}
+
+impl Default for RootHints {
+    fn default() -> Self {
+        RootHints::builtin()
+    }
+}
@@ -1,8 +1,32 @@
 use std::error::Error;
+use std::net::Ipv6Addr;
 
+use crate::client::message::DnsMessage;
 use crate::client::utility;
 
+/// Host address
+pub const A: u16 = 1;
+/// Authoritative name server
+pub const NS: u16 = 2;
+/// Canonical name for an alias
+pub const CNAME: u16 = 5;
+/// Start of a zone of authority
+pub const SOA: u16 = 6;
+/// Domain name pointer
+pub const PTR: u16 = 12;
+/// Mail exchange
+pub const MX: u16 = 15;
+/// Text strings
+pub const TXT: u16 = 16;
+/// IPv6 host address
+pub const AAAA: u16 = 28;
+/// EDNS(0) option pseudo record
+pub const OPT: u16 = 41;
+/// Internet class
+pub const IN: u16 = 1;
+
 /// DNS resource record
+#[derive(Debug, Clone, PartialEq)]
 pub struct ResourceRecord {
     /// A domain name to which this resource record pertains
     pub rr_name: Vec<u8>,
@@ -20,6 +44,18 @@ pub struct ResourceRecord {
 }
 
 impl ResourceRecord {
+    /// Create a record of the internet class
+    pub fn new(name: &str, rr_type: u16, ttl: u32, rdata: Vec<u8>) -> ResourceRecord {
+        ResourceRecord {
+            rr_name: DnsMessage::encode_address(name),
+            rr_type,
+            rr_class: IN,
+            rr_ttl: ttl,
+            rr_rdlength: rdata.len() as u16,
+            rr_rdata: rdata,
+        }
+    }
+
     /// Transform a resource record to a vector of bytes
     pub fn to_be_bytes(&self) -> Vec<u8> {
         let mut reply = vec![];
@@ -46,54 +82,75 @@ impl ResourceRecord {
     }
 
     /// Parse a vector of bytes into a resource record
-    pub fn parse(
-        message: &Vec<u8>,
+    pub fn parse(message: &[u8], start: usize) -> Result<(usize, ResourceRecord), Box<dyn Error>> {
+        let (offset, rr_name) = utility::read_name(message, start)?;
+        let fields = utility::get_slice(message, offset, offset + 10)?;
+        let rr_type = utility::to_u16(&fields[0..2]);
+        let rr_class = utility::to_u16(&fields[2..4]);
+        let rr_ttl = utility::to_u32(&fields[4..8]);
+        let rdlength = utility::to_u16(&fields[8..10]) as usize;
+        let rdata_start = offset + 10;
+        utility::get_slice(message, rdata_start, rdata_start + rdlength)?;
+        let rr_rdata = ResourceRecord::expand_rdata(message, rr_type, rdata_start, rdlength)?;
+
+        let rr = ResourceRecord {
+            rr_name,
+            rr_type,
+            rr_class,
+            rr_ttl,
+            rr_rdlength: rr_rdata.len() as u16,
+            rr_rdata,
+        };
+        Ok((rdata_start + rdlength, rr))
+    }
+
+    /// Copy rdata out of a message, decompressing embedded domain names so
+    /// the record can be used without the message it came from
+    fn expand_rdata(
+        message: &[u8],
+        rr_type: u16,
         start: usize,
-    ) -> Result<(usize, ResourceRecord), Box<dyn Error>> {
-        let is_pointer = message[start] & 0xC0;
-        if is_pointer == 0xC0 {
-            let offset = (utility::to_u16(&message[start..start + 2]) & 0x3F) as usize;
-            let null_pos = utility::find_first_null(&message[offset as usize..])?;
-            let rr_name = message[offset as usize..offset + null_pos + 1].to_vec();
-            let rr_type = utility::to_u16(&message[start + 2..start + 4]);
-            let rr_class = utility::to_u16(&message[start + 4..start + 6]);
-            let rr_ttl = utility::to_u32(&message[start + 6..start + 10]);
-            let rr_rdlength = utility::to_u16(&message[start + 10..start + 12]);
-            let rr_rdata = message[start + 12..start + 12 + rr_rdlength as usize].to_vec();
-
-            let rr = ResourceRecord {
-                rr_name,
-                rr_type,
-                rr_class,
-                rr_ttl,
-                rr_rdlength,
-                rr_rdata,
-            };
-            Ok((start + 12 + rr_rdlength as usize, rr))
-        } else {
-            let null_pos = utility::find_first_null(&message[start..])?;
-            let offset = start + null_pos;
-            let rr_name = message[start..offset + 1].to_vec();
-            let rr_type = utility::to_u16(&message[offset + 1..offset + 3]);
-            let rr_class = utility::to_u16(&message[offset + 3..offset + 5]);
-            let rr_ttl = utility::to_u32(&message[offset + 5..offset + 9]);
-            let rr_rdlength = utility::to_u16(&message[offset + 9..offset + 11]);
-            let rr_rdata = message[offset + 11..offset + 11 + rr_rdlength as usize].to_vec();
-
-            let rr = ResourceRecord {
-                rr_name,
-                rr_type,
-                rr_class,
-                rr_ttl,
-                rr_rdlength,
-                rr_rdata,
-            };
-            Ok((offset + 11 + rr_rdlength as usize, rr))
-        }
+        length: usize,
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        let end = start + length;
+        let rdata = match rr_type {
+            NS | CNAME | PTR => {
+                let (offset, name) = utility::read_name(message, start)?;
+                if offset != end {
+                    return Err("Domain name does not fill rdata".into());
+                }
+                name
+            }
+            MX => {
+                let mut rdata = utility::get_slice(message, start, start + 2)?.to_vec();
+                let (offset, mut name) = utility::read_name(message, start + 2)?;
+                if offset != end {
+                    return Err("MX exchange does not fill rdata".into());
+                }
+                rdata.append(&mut name);
+                rdata
+            }
+            SOA => {
+                let (offset, mut rdata) = utility::read_name(message, start)?;
+                let (offset, mut r_name) = utility::read_name(message, offset)?;
+                if offset + 20 != end {
+                    return Err("Invalid SOA rdata length".into());
+                }
+                rdata.append(&mut r_name);
+                rdata.extend_from_slice(utility::get_slice(message, offset, end)?);
+                rdata
+            }
+            _ => utility::get_slice(message, start, end)?.to_vec(),
+        };
+
+        Ok(rdata)
     }
 
     /// Get the ip address from resource record
     pub fn get_ip_addr(&self) -> String {
+        if let Ok(octets) = <[u8; 16]>::try_from(self.rr_rdata.as_slice()) {
+            return Ipv6Addr::from(octets).to_string();
+        }
         self.rr_rdata
             .iter()
             .map(|&seg| seg.to_string())
@@ -101,7 +158,58 @@ impl ResourceRecord {
             .join(".")
     }
 
+    /// Whether the record holds an IPv4 host address
     pub fn is_host_addr(&self) -> bool {
-        self.rr_type == 0x01
+        self.rr_type == A && self.rr_rdata.len() == 4
+    }
+
+    /// Get the domain name held in the rdata of NS, CNAME and PTR records
+    pub fn get_domain_name(&self) -> String {
+        DnsMessage::decode_address(&self.rr_rdata)
     }
+
+    /// Get the start of authority data from a SOA record
+    pub fn get_soa(&self) -> Option<Soa> {
+        if self.rr_type != SOA {
+            return None;
+        }
+        let (offset, m_name) = utility::read_name(&self.rr_rdata, 0).ok()?;
+        let (offset, r_name) = utility::read_name(&self.rr_rdata, offset).ok()?;
+        let fields = utility::get_slice(&self.rr_rdata, offset, offset + 20).ok()?;
+
+        Some(Soa {
+            zone: DnsMessage::decode_address(&self.rr_name),
+            m_name: DnsMessage::decode_address(&m_name),
+            r_name: DnsMessage::decode_address(&r_name),
+            serial: utility::to_u32(&fields[0..4]),
+            refresh: utility::to_u32(&fields[4..8]),
+            retry: utility::to_u32(&fields[8..12]),
+            expire: utility::to_u32(&fields[12..16]),
+            minimum: utility::to_u32(&fields[16..20]),
+            ttl: self.rr_ttl,
+        })
+    }
+}
+
+/// Start of a zone of authority
+#[derive(Debug, Clone, PartialEq)]
+pub struct Soa {
+    /// Owner of the SOA record, i.e. the zone apex
+    pub zone: String,
+    /// Primary name server of the zone
+    pub m_name: String,
+    /// Mailbox of the person responsible for the zone
+    pub r_name: String,
+    /// Version number of the zone
+    pub serial: u32,
+    /// Interval before the zone should be refreshed
+    pub refresh: u32,
+    /// Interval before a failed refresh should be retried
+    pub retry: u32,
+    /// Upper limit before the zone is no longer authoritative
+    pub expire: u32,
+    /// Minimum TTL, used for negative caching
+    pub minimum: u32,
+    /// TTL of the SOA record itself
+    pub ttl: u32,
 }
@@ -0,0 +1,124 @@
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::time::Instant;
+
+use crate::client::transport::remaining;
+
+/// Write a DNS message to a stream, prefixed with its two-octet length as
+/// required for TCP by RFC 1035
+pub fn write_message(stream: &mut impl Write, msg: &[u8]) -> io::Result<()> {
+    let length = u16::try_from(msg.len())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Message too long for TCP"))?;
+    let mut framed = length.to_be_bytes().to_vec();
+    framed.extend_from_slice(msg);
+    stream.write_all(&framed)?;
+    stream.flush()
+}
+
+/// Read a length prefixed DNS message from a stream
+pub fn read_message(stream: &mut impl Read) -> io::Result<Vec<u8>> {
+    let mut length = [0; 2];
+    stream.read_exact(&mut length)?;
+    let mut msg = vec![0; u16::from_be_bytes(length) as usize];
+    stream.read_exact(&mut msg)?;
+
+    Ok(msg)
+}
+
+/// Write a DNS message to a TCP stream like `write_message`, failing with
+/// `TimedOut` once `deadline` passes however slowly the server reads
+pub fn write_message_until(
+    stream: &mut TcpStream,
+    msg: &[u8],
+    deadline: Instant,
+) -> io::Result<()> {
+    let length = u16::try_from(msg.len())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Message too long for TCP"))?;
+    let mut framed = length.to_be_bytes().to_vec();
+    framed.extend_from_slice(msg);
+    let mut written = 0;
+    while written < framed.len() {
+        stream.set_write_timeout(Some(remaining(deadline)?))?;
+        match stream.write(&framed[written..]) {
+            Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+            Ok(len) => written += len,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(timed_out(err)),
+        }
+    }
+
+    Ok(())
+}
+
+/// Read a length prefixed DNS message from a TCP stream like
+/// `read_message`, failing with `TimedOut` once `deadline` passes however
+/// slowly the server sends it
+pub fn read_message_until(stream: &mut TcpStream, deadline: Instant) -> io::Result<Vec<u8>> {
+    let mut length = [0; 2];
+    read_exact_until(stream, &mut length, deadline)?;
+    let mut msg = vec![0; u16::from_be_bytes(length) as usize];
+    read_exact_until(stream, &mut msg, deadline)?;
+
+    Ok(msg)
+}
+
+/// Fill `buffer` from a TCP stream, checking `deadline` before each read
+fn read_exact_until(
+    stream: &mut TcpStream,
+    buffer: &mut [u8],
+    deadline: Instant,
+) -> io::Result<()> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        stream.set_read_timeout(Some(remaining(deadline)?))?;
+        match stream.read(&mut buffer[filled..]) {
+            Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(len) => filled += len,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(timed_out(err)),
+        }
+    }
+
+    Ok(())
+}
+
+/// Report a socket timeout, which is `WouldBlock` on some platforms, as
+/// `TimedOut`
+fn timed_out(err: io::Error) -> io::Error {
+    match err.kind() {
+        io::ErrorKind::WouldBlock => io::ErrorKind::TimedOut.into(),
+        _ => err,
+    }
+}
+
+/// Write a DNS message to an asynchronous stream, prefixed with its
+/// two-octet length
+#[cfg(feature = "tokio")]
+pub async fn write_message_async(
+    stream: &mut (impl tokio::io::AsyncWrite + Unpin),
+    msg: &[u8],
+) -> io::Result<()> {
+    use tokio::io::AsyncWriteExt;
+
+    let length = u16::try_from(msg.len())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Message too long for TCP"))?;
+    let mut framed = length.to_be_bytes().to_vec();
+    framed.extend_from_slice(msg);
+    stream.write_all(&framed).await?;
+    stream.flush().await
+}
+
+/// Read a length prefixed DNS message from an asynchronous stream
+#[cfg(feature = "tokio")]
+pub async fn read_message_async(
+    stream: &mut (impl tokio::io::AsyncRead + Unpin),
+) -> io::Result<Vec<u8>> {
+    use tokio::io::AsyncReadExt;
+
+    let mut length = [0; 2];
+    stream.read_exact(&mut length).await?;
+    let mut msg = vec![0; u16::from_be_bytes(length) as usize];
+    stream.read_exact(&mut msg).await?;
+
+    Ok(msg)
+}
@@ -0,0 +1,124 @@
+use std::fmt;
+use std::time::Duration;
+
+use crate::client::error::ResolveError;
+
+/// Receives every query of an iterative resolution as it completes
+pub type TraceHook = dyn Fn(&TraceStep) + Send + Sync;
+
+/// Why a server was picked to be asked. Within a zone the servers are
+/// asked from the fastest known to the slowest
+#[derive(Debug, Clone, PartialEq)]
+pub enum Choice {
+    /// A root server, nothing closer to the name being known
+    Root,
+    /// A server of the closest zone whose delegation was cached
+    Cached(String),
+    /// A server the previous one referred to for the zone
+    Referral(String),
+    /// Another server of the same zone, after the previous one failed
+    Fallback,
+    /// The same server, for more of the name after the minimised name
+    /// turned out not to be a zone cut
+    Longer,
+    /// The same server, for the full name after it failed a minimised
+    /// query
+    Relaxed,
+}
+
+/// What a query of an iterative resolution led to
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    /// The records asked for: addresses, or host names for reverse lookups
+    Answer(Vec<String>),
+    /// The name is an alias of the given name, resolved next
+    Alias(String),
+    /// The zone was delegated to the given name servers
+    Referral {
+        zone: String,
+        name_servers: Vec<String>,
+    },
+    /// The minimised name asked for is not a zone cut
+    NoCut,
+    /// The query failed, or the name or its addresses don't exist
+    Error(ResolveError),
+}
+
+/// A query sent while resolving a name from the root down
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceStep {
+    /// Nesting of the resolution, 0 for the name asked for and deeper for
+    /// names of name servers resolved on the way
+    pub depth: usize,
+    /// Number of queries sent in this resolution so far
+    pub step: usize,
+    /// Name asked for, shortened when QNAME minimisation is on
+    pub name: String,
+    /// Zone the server was asked as an authority for
+    pub zone: String,
+    /// Address of the server asked
+    pub server: String,
+    /// Why the server was asked
+    pub choice: Choice,
+    /// Time taken by the query, including retries over TCP
+    pub elapsed: Duration,
+    /// What the response, or the lack of one, led to
+    pub outcome: Outcome,
+}
+
+/// Zone name with the root written as `.`
+fn zone_name(zone: &str) -> &str {
+    if zone.is_empty() {
+        "."
+    } else {
+        zone
+    }
+}
+
+impl fmt::Display for Choice {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Choice::Root => write!(f, "root server"),
+            Choice::Cached(zone) => write!(f, "cached server of {}", zone_name(zone)),
+            Choice::Referral(zone) => write!(f, "referred for {}", zone_name(zone)),
+            Choice::Fallback => write!(f, "previous server failed"),
+            Choice::Longer => write!(f, "same server, longer name"),
+            Choice::Relaxed => write!(f, "same server, full name"),
+        }
+    }
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Outcome::Answer(ip_addrs) => write!(f, "answer {}", ip_addrs.join(", ")),
+            Outcome::Alias(target) => write!(f, "alias of {}", target),
+            Outcome::Referral { zone, name_servers } => write!(
+                f,
+                "referral to {} [{}]",
+                zone_name(zone),
+                name_servers.join(", ")
+            ),
+            Outcome::NoCut => write!(f, "no zone cut"),
+            Outcome::Error(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl fmt::Display for TraceStep {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:indent$}{:>2}. {} in {} at {} ({}) in {} ms: {}",
+            "",
+            self.step,
+            self.name,
+            zone_name(&self.zone),
+            self.server,
+            self.choice,
+            self.elapsed.as_millis(),
+            self.outcome,
+            indent = self.depth * 4
+        )
+    }
+}
@@ -0,0 +1,245 @@
+use rand::Rng;
+use spdlog::prelude::*;
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream, UdpSocket};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use crate::client::config::EDNS_PAYLOAD_SIZE;
+use crate::client::tcp;
+use crate::client::BIND_ATTEMPTS;
+
+/// A way of exchanging DNS messages with name servers
+pub trait Transport: Send + Sync + 'static {
+    /// Connection a single query is sent on
+    type Connection: Connection;
+
+    /// Open a connection to a server, giving up when `deadline` passes
+    fn connect(&self, server_addr: SocketAddr, deadline: Instant) -> io::Result<Self::Connection>;
+}
+
+/// Connection to a name server a query is sent on and its responses are
+/// received from
+pub trait Connection {
+    /// Send a message to the server
+    fn send(&mut self, msg: &[u8]) -> io::Result<()>;
+
+    /// Receive the next message from the server, giving up when `deadline`
+    /// passes
+    fn receive(&mut self, deadline: Instant) -> io::Result<Vec<u8>>;
+}
+
+/// Time left until `deadline`, or a timeout error when it has passed
+pub(crate) fn remaining(deadline: Instant) -> io::Result<std::time::Duration> {
+    let remaining = deadline.saturating_duration_since(Instant::now());
+    if remaining.is_zero() {
+        return Err(io::ErrorKind::TimedOut.into());
+    }
+
+    Ok(remaining)
+}
+
+/// Queries over UDP, each from its own socket on a random local port so
+/// responses can't be spoofed by guessing the port
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UdpTransport;
+
+impl Transport for UdpTransport {
+    type Connection = UdpConnection;
+
+    fn connect(&self, server_addr: SocketAddr, _: Instant) -> io::Result<UdpConnection> {
+        let local_ip = match server_addr {
+            SocketAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+            SocketAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        };
+        let socket = UdpTransport::bind(local_ip)?;
+        debug!("Connecting to {}", server_addr);
+        socket.connect(server_addr)?;
+
+        Ok(UdpConnection {
+            socket,
+            server_addr,
+        })
+    }
+}
+
+impl UdpTransport {
+    /// Open a socket on a random local port
+    pub(crate) fn bind(local_ip: IpAddr) -> io::Result<UdpSocket> {
+        for _ in 0..BIND_ATTEMPTS {
+            let port = rand::thread_rng().gen_range(1024..=u16::MAX);
+            if let Ok(socket) = UdpSocket::bind((local_ip, port)) {
+                debug!("Initialize host at address: {}", socket.local_addr()?);
+                return Ok(socket);
+            }
+        }
+
+        // Let the operating system pick an ephemeral port instead
+        UdpSocket::bind((local_ip, 0))
+    }
+}
+
+/// Socket a query is sent on over UDP
+pub struct UdpConnection {
+    socket: UdpSocket,
+    server_addr: SocketAddr,
+}
+
+impl Connection for UdpConnection {
+    fn send(&mut self, msg: &[u8]) -> io::Result<()> {
+        let number_of_bytes = self.socket.send(msg)?;
+        debug!(
+            "Send a {}-byte message to address: {}",
+            number_of_bytes, self.server_addr
+        );
+
+        Ok(())
+    }
+
+    /// Receive the next datagram from the server, discarding datagrams
+    /// from any other source
+    fn receive(&mut self, deadline: Instant) -> io::Result<Vec<u8>> {
+        let mut buffer = [0; EDNS_PAYLOAD_SIZE as usize];
+        loop {
+            self.socket.set_read_timeout(Some(remaining(deadline)?))?;
+            let (number_of_bytes, source) = self.socket.recv_from(&mut buffer)?;
+            debug!("Received: {} bytes from {}", number_of_bytes, source);
+            if source == self.server_addr {
+                return Ok(buffer[..number_of_bytes].to_vec());
+            }
+            debug!("Discard response from unexpected source {}", source);
+        }
+    }
+}
+
+/// Queries over a new TCP connection each, for responses that don't fit in
+/// a UDP datagram
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TcpTransport;
+
+impl Transport for TcpTransport {
+    type Connection = TcpConnection;
+
+    fn connect(&self, server_addr: SocketAddr, deadline: Instant) -> io::Result<TcpConnection> {
+        let stream = TcpStream::connect_timeout(&server_addr, remaining(deadline)?)?;
+        Ok(TcpConnection {
+            stream,
+            server_addr,
+            deadline,
+        })
+    }
+}
+
+/// Stream a query is sent on over TCP
+pub struct TcpConnection {
+    stream: TcpStream,
+    server_addr: SocketAddr,
+    /// Deadline the connection was opened with, bounding sending too
+    deadline: Instant,
+}
+
+impl Connection for TcpConnection {
+    fn send(&mut self, msg: &[u8]) -> io::Result<()> {
+        tcp::write_message_until(&mut self.stream, msg, self.deadline)?;
+        debug!(
+            "Send a {}-byte message to address: {} over TCP",
+            msg.len(),
+            self.server_addr
+        );
+
+        Ok(())
+    }
+
+    fn receive(&mut self, deadline: Instant) -> io::Result<Vec<u8>> {
+        let response = tcp::read_message_until(&mut self.stream, deadline)?;
+        debug!(
+            "Received: {} bytes from {} over TCP",
+            response.len(),
+            self.server_addr
+        );
+
+        Ok(response)
+    }
+}
+
+/// Answers a query sent to a server of a `MemoryTransport`, or gives
+/// nothing to let it time out
+pub type Handler = dyn Fn(&[u8]) -> Option<Vec<u8>> + Send + Sync;
+
+/// Queries sent over a `MemoryTransport` with the server they were sent to
+type QueryLog = Arc<Mutex<Vec<(SocketAddr, Vec<u8>)>>>;
+
+/// Queries answered in memory by handlers standing in for name servers,
+/// without touching the network. Queries to servers without a handler and
+/// queries a handler doesn't answer time out at once. Clones share their
+/// servers and the log of queries
+#[derive(Clone, Default)]
+pub struct MemoryTransport {
+    servers: Arc<Mutex<HashMap<SocketAddr, Arc<Handler>>>>,
+    queries: QueryLog,
+}
+
+impl MemoryTransport {
+    /// Create a transport without servers
+    pub fn new() -> MemoryTransport {
+        MemoryTransport::default()
+    }
+
+    /// Answer the queries sent to `server_addr` with `handler`
+    pub fn add_server<F>(&self, server_addr: SocketAddr, handler: F)
+    where
+        F: Fn(&[u8]) -> Option<Vec<u8>> + Send + Sync + 'static,
+    {
+        self.servers
+            .lock()
+            .unwrap()
+            .insert(server_addr, Arc::new(handler));
+    }
+
+    /// Queries sent so far with the server they were sent to, in order
+    pub fn queries(&self) -> Vec<(SocketAddr, Vec<u8>)> {
+        self.queries.lock().unwrap().clone()
+    }
+}
+
+impl Transport for MemoryTransport {
+    type Connection = MemoryConnection;
+
+    fn connect(&self, server_addr: SocketAddr, _: Instant) -> io::Result<MemoryConnection> {
+        Ok(MemoryConnection {
+            server_addr,
+            handler: self.servers.lock().unwrap().get(&server_addr).cloned(),
+            queries: self.queries.clone(),
+            responses: VecDeque::new(),
+        })
+    }
+}
+
+/// Connection to a server of a `MemoryTransport`
+pub struct MemoryConnection {
+    server_addr: SocketAddr,
+    handler: Option<Arc<Handler>>,
+    queries: QueryLog,
+    responses: VecDeque<Vec<u8>>,
+}
+
+impl Connection for MemoryConnection {
+    fn send(&mut self, msg: &[u8]) -> io::Result<()> {
+        self.queries
+            .lock()
+            .unwrap()
+            .push((self.server_addr, msg.to_vec()));
+        if let Some(response) = self.handler.as_ref().and_then(|handler| handler(msg)) {
+            self.responses.push_back(response);
+        }
+
+        Ok(())
+    }
+
+    fn receive(&mut self, _: Instant) -> io::Result<Vec<u8>> {
+        self.responses
+            .pop_front()
+            .ok_or_else(|| io::ErrorKind::TimedOut.into())
+    }
+}
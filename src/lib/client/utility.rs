@@ -1,8 +1,14 @@
 use std::error::Error;
-use std::io::Cursor;
+use std::fs;
+use std::io::{self, Cursor};
+use std::path::Path;
+use std::process;
 
 use byteorder::{BigEndian, ReadBytesExt};
 
+/// Maximum number of compression pointers followed while reading a name
+const MAX_POINTERS: usize = 64;
+
 pub fn to_u16(bytes: &[u8]) -> u16 {
     let mut rdr = Cursor::new(bytes);
     rdr.read_u16::<BigEndian>().unwrap()
@@ -15,10 +21,9 @@ pub fn to_u32(bytes: &[u8]) -> u32 {
 
 pub fn get_bits_range(number: u16, start: u32, end: u32) -> u16 {
     let range = end - start;
-    let base: u16 = 2;
-    let mask = base.pow(range - 1);
+    let mask = ((1u32 << range) - 1) as u16;
 
-    (number >> start) | mask
+    (number >> start) & mask
 }
 
 pub fn find_first_null(bytes: &[u8]) -> Result<usize, Box<dyn Error>> {
@@ -30,6 +35,105 @@ pub fn find_first_null(bytes: &[u8]) -> Result<usize, Box<dyn Error>> {
     Ok(null_pos)
 }
 
+/// Get a sub slice of a message, failing instead of panicking when the
+/// message is too short
+pub fn get_slice(message: &[u8], start: usize, end: usize) -> Result<&[u8], Box<dyn Error>> {
+    message.get(start..end).ok_or_else(|| {
+        format!(
+            "Message too short: need {} bytes, got {}",
+            end,
+            message.len()
+        )
+        .into()
+    })
+}
+
+/// Read a possibly compressed domain name starting at `start`. Return the
+/// position right after the name in the message and the name in its
+/// uncompressed wire format
+pub fn read_name(message: &[u8], start: usize) -> Result<(usize, Vec<u8>), Box<dyn Error>> {
+    let mut name = vec![];
+    let mut pos = start;
+    let mut end = None;
+    let mut pointers = 0;
+    loop {
+        let len = *message.get(pos).ok_or("Name runs past end of message")?;
+        if len & 0xC0 == 0xC0 {
+            let offset = (to_u16(get_slice(message, pos, pos + 2)?) & 0x3FFF) as usize;
+            if end.is_none() {
+                end = Some(pos + 2);
+            }
+            pointers += 1;
+            if pointers > MAX_POINTERS {
+                return Err("Too many compression pointers in name".into());
+            }
+            pos = offset;
+        } else if len & 0xC0 != 0 {
+            return Err(format!("Invalid label type 0x{:02x}", len).into());
+        } else if len == 0 {
+            name.push(0);
+            break;
+        } else {
+            let label = get_slice(message, pos, pos + 1 + len as usize)?;
+            name.extend_from_slice(label);
+            if name.len() > 255 {
+                return Err("Name exceeds 255 octets".into());
+            }
+            pos += 1 + len as usize;
+        }
+    }
+
+    Ok((end.unwrap_or(pos + 1), name))
+}
+
+/// Write `body` to a file after a header of `magic` and the format
+/// `version`. The file is replaced atomically so a reader never sees half
+/// of it. It is first written to a temporary file of its own next to it,
+/// so files of the same directory and concurrent writers don't collide
+pub fn write_versioned(path: &Path, magic: &[u8; 4], version: u8, body: &[u8]) -> io::Result<()> {
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Path has no file name"))?;
+    let mut temp_name = file_name.to_os_string();
+    temp_name.push(format!(
+        ".{}.{:08x}.tmp",
+        process::id(),
+        rand::random::<u32>()
+    ));
+    let temp_path = path.with_file_name(temp_name);
+
+    let mut bytes = magic.to_vec();
+    bytes.push(version);
+    bytes.extend_from_slice(body);
+    let result = fs::write(&temp_path, bytes).and_then(|()| fs::rename(&temp_path, path));
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+
+    result
+}
+
+/// Read the body of a file written by `write_versioned` with the same
+/// `magic` and `version`
+pub fn read_versioned(
+    path: &Path,
+    magic: &[u8; 4],
+    version: u8,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut bytes = fs::read(path)?;
+    if bytes.len() < 5 || bytes[..4] != magic[..] || bytes[4] != version {
+        return Err(format!(
+            "{} is not a {} file of version {}",
+            path.display(),
+            String::from_utf8_lossy(magic),
+            version
+        )
+        .into());
+    }
+
+    Ok(bytes.split_off(5))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -39,4 +143,50 @@ mod tests {
         let number = 0b11001100;
         assert_eq!(get_bits_range(number, 4, 8), 0b1100);
     }
+
+    #[test]
+    fn single_bit_range() {
+        let number = 0b1000_0000_0000_0000;
+        assert_eq!(get_bits_range(number, 15, 16), 1);
+        assert_eq!(get_bits_range(number, 0, 4), 0);
+    }
+
+    #[test]
+    fn read_compressed_name() {
+        let message = vec![3, b'c', b'o', b'm', 0, 3, b'n', b's', b'1', 0xC0, 0x00];
+        let (end, name) = read_name(&message, 5).unwrap();
+        assert_eq!(end, 11);
+        assert_eq!(name, vec![3, b'n', b's', b'1', 3, b'c', b'o', b'm', 0]);
+    }
+
+    #[test]
+    fn concurrent_versioned_writes() {
+        let dir = std::env::temp_dir().join(format!("{}-versioned", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("cache.bin");
+        let writers = (0..8u8)
+            .map(|i| {
+                let path = path.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..20 {
+                        write_versioned(&path, b"TEST", 1, &[i]).unwrap();
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        write_versioned(&dir.join("cache.json"), b"TEST", 1, &[]).unwrap();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        assert_eq!(read_versioned(&path, b"TEST", 1).unwrap().len(), 1);
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 2);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn read_name_pointer_loop() {
+        let message = vec![0xC0, 0x00];
+        assert!(read_name(&message, 0).is_err());
+    }
 }
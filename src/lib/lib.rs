@@ -1 +1,3 @@
-pub mod client;
\ No newline at end of file
+pub mod client;
+#[cfg(feature = "test-util")]
+pub mod test_util;
@@ -0,0 +1,448 @@
+use spdlog::prelude::*;
+use std::collections::HashSet;
+use std::io::{self, ErrorKind};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener, TcpStream, UdpSocket};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::client::config::{ResolverConfig, ResolverMode, EDNS_PAYLOAD_SIZE};
+use crate::client::message::DnsMessage;
+use crate::client::root_hints::RootHints;
+use crate::client::rr::{self, ResourceRecord};
+use crate::client::tcp;
+use crate::client::{BIND_ATTEMPTS, MAX_CNAMES, NAME_ERROR, REFUSED};
+
+/// TTL of the records built here
+const TTL: u32 = 300;
+/// Negative TTL in the SOA of the zones
+const NEGATIVE_TTL: u32 = 60;
+/// How often the server threads check whether they should stop
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+/// Last octet of the loopback address of the first server of a hierarchy
+const FIRST_HOST: u8 = 10;
+
+/// Lower case name without trailing dot, the root being the empty string
+fn normalize(name: &str) -> String {
+    name.trim_end_matches('.').to_ascii_lowercase()
+}
+
+/// Name of the name server of a zone in a hierarchy
+fn ns_name(origin: &str) -> String {
+    match origin {
+        "" => String::from("a.root-servers.test"),
+        origin => format!("ns1.{}", origin),
+    }
+}
+
+/// Records served by a mock name server for a zone, with names it answers
+/// badly on purpose
+#[derive(Debug, Clone)]
+pub struct Zone {
+    origin: String,
+    records: Vec<ResourceRecord>,
+    truncated: HashSet<String>,
+    dropped: HashSet<String>,
+}
+
+impl Zone {
+    /// Create a zone holding only the SOA record at its apex. The origin
+    /// of the root zone is the empty string
+    pub fn new(origin: &str) -> Zone {
+        let origin = normalize(origin);
+        let mut rdata = DnsMessage::encode_address(&ns_name(&origin));
+        rdata.extend(DnsMessage::encode_address(&format!(
+            "hostmaster.{}",
+            origin
+        )));
+        for field in [1, 1800, 900, 604800, NEGATIVE_TTL] {
+            rdata.extend(u32::to_be_bytes(field));
+        }
+        Zone {
+            records: vec![ResourceRecord::new(&origin, rr::SOA, TTL, rdata)],
+            origin,
+            truncated: HashSet::new(),
+            dropped: HashSet::new(),
+        }
+    }
+
+    /// Name of the apex of the zone
+    pub fn origin(&self) -> &str {
+        &self.origin
+    }
+
+    /// Add a record to the zone
+    pub fn with_record(mut self, record: ResourceRecord) -> Zone {
+        self.records.push(record);
+        self
+    }
+
+    /// Add a host address
+    pub fn a(self, name: &str, ip_addr: Ipv4Addr) -> Zone {
+        self.with_record(ResourceRecord::new(
+            name,
+            rr::A,
+            TTL,
+            ip_addr.octets().to_vec(),
+        ))
+    }
+
+    /// Add an alias of `name` for `target`
+    pub fn cname(self, name: &str, target: &str) -> Zone {
+        self.with_record(ResourceRecord::new(
+            name,
+            rr::CNAME,
+            TTL,
+            DnsMessage::encode_address(target),
+        ))
+    }
+
+    /// Add a name server of `name`, which delegates it when it is below the
+    /// apex
+    pub fn ns(self, name: &str, ns_name: &str) -> Zone {
+        self.with_record(ResourceRecord::new(
+            name,
+            rr::NS,
+            TTL,
+            DnsMessage::encode_address(ns_name),
+        ))
+    }
+
+    /// Answer queries for `name` over UDP with an empty truncated response,
+    /// so they have to be asked again over TCP
+    pub fn truncate(mut self, name: &str) -> Zone {
+        self.truncated.insert(normalize(name));
+        self
+    }
+
+    /// Never answer queries for `name`, so they time out
+    pub fn drop_queries(mut self, name: &str) -> Zone {
+        self.dropped.insert(normalize(name));
+        self
+    }
+
+    /// Response of an authoritative server of the zone to a query, or
+    /// nothing when it is dropped. Names below a delegation are referred
+    /// to its name servers, with glue from the zone
+    pub fn respond(&self, query: &[u8], over_udp: bool) -> Option<Vec<u8>> {
+        let mut dns_response = DnsMessage::parse(query).ok()?;
+        let name = normalize(&DnsMessage::decode_address(&dns_response.question.q_name));
+        let q_type = dns_response.question.q_type;
+        if self.dropped.contains(&name) {
+            debug!("Drop query for {}", name);
+            return None;
+        }
+
+        dns_response.header.flags.qr = 1;
+        dns_response.header.flags.ra = 0;
+        dns_response.additionals = vec![];
+        if over_udp && self.truncated.contains(&name) {
+            dns_response.header.flags.tc = 1;
+        } else if !DnsMessage::is_subdomain(&name, &self.origin) {
+            dns_response.header.flags.r_code = REFUSED;
+        } else if let Some(cut) = self.delegation(&name) {
+            dns_response.authorities = self.rrset(&cut, rr::NS);
+            dns_response.additionals = dns_response
+                .authorities
+                .iter()
+                .flat_map(|ns| self.rrset(&ns.get_domain_name(), rr::A))
+                .collect();
+        } else {
+            dns_response.header.flags.aa = 1;
+            dns_response.answers = self.answers(&name, q_type);
+            if dns_response.answers.is_empty() {
+                if !self.exists(&name) {
+                    dns_response.header.flags.r_code = NAME_ERROR;
+                }
+                dns_response.authorities = self.rrset(&self.origin, rr::SOA);
+            }
+        }
+        dns_response.header.an_cnt = dns_response.answers.len() as u16;
+        dns_response.header.ns_cnt = dns_response.authorities.len() as u16;
+        dns_response.header.ar_cnt = dns_response.additionals.len() as u16;
+
+        Some(dns_response.to_be_bytes())
+    }
+
+    /// Records of the zone owned by `name` of `rr_type`
+    fn rrset(&self, name: &str, rr_type: u16) -> Vec<ResourceRecord> {
+        self.records
+            .iter()
+            .filter(|rr| {
+                rr.rr_type == rr_type && normalize(&DnsMessage::decode_address(&rr.rr_name)) == name
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// Highest delegation below the apex that `name` falls under
+    fn delegation(&self, name: &str) -> Option<String> {
+        self.records
+            .iter()
+            .filter(|rr| rr.rr_type == rr::NS)
+            .map(|rr| normalize(&DnsMessage::decode_address(&rr.rr_name)))
+            .filter(|owner| *owner != self.origin && DnsMessage::is_subdomain(name, owner))
+            .min_by_key(|owner| owner.len())
+    }
+
+    /// Records of `rr_type` for `name`, following aliases within the zone
+    fn answers(&self, name: &str, q_type: u16) -> Vec<ResourceRecord> {
+        let mut answers = vec![];
+        let mut name = name.to_string();
+        for _ in 0..MAX_CNAMES {
+            let rrset = self.rrset(&name, q_type);
+            if !rrset.is_empty() {
+                answers.extend(rrset);
+                break;
+            }
+            match self.rrset(&name, rr::CNAME).into_iter().next() {
+                Some(cname) if q_type != rr::CNAME => {
+                    name = normalize(&cname.get_domain_name());
+                    answers.push(cname);
+                    if !DnsMessage::is_subdomain(&name, &self.origin) {
+                        break;
+                    }
+                }
+                _ => break,
+            }
+        }
+
+        answers
+    }
+
+    /// Whether `name` owns records or has names below it
+    fn exists(&self, name: &str) -> bool {
+        self.records.iter().any(|rr| {
+            DnsMessage::is_subdomain(&normalize(&DnsMessage::decode_address(&rr.rr_name)), name)
+        })
+    }
+}
+
+/// A name server answering from a zone over UDP and TCP on one address,
+/// until it is dropped
+pub struct MockServer {
+    addr: SocketAddr,
+    queries: Arc<AtomicUsize>,
+    stop: Arc<AtomicBool>,
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl MockServer {
+    /// Serve a zone on `addr`. Its port may be 0 to let the operating
+    /// system pick one
+    pub fn start(zone: Zone, addr: SocketAddr) -> io::Result<MockServer> {
+        let udp = UdpSocket::bind(addr)?;
+        let addr = udp.local_addr()?;
+        let tcp = TcpListener::bind(addr)?;
+        udp.set_read_timeout(Some(POLL_INTERVAL))?;
+        tcp.set_nonblocking(true)?;
+
+        let zone = Arc::new(zone);
+        let queries = Arc::new(AtomicUsize::new(0));
+        let stop = Arc::new(AtomicBool::new(false));
+        let threads = vec![
+            MockServer::serve_udp(udp, zone.clone(), queries.clone(), stop.clone()),
+            MockServer::serve_tcp(tcp, zone, queries.clone(), stop.clone()),
+        ];
+        debug!("Mock server listening on {}", addr);
+
+        Ok(MockServer {
+            addr,
+            queries,
+            stop,
+            threads,
+        })
+    }
+
+    /// Address the server listens on over UDP and TCP
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Number of queries received over UDP and TCP
+    pub fn queries(&self) -> usize {
+        self.queries.load(Ordering::SeqCst)
+    }
+
+    /// Answer the datagrams received on a socket until asked to stop
+    fn serve_udp(
+        socket: UdpSocket,
+        zone: Arc<Zone>,
+        queries: Arc<AtomicUsize>,
+        stop: Arc<AtomicBool>,
+    ) -> JoinHandle<()> {
+        thread::spawn(move || {
+            let mut buffer = [0; EDNS_PAYLOAD_SIZE as usize];
+            while !stop.load(Ordering::SeqCst) {
+                let (len, source) = match socket.recv_from(&mut buffer) {
+                    Ok(received) => received,
+                    Err(_) => continue,
+                };
+                queries.fetch_add(1, Ordering::SeqCst);
+                if let Some(response) = zone.respond(&buffer[..len], true) {
+                    let _ = socket.send_to(&response, source);
+                }
+            }
+        })
+    }
+
+    /// Answer the connections accepted on a listener until asked to stop
+    fn serve_tcp(
+        listener: TcpListener,
+        zone: Arc<Zone>,
+        queries: Arc<AtomicUsize>,
+        stop: Arc<AtomicBool>,
+    ) -> JoinHandle<()> {
+        thread::spawn(move || {
+            while !stop.load(Ordering::SeqCst) {
+                match listener.accept() {
+                    Ok((stream, _)) => {
+                        if let Err(err) = MockServer::answer_tcp(stream, &zone, &queries) {
+                            debug!("Mock TCP exchange failed: {}", err);
+                        }
+                    }
+                    Err(err) if err.kind() == ErrorKind::WouldBlock => thread::sleep(POLL_INTERVAL),
+                    Err(_) => continue,
+                }
+            }
+        })
+    }
+
+    /// Answer the query read from a TCP connection
+    fn answer_tcp(mut stream: TcpStream, zone: &Zone, queries: &AtomicUsize) -> io::Result<()> {
+        stream.set_nonblocking(false)?;
+        stream.set_read_timeout(Some(Duration::from_secs(1)))?;
+        let query = tcp::read_message(&mut stream)?;
+        queries.fetch_add(1, Ordering::SeqCst);
+        match zone.respond(&query, false) {
+            Some(response) => tcp::write_message(&mut stream, &response),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Mock name servers for a tree of zones, each on its own loopback address
+/// and all on the same port. Every zone is delegated from the closest
+/// enclosing zone, with its name server `ns1.<zone>` and glue added to
+/// both. A root zone is added when none is given.
+///
+/// Glue carries addresses without ports, so the servers listen on 127.0.0.10
+/// and up rather than on ports of 127.0.0.1. Only Linux routes the whole of
+/// 127.0.0.0/8 to loopback; elsewhere these addresses have to be added as
+/// loopback aliases first, like `ifconfig lo0 alias 127.0.0.10` on macOS
+pub struct MockHierarchy {
+    servers: Vec<MockServer>,
+    origins: Vec<String>,
+    port: u16,
+}
+
+impl MockHierarchy {
+    /// Start a server for each zone
+    pub fn start(zones: Vec<Zone>) -> io::Result<MockHierarchy> {
+        let mut zones = zones;
+        if !zones.iter().any(|zone| zone.origin.is_empty()) {
+            zones.push(Zone::new(""));
+        }
+        zones.sort_by_key(|zone| zone.origin.split('.').filter(|l| !l.is_empty()).count());
+        if zones.len() > (u8::MAX - FIRST_HOST) as usize {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "Too many zones for a mock hierarchy",
+            ));
+        }
+
+        let ip_addrs = (0..zones.len())
+            .map(|i| Ipv4Addr::new(127, 0, 0, FIRST_HOST + i as u8))
+            .collect::<Vec<_>>();
+        for i in 0..zones.len() {
+            let origin = zones[i].origin.clone();
+            let ns_name = ns_name(&origin);
+            zones[i] = zones[i]
+                .clone()
+                .ns(&origin, &ns_name)
+                .a(&ns_name, ip_addrs[i]);
+            let parent = (0..i)
+                .rev()
+                .find(|&j| DnsMessage::is_subdomain(&origin, &zones[j].origin));
+            if let Some(j) = parent {
+                zones[j] = zones[j]
+                    .clone()
+                    .ns(&origin, &ns_name)
+                    .a(&ns_name, ip_addrs[i]);
+            }
+        }
+
+        let mut last_error = None;
+        for _ in 0..BIND_ATTEMPTS {
+            match MockHierarchy::bind(&zones, &ip_addrs) {
+                Ok(hierarchy) => return Ok(hierarchy),
+                Err(err) => last_error = Some(err),
+            }
+        }
+
+        Err(last_error.unwrap())
+    }
+
+    /// Start the servers on a port free on all of their addresses
+    fn bind(zones: &[Zone], ip_addrs: &[Ipv4Addr]) -> io::Result<MockHierarchy> {
+        let mut servers: Vec<MockServer> = vec![];
+        for (zone, ip_addr) in zones.iter().zip(ip_addrs) {
+            let port = servers.first().map_or(0, |server| server.addr().port());
+            let addr = SocketAddr::new(IpAddr::V4(*ip_addr), port);
+            servers.push(MockServer::start(zone.clone(), addr)?);
+        }
+
+        Ok(MockHierarchy {
+            port: servers[0].addr().port(),
+            origins: zones.iter().map(|zone| zone.origin.clone()).collect(),
+            servers,
+        })
+    }
+
+    /// Port all the servers listen on
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// Server of a zone
+    pub fn server(&self, origin: &str) -> Option<&MockServer> {
+        let origin = normalize(origin);
+        self.origins
+            .iter()
+            .position(|o| *o == origin)
+            .map(|i| &self.servers[i])
+    }
+
+    /// Root hints naming the server of the root zone
+    pub fn root_hints(&self) -> RootHints {
+        let root = self.server("").unwrap();
+        RootHints::from_addresses(&[root.addr().ip().to_string()])
+    }
+
+    /// Settings resolving iteratively from the mock root, without priming,
+    /// hosts file or long timeouts
+    pub fn config(&self) -> ResolverConfig {
+        ResolverConfig {
+            mode: ResolverMode::Iterative,
+            root_hints: self.root_hints(),
+            port: self.port,
+            prime_roots: false,
+            hosts_path: None,
+            query_timeout: Duration::from_millis(200),
+            max_query_timeout: Duration::from_millis(400),
+            retries: 0,
+            deadline: Duration::from_secs(3),
+            ..ResolverConfig::default()
+        }
+    }
+}
@@ -1,17 +1,120 @@
 use std::fmt::Debug;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::process;
+
 use clap::Parser;
+use spdlog::prelude::*;
 
 use dns_resolver::client;
+use dns_resolver::client::config::{ResolverConfig, ResolverMode};
+use dns_resolver::client::hosts::HOSTS_PATH;
+use dns_resolver::client::resolv_conf::{ResolvConf, RESOLV_CONF_PATH};
+use dns_resolver::client::root_hints::RootHints;
 
 #[derive(Parser, Debug)]
 struct Options {
     /// Host name that is needed to resolve
-    host: String,
+    #[arg(required_unless_present = "reverse")]
+    host: Option<String>,
+    /// Look up the host names of an address instead of a host name
+    #[arg(short = 'x', value_name = "ADDRESS", conflicts_with = "host")]
+    reverse: Option<IpAddr>,
+    /// Recursive name server to ask instead of resolving from the root,
+    /// can be given several times
+    #[arg(short, long = "server", value_name = "ADDRESS")]
+    servers: Vec<String>,
+    /// Ask the recursive name servers of the system resolver, configured
+    /// in /etc/resolv.conf
+    #[arg(long)]
+    stub: bool,
+    /// Hosts file consulted before asking name servers
+    #[arg(long, value_name = "PATH", default_value = HOSTS_PATH)]
+    hosts: PathBuf,
+    /// Root hints file in the named.root format to start resolution from
+    /// instead of the compiled-in root servers
+    #[arg(long, value_name = "PATH")]
+    root_hints: Option<PathBuf>,
+    /// Don't refresh the root servers with a priming query
+    #[arg(long)]
+    no_prime: bool,
+    /// File keeping cached records between runs
+    #[arg(long, value_name = "PATH")]
+    cache_file: Option<PathBuf>,
+    /// Print every query sent while resolving from the root down, with the
+    /// server asked, why it was chosen, how long it took and its outcome
+    #[arg(long)]
+    trace: bool,
 }
 
 fn main() {
     spdlog::default_logger().set_level_filter(spdlog::LevelFilter::Equal(spdlog::Level::Error));
     let options = Options::parse();
-    let dns_client = client::DnsClient::new();
-    dns_client.ask(&options.host);
-}
\ No newline at end of file
+    let mut config = if options.stub {
+        match ResolvConf::read_system() {
+            Ok(resolv_conf) => resolv_conf.to_config(),
+            Err(err) => {
+                error!("Can't read {}: {}", RESOLV_CONF_PATH, err);
+                process::exit(1);
+            }
+        }
+    } else {
+        ResolverConfig::default()
+    };
+    config.hosts_path = Some(options.hosts.clone());
+    if let Some(path) = &options.root_hints {
+        match RootHints::read(path) {
+            Ok(root_hints) => config.root_hints = root_hints,
+            Err(err) => {
+                error!("Can't read root hints {}: {}", path.display(), err);
+                process::exit(1);
+            }
+        }
+    }
+    config.prime_roots = !options.no_prime;
+    config.cache_path = options.cache_file.clone();
+    if !options.servers.is_empty() {
+        config.mode = ResolverMode::Stub(options.servers.clone());
+    }
+    let mut dns_client = client::DnsClient::with_config(config);
+    if options.trace {
+        dns_client = dns_client.with_trace(|step| println!("{}", step));
+    }
+    if let Some(ip) = options.reverse {
+        let result = dns_client.reverse(ip);
+        save_cache(&dns_client);
+        match result {
+            Ok(host_names) => {
+                println!("Address: {}\n", ip);
+                println!("Host Names: \n");
+                println!("[\n\t{}\n]", host_names.join("\n\t"));
+            }
+            Err(err) => {
+                error!("Can't look up {}: {}", ip, err);
+                process::exit(err.exit_code());
+            }
+        }
+        return;
+    }
+
+    let host = options.host.unwrap_or_default();
+    let result = dns_client.ask(&host);
+    save_cache(&dns_client);
+    match result {
+        Ok(result) => {
+            println!("Name: {}\n", result.name);
+            println!("IP Address: \n");
+            println!("[\n\t{}\n]", result.ip_addrs.join("\n\t"));
+        }
+        Err(err) => {
+            error!("Can't resolve {}: {}", host, err);
+            process::exit(err.exit_code());
+        }
+    }
+}
+
+fn save_cache(dns_client: &client::DnsClient) {
+    if let Err(err) = dns_client.save_cache() {
+        error!("Can't save cache: {}", err);
+    }
+}
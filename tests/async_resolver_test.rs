@@ -0,0 +1,265 @@
+#![cfg(feature = "tokio")]
+
+use std::collections::{HashMap, HashSet};
+use std::net::{Ipv6Addr, SocketAddr, UdpSocket};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use dns_resolver::client::async_resolver::AsyncResolver;
+use dns_resolver::client::config::{ResolverConfig, ResolverMode};
+use dns_resolver::client::error::ResolveError;
+use dns_resolver::client::message::DnsMessage;
+use dns_resolver::client::rr::{self, ResourceRecord};
+
+mod common;
+use common::{question, record, recursive, recursive_negative};
+
+/// Records of a recursive server holding `www.example.com` at 192.0.2.1
+/// and 2001:db8::1, an alias `alias.example.com` of it, `v6.example.com`
+/// at 2001:db8::6 only, and `hostN.example.com` at 192.0.2.N
+fn answers(name: &str) -> Vec<ResourceRecord> {
+    let www = vec![
+        record("www.example.com", rr::A, 300, vec![192, 0, 2, 1]),
+        record(
+            "www.example.com",
+            rr::AAAA,
+            300,
+            "2001:db8::1".parse::<Ipv6Addr>().unwrap().octets().to_vec(),
+        ),
+    ];
+    match name {
+        "www.example.com" => www,
+        "v6.example.com" => vec![record(
+            name,
+            rr::AAAA,
+            300,
+            "2001:db8::6".parse::<Ipv6Addr>().unwrap().octets().to_vec(),
+        )],
+        "alias.example.com" => [
+            vec![record(
+                "alias.example.com",
+                rr::CNAME,
+                300,
+                DnsMessage::encode_address("www.example.com"),
+            )],
+            www,
+        ]
+        .concat(),
+        _ => match name
+            .strip_prefix("host")
+            .and_then(|host| host.strip_suffix(".example.com"))
+            .and_then(|n| n.parse::<u8>().ok())
+        {
+            Some(n) => vec![record(name, rr::A, 300, vec![192, 0, 2, n])],
+            None => vec![],
+        },
+    }
+}
+
+/// Response of the recursive server of `answers` to `query`: the records
+/// of the type asked for and the aliases leading to them, no data when the
+/// name has none of the type, or NXDOMAIN when it has no records at all
+fn answer(query: &[u8]) -> Vec<u8> {
+    let (name, rr_type) = question(query);
+    let records = answers(&name);
+    if records.is_empty() {
+        return recursive(query, records);
+    }
+    let records = records
+        .into_iter()
+        .filter(|rr| rr.rr_type == rr_type || rr.rr_type == rr::CNAME)
+        .collect::<Vec<_>>();
+    match records.is_empty() {
+        true => recursive_negative(query, 0, "example.com"),
+        false => recursive(query, records),
+    }
+}
+
+/// Start a server on a loopback port that waits for `batch` queries, then
+/// answers them in reverse order, `batches` times over. It gives the
+/// source address of each query
+fn start_batch_server(
+    batch: usize,
+    batches: usize,
+) -> (String, thread::JoinHandle<Vec<SocketAddr>>) {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let addr = socket.local_addr().unwrap().to_string();
+    let handle = thread::spawn(move || {
+        let mut sources = vec![];
+        for _ in 0..batches {
+            let mut queries = vec![];
+            while queries.len() < batch {
+                let mut buffer = [0; 512];
+                let (len, source) = socket.recv_from(&mut buffer).unwrap();
+                queries.push((buffer[..len].to_vec(), source));
+            }
+            for (query, source) in queries.iter().rev() {
+                socket.send_to(&answer(query), source).unwrap();
+            }
+            sources.extend(queries.into_iter().map(|(_, source)| source));
+        }
+        sources
+    });
+    (addr, handle)
+}
+
+fn stub_config(servers: Vec<String>) -> ResolverConfig {
+    ResolverConfig {
+        mode: ResolverMode::Stub(servers),
+        query_timeout: Duration::from_millis(500),
+        retries: 0,
+        deadline: Duration::from_secs(2),
+        hosts_path: None,
+        ..ResolverConfig::default()
+    }
+}
+
+#[tokio::test]
+async fn lookup_ip_through_recursive_server() {
+    let (server, _) = start_batch_server(1, 2);
+    let resolver = AsyncResolver::with_config(stub_config(vec![server]));
+
+    let result = resolver.lookup_ip("www.example.com").await.unwrap();
+    assert_eq!(result.ip_addrs, vec!["192.0.2.1", "2001:db8::1"]);
+    assert_eq!(result.ttl, 300);
+    assert!(!result.stale);
+}
+
+#[tokio::test]
+async fn lookup_ip_of_ipv6_only_host() {
+    let (server, _) = start_batch_server(1, 2);
+    let resolver = AsyncResolver::with_config(stub_config(vec![server]));
+
+    let result = resolver.lookup_ip("v6.example.com").await.unwrap();
+    assert_eq!(result.ip_addrs, vec!["2001:db8::6"]);
+}
+
+#[tokio::test]
+async fn lookup_follows_alias() {
+    let (server, _) = start_batch_server(1, 1);
+    let resolver = AsyncResolver::with_config(stub_config(vec![server]));
+
+    let records = resolver.lookup("alias.example.com", rr::A).await.unwrap();
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0].get_ip_addr(), "192.0.2.1");
+    assert_eq!(
+        resolver
+            .lookup("alias.example.com", rr::CNAME)
+            .await
+            .unwrap()[0]
+            .get_domain_name(),
+        "www.example.com"
+    );
+}
+
+#[tokio::test]
+async fn report_missing_name() {
+    let (server, _) = start_batch_server(1, 2);
+    let resolver = AsyncResolver::with_config(stub_config(vec![server]));
+
+    match resolver.lookup_ip("missing.example.com").await {
+        Err(ResolveError::NxDomain { name, .. }) => assert_eq!(name, "missing.example.com"),
+        other => panic!("Expected a non-existent domain, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn prefetch_popular_name_before_expiry() {
+    let (server, handle) = start_batch_server(1, 2);
+    let resolver = AsyncResolver::with_config(ResolverConfig {
+        cache_max_ttl: Duration::from_millis(400),
+        prefetch: true,
+        prefetch_ttl_fraction: 0.5,
+        prefetch_min_hits: 1,
+        ..stub_config(vec![server])
+    });
+    resolver.lookup("www.example.com", rr::A).await.unwrap();
+    resolver.lookup("www.example.com", rr::A).await.unwrap();
+
+    tokio::time::sleep(Duration::from_millis(250)).await;
+    resolver.lookup("www.example.com", rr::A).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert!(handle.is_finished());
+    assert_eq!(resolver.cache().stats().prefetches, 1);
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let records = resolver.lookup("www.example.com", rr::A).await.unwrap();
+    assert_eq!(records[0].get_ip_addr(), "192.0.2.1");
+    assert_eq!(handle.join().unwrap().len(), 2);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn multiplex_concurrent_lookups_on_few_sockets() {
+    let (server, sources) = start_batch_server(32, 1);
+    let resolver = Arc::new(AsyncResolver::with_config(stub_config(vec![server])));
+
+    let lookups = (1..=16)
+        .map(|n| {
+            let resolver = resolver.clone();
+            tokio::spawn(async move {
+                let result = resolver
+                    .lookup_ip(&format!("host{}.example.com", n))
+                    .await
+                    .unwrap();
+                (n, result.ip_addrs)
+            })
+        })
+        .collect::<Vec<_>>();
+    for lookup in lookups {
+        let (n, ip_addrs) = lookup.await.unwrap();
+        assert_eq!(ip_addrs, vec![format!("192.0.2.{}", n)]);
+    }
+    let sources = sources.join().unwrap().into_iter().collect::<HashSet<_>>();
+    assert!(sources.len() > 1, "All queries came from {:?}", sources);
+    assert!(sources.len() < 32, "No socket was shared");
+}
+
+#[tokio::test]
+async fn move_to_new_source_ports() {
+    let (server, sources) = start_batch_server(1, 128);
+    let resolver = AsyncResolver::with_config(stub_config(vec![server]));
+
+    for n in 1..=64 {
+        let result = resolver
+            .lookup_ip(&format!("host{}.example.com", n))
+            .await
+            .unwrap();
+        assert_eq!(result.ip_addrs, vec![format!("192.0.2.{}", n)]);
+    }
+    // A socket is replaced after carrying 8 queries
+    let mut counts = HashMap::new();
+    for source in sources.join().unwrap() {
+        *counts.entry(source).or_insert(0) += 1;
+    }
+    assert!(counts.values().all(|&count| count <= 8), "{:?}", counts);
+}
+
+// The mock servers listen on loopback addresses only Linux has by default
+#[cfg(all(feature = "test-util", target_os = "linux"))]
+#[tokio::test]
+async fn resolve_iteratively_from_mock_root() {
+    use dns_resolver::test_util::{MockHierarchy, Zone};
+    use std::net::Ipv4Addr;
+
+    let hierarchy = MockHierarchy::start(vec![
+        Zone::new("example.com").cname("www.example.com", "edge.cdn.net"),
+        Zone::new("cdn.net").a("edge.cdn.net", Ipv4Addr::new(192, 0, 2, 2)),
+    ])
+    .unwrap();
+    let resolver = AsyncResolver::with_config(hierarchy.config());
+
+    let result = resolver.lookup_ip("www.example.com").await.unwrap();
+    assert_eq!(result.ip_addrs, vec!["192.0.2.2"]);
+    // Both address types are asked for
+    assert_eq!(hierarchy.server("cdn.net").unwrap().queries(), 2);
+    // Later lookups start from the delegations learnt on the way
+    let root_queries = hierarchy.server("").unwrap().queries();
+    match resolver.lookup_ip("missing.example.com").await {
+        Err(ResolveError::NxDomain { soa, .. }) => assert_eq!(soa.unwrap().zone, "example.com"),
+        other => panic!("Expected a non-existent domain, got {:?}", other),
+    }
+
+    resolver.lookup_ip("other.example.com").await.unwrap_err();
+    assert_eq!(hierarchy.server("").unwrap().queries(), root_queries);
+}
@@ -0,0 +1,462 @@
+mod common;
+
+use std::thread;
+use std::time::Duration;
+
+use dns_resolver::client::cache::{Cache, CacheKey, NegativeKind};
+use dns_resolver::client::message::DnsMessage;
+use dns_resolver::client::rr::{self, Soa};
+
+use common::record;
+
+fn default_cache() -> Cache {
+    Cache::new(
+        Duration::ZERO,
+        Duration::from_secs(86400),
+        Duration::from_secs(10800),
+        Duration::ZERO,
+        1 << 20,
+    )
+}
+
+#[test]
+fn get_cached_rrset() {
+    let cache = default_cache();
+    let records = vec![
+        record("www.example.com", rr::A, 300, vec![192, 0, 2, 1]),
+        record("www.example.com", rr::A, 300, vec![192, 0, 2, 2]),
+    ];
+    cache.insert(CacheKey::new("www.example.com", rr::A, rr::IN), records);
+
+    let cached = cache.get("WWW.Example.com.", rr::A, rr::IN).unwrap();
+    assert_eq!(cached.len(), 2);
+    assert_eq!(cached[1].get_ip_addr(), "192.0.2.2");
+    assert!(cached[0].rr_ttl <= 300 && cached[0].rr_ttl >= 299);
+    assert!(cache.get("www.example.com", rr::AAAA, rr::IN).is_none());
+    assert!(cache.get("www.example.com", rr::A, 3).is_none());
+}
+
+#[test]
+fn cap_ttl() {
+    let cache = Cache::new(
+        Duration::from_secs(60),
+        Duration::from_secs(3600),
+        Duration::from_secs(3600),
+        Duration::ZERO,
+        1 << 20,
+    );
+    let key = CacheKey::new("low.example.com", rr::A, rr::IN);
+    cache.insert(
+        key,
+        vec![record("low.example.com", rr::A, 5, vec![192, 0, 2, 1])],
+    );
+    let key = CacheKey::new("high.example.com", rr::A, rr::IN);
+    cache.insert(
+        key,
+        vec![record(
+            "high.example.com",
+            rr::A,
+            172800,
+            vec![192, 0, 2, 2],
+        )],
+    );
+
+    let low = cache.get("low.example.com", rr::A, rr::IN).unwrap();
+    assert!(low[0].rr_ttl > 5 && low[0].rr_ttl <= 60);
+    let high = cache.get("high.example.com", rr::A, rr::IN).unwrap();
+    assert!(high[0].rr_ttl <= 3600);
+}
+
+#[test]
+fn expire_rrset() {
+    let cache = Cache::new(
+        Duration::ZERO,
+        Duration::from_millis(50),
+        Duration::from_millis(50),
+        Duration::ZERO,
+        1 << 20,
+    );
+    let key = CacheKey::new("www.example.com", rr::A, rr::IN);
+    cache.insert(
+        key,
+        vec![record("www.example.com", rr::A, 300, vec![192, 0, 2, 1])],
+    );
+    let key = CacheKey::new("zero.example.com", rr::A, rr::IN);
+    cache.insert(
+        key,
+        vec![record("zero.example.com", rr::A, 0, vec![192, 0, 2, 2])],
+    );
+    assert_eq!(cache.len(), 1);
+
+    thread::sleep(Duration::from_millis(100));
+    assert!(cache.get("www.example.com", rr::A, rr::IN).is_none());
+    assert!(cache.is_empty());
+}
+
+#[test]
+fn skip_records_outside_zone() {
+    let cache = default_cache();
+    let records = vec![
+        record("www.example.com", rr::A, 300, vec![192, 0, 2, 1]),
+        record("www.example.org", rr::A, 300, vec![192, 0, 2, 2]),
+    ];
+    cache.insert_records(&records, "example.com");
+
+    assert!(cache.get("www.example.com", rr::A, rr::IN).is_some());
+    assert!(cache.get("www.example.org", rr::A, rr::IN).is_none());
+}
+
+#[test]
+fn find_deepest_delegation() {
+    let cache = default_cache();
+    let records = vec![
+        record(
+            "com",
+            rr::NS,
+            3600,
+            DnsMessage::encode_address("a.gtld.net"),
+        ),
+        record("a.gtld.net", rr::A, 3600, vec![192, 0, 2, 30]),
+        record(
+            "example.com",
+            rr::NS,
+            3600,
+            DnsMessage::encode_address("ns.example.com"),
+        ),
+        record(
+            "sub.example.com",
+            rr::NS,
+            3600,
+            DnsMessage::encode_address("ns.sub.example.com"),
+        ),
+        record("ns.example.com", rr::A, 3600, vec![192, 0, 2, 53]),
+    ];
+    cache.insert_records(&records, "");
+
+    let delegation = cache.delegation("www.example.com").unwrap();
+    assert_eq!(delegation.zone, "example.com");
+    assert_eq!(delegation.name_servers, vec!["ns.example.com"]);
+    assert_eq!(delegation.addresses, vec!["192.0.2.53"]);
+
+    // No address is cached for the name server of sub.example.com
+    let delegation = cache.delegation("www.sub.example.com").unwrap();
+    assert_eq!(delegation.zone, "example.com");
+
+    assert_eq!(cache.delegation("www.example.net.com").unwrap().zone, "com");
+    assert!(cache.delegation("www.example.org").is_none());
+}
+
+fn soa(zone: &str, ttl: u32, minimum: u32) -> Soa {
+    Soa {
+        zone: zone.to_string(),
+        m_name: format!("ns.{}", zone),
+        r_name: format!("hostmaster.{}", zone),
+        serial: 1,
+        refresh: 7200,
+        retry: 3600,
+        expire: 1209600,
+        minimum,
+        ttl,
+    }
+}
+
+#[test]
+fn cache_nxdomain_below_cut() {
+    let cache = default_cache();
+    cache.insert_nxdomain(
+        "missing.example.com",
+        "192.0.2.53",
+        &soa("example.com", 3600, 300),
+    );
+
+    let answer = cache
+        .get_negative("missing.example.com", rr::A, rr::IN)
+        .unwrap();
+    assert_eq!(answer.kind, NegativeKind::NxDomain);
+    assert_eq!(answer.server, "192.0.2.53");
+    assert!(answer.soa.ttl <= 300 && answer.soa.ttl >= 299);
+
+    // Any type of any name below a name that does not exist
+    let answer = cache
+        .get_negative("www.Missing.example.com", rr::AAAA, rr::IN)
+        .unwrap();
+    assert_eq!(answer.kind, NegativeKind::NxDomain);
+    assert_eq!(answer.owner, "missing.example.com");
+
+    assert!(cache.get_negative("example.com", rr::A, rr::IN).is_none());
+    assert!(cache
+        .get_negative("other.example.com", rr::A, rr::IN)
+        .is_none());
+}
+
+#[test]
+fn cache_nodata_per_type() {
+    let cache = default_cache();
+    let key = CacheKey::new("www.example.com", rr::AAAA, rr::IN);
+    cache.insert_nodata(key, "192.0.2.53", &soa("example.com", 60, 300));
+
+    let answer = cache
+        .get_negative("www.example.com", rr::AAAA, rr::IN)
+        .unwrap();
+    assert_eq!(answer.kind, NegativeKind::NoData);
+    assert!(answer.soa.ttl <= 60);
+    assert!(cache
+        .get_negative("www.example.com", rr::A, rr::IN)
+        .is_none());
+    assert!(cache
+        .get_negative("a.www.example.com", rr::AAAA, rr::IN)
+        .is_none());
+    assert_eq!(cache.negative_len(), 1);
+}
+
+#[test]
+fn expire_negative_answer() {
+    let cache = Cache::new(
+        Duration::ZERO,
+        Duration::from_secs(86400),
+        Duration::from_millis(50),
+        Duration::ZERO,
+        1 << 20,
+    );
+    cache.insert_nxdomain(
+        "missing.example.com",
+        "192.0.2.53",
+        &soa("example.com", 3600, 3600),
+    );
+    cache.insert_nxdomain(
+        "zero.example.com",
+        "192.0.2.53",
+        &soa("example.com", 3600, 0),
+    );
+    assert_eq!(cache.negative_len(), 1);
+
+    thread::sleep(Duration::from_millis(100));
+    assert!(cache
+        .get_negative("missing.example.com", rr::A, rr::IN)
+        .is_none());
+    assert_eq!(cache.negative_len(), 0);
+}
+
+fn temp_path(name: &str) -> std::path::PathBuf {
+    std::env::temp_dir().join(format!("{}-{}", std::process::id(), name))
+}
+
+#[test]
+fn save_and_load() {
+    let cache = default_cache();
+    let records = vec![
+        record("www.example.com", rr::A, 300, vec![192, 0, 2, 1]),
+        record("www.example.com", rr::A, 300, vec![192, 0, 2, 2]),
+        record(
+            "example.com",
+            rr::NS,
+            3600,
+            DnsMessage::encode_address("ns.example.com"),
+        ),
+    ];
+    cache.insert_records(&records, "");
+    let path = temp_path("save_and_load.cache");
+    assert_eq!(cache.save(&path).unwrap(), 2);
+
+    let loaded = default_cache();
+    assert_eq!(loaded.load(&path).unwrap(), 2);
+    let cached = loaded.get("www.example.com", rr::A, rr::IN).unwrap();
+    assert_eq!(cached.len(), 2);
+    assert_eq!(cached[0].get_ip_addr(), "192.0.2.1");
+    assert!(cached[0].rr_ttl <= 300 && cached[0].rr_ttl >= 298);
+    let ns = loaded.get("example.com", rr::NS, rr::IN).unwrap();
+    assert_eq!(ns[0].get_domain_name(), "ns.example.com");
+
+    // RRsets already cached are kept
+    assert_eq!(loaded.load(&path).unwrap(), 0);
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn skip_expired_on_load() {
+    let mut bytes = b"DNSC\x01".to_vec();
+    for expires in [1u64, u32::MAX as u64] {
+        bytes.extend(expires.to_be_bytes());
+        bytes.extend(1u16.to_be_bytes());
+        bytes.extend(record("www.example.com", rr::A, 300, vec![192, 0, 2, 1]).to_be_bytes());
+    }
+    let path = temp_path("skip_expired_on_load.cache");
+    std::fs::write(&path, &bytes).unwrap();
+
+    let cache = default_cache();
+    assert_eq!(cache.load(&path).unwrap(), 1);
+    assert!(cache.get("www.example.com", rr::A, rr::IN).is_some());
+
+    // Truncated and foreign files are rejected
+    std::fs::write(&path, &bytes[..bytes.len() - 2]).unwrap();
+    assert!(default_cache().load(&path).is_err());
+    std::fs::write(&path, b"not a cache").unwrap();
+    assert!(default_cache().load(&path).is_err());
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn evict_least_recently_used() {
+    // Room for about three single address RRsets
+    let cache = Cache::new(
+        Duration::ZERO,
+        Duration::from_secs(86400),
+        Duration::from_secs(10800),
+        Duration::ZERO,
+        300,
+    );
+    for i in 1..=3 {
+        let name = format!("host{}.example.com", i);
+        let key = CacheKey::new(&name, rr::A, rr::IN);
+        cache.insert(key, vec![record(&name, rr::A, 300, vec![192, 0, 2, i])]);
+    }
+    assert!(cache.get("host1.example.com", rr::A, rr::IN).is_some());
+
+    let key = CacheKey::new("host4.example.com", rr::A, rr::IN);
+    cache.insert(
+        key,
+        vec![record("host4.example.com", rr::A, 300, vec![192, 0, 2, 4])],
+    );
+    assert!(cache.get("host1.example.com", rr::A, rr::IN).is_some());
+    assert!(cache.get("host2.example.com", rr::A, rr::IN).is_none());
+    assert!(cache.get("host4.example.com", rr::A, rr::IN).is_some());
+
+    let stats = cache.stats();
+    assert_eq!(stats.insertions, 4);
+    assert_eq!(stats.evictions, 1);
+    assert_eq!(stats.entries, 3);
+    assert!(stats.bytes <= stats.budget);
+    assert_eq!(stats.budget, 300);
+}
+
+#[test]
+fn count_hits_misses_and_expirations() {
+    let cache = Cache::new(
+        Duration::ZERO,
+        Duration::from_millis(50),
+        Duration::from_millis(50),
+        Duration::ZERO,
+        1 << 20,
+    );
+    let key = CacheKey::new("www.example.com", rr::A, rr::IN);
+    cache.insert(
+        key,
+        vec![record("www.example.com", rr::A, 300, vec![192, 0, 2, 1])],
+    );
+    cache.insert_nxdomain(
+        "missing.example.com",
+        "192.0.2.53",
+        &soa("example.com", 60, 60),
+    );
+
+    assert!(cache.get("www.example.com", rr::A, rr::IN).is_some());
+    assert!(cache.get("www.example.org", rr::A, rr::IN).is_none());
+    assert!(cache
+        .get_negative("a.missing.example.com", rr::A, rr::IN)
+        .is_some());
+    thread::sleep(Duration::from_millis(100));
+    assert!(cache.get("www.example.com", rr::A, rr::IN).is_none());
+    assert!(cache
+        .get_negative("missing.example.com", rr::A, rr::IN)
+        .is_none());
+
+    let stats = cache.stats();
+    assert_eq!(stats.hits, 2);
+    assert_eq!(stats.misses, 3);
+    assert_eq!(stats.insertions, 2);
+    assert_eq!(stats.expirations, 2);
+    assert_eq!(stats.entries, 0);
+    assert_eq!(stats.bytes, 0);
+}
+
+#[test]
+fn serve_expired_rrset_while_stale() {
+    let cache = Cache::new(
+        Duration::ZERO,
+        Duration::from_millis(50),
+        Duration::from_secs(10800),
+        Duration::from_millis(200),
+        1 << 20,
+    );
+    let key = CacheKey::new("www.example.com", rr::A, rr::IN);
+    cache.insert(
+        key,
+        vec![record("www.example.com", rr::A, 300, vec![192, 0, 2, 1])],
+    );
+
+    thread::sleep(Duration::from_millis(100));
+    assert!(cache.get("www.example.com", rr::A, rr::IN).is_none());
+    let stale = cache.get_stale("www.example.com", rr::A, rr::IN).unwrap();
+    assert_eq!(stale[0].get_ip_addr(), "192.0.2.1");
+    assert_eq!(stale[0].rr_ttl, 30);
+    assert_eq!(cache.stats().stale_hits, 1);
+
+    thread::sleep(Duration::from_millis(200));
+    assert!(cache.get_stale("www.example.com", rr::A, rr::IN).is_none());
+    assert!(cache.get("www.example.com", rr::A, rr::IN).is_none());
+    assert_eq!(cache.stats().expirations, 1);
+    assert!(cache.is_empty());
+}
+
+#[test]
+fn claim_prefetch_of_popular_rrset_once() {
+    let cache = Cache::new(
+        Duration::ZERO,
+        Duration::from_millis(100),
+        Duration::from_millis(100),
+        Duration::ZERO,
+        1 << 20,
+    );
+    let key = CacheKey::new("www.example.com", rr::A, rr::IN);
+    cache.insert(
+        key.clone(),
+        vec![record("www.example.com", rr::A, 300, vec![192, 0, 2, 1])],
+    );
+    cache.get("www.example.com", rr::A, rr::IN).unwrap();
+    assert!(!cache.claim_prefetch(&key, 0.5, 1));
+
+    thread::sleep(Duration::from_millis(60));
+    assert!(!cache.claim_prefetch(&key, 0.5, 2));
+    cache.get("www.example.com", rr::A, rr::IN).unwrap();
+    assert!(cache.claim_prefetch(&key, 0.5, 2));
+    assert!(!cache.claim_prefetch(&key, 0.5, 2));
+    assert_eq!(cache.stats().prefetches, 1);
+}
+
+#[test]
+fn prefetch_again_after_release() {
+    let cache = default_cache();
+    let key = CacheKey::new("www.example.com", rr::A, rr::IN);
+    cache.insert(
+        key.clone(),
+        vec![record("www.example.com", rr::A, 300, vec![192, 0, 2, 1])],
+    );
+    assert!(cache.claim_prefetch(&key, 1.0, 0));
+    assert!(!cache.claim_prefetch(&key, 1.0, 0));
+    cache.release_prefetch(&key);
+    assert!(cache.claim_prefetch(&key, 1.0, 0));
+    assert_eq!(cache.stats().prefetches, 2);
+}
+
+#[test]
+fn delegation_lookups_are_not_hits() {
+    let cache = default_cache();
+    let records = vec![
+        record(
+            "example.com",
+            rr::NS,
+            3600,
+            DnsMessage::encode_address("ns.example.com"),
+        ),
+        record("ns.example.com", rr::A, 3600, vec![192, 0, 2, 53]),
+    ];
+    cache.insert_records(&records, "");
+    for _ in 0..3 {
+        assert!(cache.delegation("www.example.com").is_some());
+    }
+
+    let key = CacheKey::new("ns.example.com", rr::A, rr::IN);
+    assert!(!cache.claim_prefetch(&key, 1.0, 1));
+    cache.get("ns.example.com", rr::A, rr::IN).unwrap();
+    assert!(cache.claim_prefetch(&key, 1.0, 1));
+}
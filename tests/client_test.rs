@@ -0,0 +1,457 @@
+mod common;
+
+use std::net::UdpSocket;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use dns_resolver::client::config::{ResolverConfig, ResolverMode};
+use dns_resolver::client::error::ResolveError;
+use dns_resolver::client::message::DnsMessage;
+use dns_resolver::client::rr;
+use dns_resolver::client::DnsClient;
+
+use common::{question, record, recursive, recursive_negative, response};
+
+/// Start a server on a loopback port that answers every query it gets
+/// like a recursive server with a single A record
+fn start_server(ip_addr: [u8; 4]) -> String {
+    start_scripted_server(move |query| answer(query, ip_addr))
+}
+
+/// Start a server on a loopback port that answers each query with the
+/// response built by `script`
+fn start_scripted_server<F>(script: F) -> String
+where
+    F: Fn(&[u8]) -> Vec<u8> + Send + 'static,
+{
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let addr = socket.local_addr().unwrap().to_string();
+    thread::spawn(move || loop {
+        let mut buffer = [0; 512];
+        let (len, source) = socket.recv_from(&mut buffer).unwrap();
+        socket.send_to(&script(&buffer[..len]), source).unwrap();
+    });
+    addr
+}
+
+/// Response of a recursive server to `query` with an A record for the
+/// name asked for
+fn answer(query: &[u8], ip_addr: [u8; 4]) -> Vec<u8> {
+    let (name, _) = question(query);
+    recursive(query, vec![record(&name, rr::A, 300, ip_addr.to_vec())])
+}
+
+/// Response of a recursive server to `query` without answers, with the
+/// given response code and the SOA of the root zone
+fn no_answer(query: &[u8], r_code: u16) -> Vec<u8> {
+    recursive_negative(query, r_code, "")
+}
+
+/// Reserve a loopback port nobody answers on
+fn silent_server() -> (UdpSocket, String) {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let addr = socket.local_addr().unwrap().to_string();
+    (socket, addr)
+}
+
+fn stub_config(servers: Vec<String>) -> ResolverConfig {
+    ResolverConfig {
+        mode: ResolverMode::Stub(servers),
+        query_timeout: Duration::from_millis(100),
+        retries: 0,
+        deadline: Duration::from_secs(2),
+        hosts_path: None,
+        ..ResolverConfig::default()
+    }
+}
+
+#[test]
+fn stub_resolve_through_recursive_server() {
+    let server = start_server([192, 0, 2, 10]);
+    let dns_client = DnsClient::with_config(stub_config(vec![server]));
+
+    assert_eq!(
+        dns_client.ask("dns.google.com").unwrap().ip_addrs,
+        vec!["192.0.2.10"]
+    );
+}
+
+#[test]
+fn stub_fail_over_to_next_server() {
+    let (_socket, silent) = silent_server();
+    let non_recursive = start_scripted_server(|query| {
+        let (name, _) = question(query);
+        let answers = vec![record(&name, rr::A, 300, vec![192, 0, 2, 20])];
+        response(query, 1, answers, vec![], vec![])
+    });
+    let recursive = start_server([192, 0, 2, 30]);
+    let dns_client = DnsClient::with_config(stub_config(vec![silent, non_recursive, recursive]));
+
+    assert_eq!(
+        dns_client.ask("dns.google.com").unwrap().ip_addrs,
+        vec!["192.0.2.30"]
+    );
+}
+
+#[test]
+fn stub_report_last_failure() {
+    let (_socket, silent) = silent_server();
+    let dns_client = DnsClient::with_config(stub_config(vec![silent.clone()]));
+
+    match dns_client.ask("dns.google.com") {
+        Err(ResolveError::Timeout { server, .. }) => assert_eq!(server, silent),
+        _ => panic!("Expected a timeout"),
+    }
+}
+
+fn search_config(server: String) -> ResolverConfig {
+    ResolverConfig {
+        search: vec![
+            String::from("corp.example.com"),
+            String::from("example.com"),
+        ],
+        ..stub_config(vec![server])
+    }
+}
+
+#[test]
+fn search_list_skips_missing_names() {
+    let server = start_scripted_server(|query| match question(query).0.as_str() {
+        "www.example.com" => answer(query, [192, 0, 2, 40]),
+        _ => no_answer(query, 3),
+    });
+    let dns_client = DnsClient::with_config(search_config(server));
+
+    let result = dns_client.ask("www").unwrap();
+    assert_eq!(result.name, "www.example.com");
+    assert_eq!(result.ip_addrs, vec!["192.0.2.40"]);
+}
+
+#[test]
+fn search_list_stops_at_existing_name() {
+    let server = start_scripted_server(|query| match question(query).0.as_str() {
+        "www.corp.example.com" => no_answer(query, 0),
+        _ => answer(query, [192, 0, 2, 50]),
+    });
+    let dns_client = DnsClient::with_config(search_config(server));
+
+    match dns_client.ask("www") {
+        Err(ResolveError::NoData { name, .. }) => assert_eq!(name, "www.corp.example.com"),
+        _ => panic!("Expected no data"),
+    }
+}
+
+#[test]
+fn absolute_name_skips_search_list() {
+    let server = start_scripted_server(|query| match question(query).0.as_str() {
+        "www" => no_answer(query, 3),
+        _ => answer(query, [192, 0, 2, 60]),
+    });
+    let dns_client = DnsClient::with_config(search_config(server));
+
+    match dns_client.ask("www.") {
+        Err(ResolveError::NxDomain { name, .. }) => assert_eq!(name, "www"),
+        _ => panic!("Expected a non-existent domain"),
+    }
+}
+
+#[test]
+fn hosts_file_before_dns() {
+    let server = start_server([192, 0, 2, 70]);
+    let dns_client = DnsClient::with_config(ResolverConfig {
+        hosts_path: Some(PathBuf::from("tests/fixtures/hosts")),
+        ..stub_config(vec![server])
+    });
+
+    let result = dns_client.ask("ci").unwrap();
+    assert_eq!(result.name, "ci");
+    assert_eq!(result.ip_addrs, vec!["192.0.2.10"]);
+    assert_eq!(result.ttl, 0);
+    assert_eq!(
+        dns_client.ask("dns.google.com").unwrap().ip_addrs,
+        vec!["192.0.2.70"]
+    );
+}
+
+#[test]
+fn answer_from_cache() {
+    let queries = Arc::new(AtomicUsize::new(0));
+    let counter = queries.clone();
+    let server = start_scripted_server(move |query| {
+        counter.fetch_add(1, Ordering::SeqCst);
+        answer(query, [192, 0, 2, 80])
+    });
+    let dns_client = DnsClient::with_config(stub_config(vec![server]));
+
+    assert_eq!(dns_client.ask("dns.google.com").unwrap().ttl, 300);
+    thread::sleep(Duration::from_millis(1100));
+    for _ in 0..2 {
+        let result = dns_client.ask("dns.google.com").unwrap();
+        assert_eq!(result.ip_addrs, vec!["192.0.2.80"]);
+        // Cached records have what is left of their TTL
+        assert!(result.ttl < 300);
+    }
+    assert_eq!(queries.load(Ordering::SeqCst), 1);
+    assert_eq!(dns_client.cache().len(), 1);
+}
+
+#[test]
+fn count_each_cache_lookup_once() {
+    let server = start_scripted_server(|query| {
+        let target = DnsMessage::encode_address("host.example.net");
+        let answers = vec![
+            record("www.example.com", rr::CNAME, 300, target),
+            record("host.example.net", rr::A, 300, vec![192, 0, 2, 85]),
+        ];
+        recursive(query, answers)
+    });
+    let dns_client = DnsClient::with_config(stub_config(vec![server]));
+
+    dns_client.ask("www.example.com").unwrap();
+    let stats = dns_client.cache().stats();
+    assert_eq!((stats.hits, stats.misses), (0, 1));
+    // Following the cached alias is part of the same lookup
+    dns_client.ask("www.example.com").unwrap();
+    let stats = dns_client.cache().stats();
+    assert_eq!((stats.hits, stats.misses), (1, 1));
+}
+
+#[test]
+fn cache_disabled() {
+    let queries = Arc::new(AtomicUsize::new(0));
+    let counter = queries.clone();
+    let server = start_scripted_server(move |query| {
+        counter.fetch_add(1, Ordering::SeqCst);
+        answer(query, [192, 0, 2, 90])
+    });
+    let dns_client = DnsClient::with_config(ResolverConfig {
+        cache: false,
+        ..stub_config(vec![server])
+    });
+
+    dns_client.ask("dns.google.com").unwrap();
+    dns_client.ask("dns.google.com").unwrap();
+    assert_eq!(queries.load(Ordering::SeqCst), 2);
+    assert!(dns_client.cache().is_empty());
+}
+
+#[test]
+fn nxdomain_from_cache() {
+    let queries = Arc::new(AtomicUsize::new(0));
+    let counter = queries.clone();
+    let server = start_scripted_server(move |query| {
+        counter.fetch_add(1, Ordering::SeqCst);
+        no_answer(query, 3)
+    });
+    let dns_client = DnsClient::with_config(stub_config(vec![server.clone()]));
+
+    let names = [
+        "typo.example.com",
+        "typo.example.com",
+        "www.typo.example.com",
+    ];
+    for (i, name) in names.into_iter().enumerate() {
+        match dns_client.ask(name) {
+            Err(ResolveError::NxDomain {
+                name: missing,
+                server: from,
+                step,
+                soa: Some(soa),
+            }) => {
+                assert_eq!(missing, name);
+                assert_eq!(from, server);
+                // Cached answers are found without a query and with the
+                // negative TTL of the SOA
+                if i > 0 {
+                    assert_eq!(step, 0);
+                    assert!(soa.ttl <= 60);
+                }
+            }
+            other => panic!("Expected a non-existent domain, got {:?}", other),
+        }
+    }
+    assert_eq!(queries.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+fn nodata_from_cache() {
+    let queries = Arc::new(AtomicUsize::new(0));
+    let counter = queries.clone();
+    let server = start_scripted_server(move |query| {
+        counter.fetch_add(1, Ordering::SeqCst);
+        no_answer(query, 0)
+    });
+    let dns_client = DnsClient::with_config(stub_config(vec![server]));
+
+    for _ in 0..2 {
+        match dns_client.ask("v6only.example.com") {
+            Err(ResolveError::NoData { .. }) => {}
+            other => panic!("Expected no data, got {:?}", other),
+        }
+    }
+    // The name exists, so names below it are still asked for
+    assert!(dns_client.ask("www.v6only.example.com").is_err());
+    assert_eq!(queries.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn cache_survives_restart() {
+    let path = std::env::temp_dir().join(format!("{}-client.cache", std::process::id()));
+    let server = start_server([192, 0, 2, 100]);
+    let dns_client = DnsClient::with_config(ResolverConfig {
+        cache_path: Some(path.clone()),
+        ..stub_config(vec![server])
+    });
+    dns_client.ask("dns.google.com").unwrap();
+    assert_eq!(dns_client.save_cache().unwrap(), 1);
+
+    let (_socket, silent) = silent_server();
+    let dns_client = DnsClient::with_config(ResolverConfig {
+        cache_path: Some(path.clone()),
+        ..stub_config(vec![silent])
+    });
+    assert_eq!(
+        dns_client.ask("dns.google.com").unwrap().ip_addrs,
+        vec!["192.0.2.100"]
+    );
+    std::fs::remove_file(path).unwrap();
+}
+
+/// Start a server answering with an A record until `healthy` is cleared,
+/// then failing every query with SERVFAIL
+fn start_flaky_server(ip_addr: [u8; 4]) -> (Arc<AtomicUsize>, String) {
+    start_failing_server(ip_addr, 2)
+}
+
+/// Start a server answering with an A record until `healthy` is cleared,
+/// then answering every query with the response code `r_code`
+fn start_failing_server(ip_addr: [u8; 4], r_code: u16) -> (Arc<AtomicUsize>, String) {
+    let healthy = Arc::new(AtomicUsize::new(1));
+    let flag = healthy.clone();
+    let server = start_scripted_server(move |query| match flag.load(Ordering::SeqCst) {
+        1 => answer(query, ip_addr),
+        _ => no_answer(query, r_code),
+    });
+    (healthy, server)
+}
+
+fn stale_config(server: String, max_stale: Duration) -> ResolverConfig {
+    ResolverConfig {
+        cache_max_ttl: Duration::from_millis(50),
+        serve_stale: true,
+        max_stale,
+        ..stub_config(vec![server])
+    }
+}
+
+#[test]
+fn serve_stale_during_outage() {
+    let (healthy, server) = start_flaky_server([192, 0, 2, 110]);
+    let dns_client = DnsClient::with_config(stale_config(server, Duration::from_secs(60)));
+    assert!(!dns_client.ask("dns.google.com").unwrap().stale);
+
+    healthy.store(0, Ordering::SeqCst);
+    thread::sleep(Duration::from_millis(100));
+    let result = dns_client.ask("dns.google.com").unwrap();
+    assert!(result.stale);
+    assert_eq!(result.ip_addrs, vec!["192.0.2.110"]);
+    // RFC 8767 recommends 30 seconds for stale answers
+    assert_eq!(result.ttl, 30);
+}
+
+#[test]
+fn serve_stale_when_refused() {
+    let (healthy, server) = start_failing_server([192, 0, 2, 115], 5);
+    let dns_client = DnsClient::with_config(stale_config(server, Duration::from_secs(60)));
+    dns_client.ask("dns.google.com").unwrap();
+
+    healthy.store(0, Ordering::SeqCst);
+    thread::sleep(Duration::from_millis(100));
+    let result = dns_client.ask("dns.google.com").unwrap();
+    assert!(result.stale);
+    assert_eq!(result.ip_addrs, vec!["192.0.2.115"]);
+}
+
+#[test]
+fn no_stale_answer_past_max_stale() {
+    let (healthy, server) = start_flaky_server([192, 0, 2, 120]);
+    let dns_client = DnsClient::with_config(stale_config(server, Duration::from_millis(50)));
+    dns_client.ask("dns.google.com").unwrap();
+
+    healthy.store(0, Ordering::SeqCst);
+    thread::sleep(Duration::from_millis(150));
+    match dns_client.ask("dns.google.com") {
+        Err(ResolveError::ServFail { .. }) => {}
+        other => panic!("Expected a server failure, got {:?}", other),
+    }
+}
+
+#[test]
+fn no_stale_answer_unless_enabled() {
+    let (healthy, server) = start_flaky_server([192, 0, 2, 130]);
+    let dns_client = DnsClient::with_config(ResolverConfig {
+        serve_stale: false,
+        ..stale_config(server, Duration::from_secs(60))
+    });
+    dns_client.ask("dns.google.com").unwrap();
+
+    healthy.store(0, Ordering::SeqCst);
+    thread::sleep(Duration::from_millis(100));
+    assert!(dns_client.ask("dns.google.com").is_err());
+}
+
+fn start_counting_server(ip_addr: [u8; 4]) -> (Arc<AtomicUsize>, String) {
+    let queries = Arc::new(AtomicUsize::new(0));
+    let count = queries.clone();
+    let server = start_scripted_server(move |query| {
+        count.fetch_add(1, Ordering::SeqCst);
+        answer(query, ip_addr)
+    });
+    (queries, server)
+}
+
+fn prefetch_config(server: String, min_hits: u64) -> ResolverConfig {
+    ResolverConfig {
+        cache_max_ttl: Duration::from_millis(400),
+        prefetch: true,
+        prefetch_ttl_fraction: 0.5,
+        prefetch_min_hits: min_hits,
+        ..stub_config(vec![server])
+    }
+}
+
+#[test]
+fn prefetch_popular_name_before_expiry() {
+    let (queries, server) = start_counting_server([192, 0, 2, 140]);
+    let dns_client = DnsClient::with_config(prefetch_config(server, 1));
+    dns_client.ask("dns.google.com").unwrap();
+    dns_client.ask("dns.google.com").unwrap();
+    assert_eq!(queries.load(Ordering::SeqCst), 1);
+
+    thread::sleep(Duration::from_millis(250));
+    dns_client.ask("dns.google.com").unwrap();
+    thread::sleep(Duration::from_millis(100));
+    assert_eq!(queries.load(Ordering::SeqCst), 2);
+    assert_eq!(dns_client.cache().stats().prefetches, 1);
+
+    thread::sleep(Duration::from_millis(100));
+    let result = dns_client.ask("dns.google.com").unwrap();
+    assert_eq!(result.ip_addrs, vec!["192.0.2.140"]);
+    assert_eq!(queries.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn no_prefetch_of_rarely_used_name() {
+    let (queries, server) = start_counting_server([192, 0, 2, 150]);
+    let dns_client = DnsClient::with_config(prefetch_config(server, 5));
+    dns_client.ask("dns.google.com").unwrap();
+
+    thread::sleep(Duration::from_millis(250));
+    dns_client.ask("dns.google.com").unwrap();
+    thread::sleep(Duration::from_millis(100));
+    assert_eq!(queries.load(Ordering::SeqCst), 1);
+    assert_eq!(dns_client.cache().stats().prefetches, 0);
+}
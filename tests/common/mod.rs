@@ -0,0 +1,140 @@
+//! Fixtures shared by the integration tests: records, responses of fake
+//! name servers and a small hierarchy served over a `MemoryTransport`
+#![allow(dead_code)]
+
+use std::net::SocketAddr;
+
+use dns_resolver::client::config::ResolverConfig;
+use dns_resolver::client::message::DnsMessage;
+use dns_resolver::client::root_hints::RootHints;
+use dns_resolver::client::rr::{self, ResourceRecord};
+use dns_resolver::client::transport::MemoryTransport;
+
+/// Record of `rr_type` owned by `name`
+pub fn record(name: &str, rr_type: u16, ttl: u32, rdata: Vec<u8>) -> ResourceRecord {
+    ResourceRecord::new(name, rr_type, ttl, rdata)
+}
+
+/// SOA record of `zone`, naming `ns1.<zone>` as its primary server
+pub fn soa(zone: &str) -> ResourceRecord {
+    let mut rdata = DnsMessage::encode_address(&format!("ns1.{}", zone));
+    rdata.extend(DnsMessage::encode_address(&format!("admin.{}", zone)));
+    for value in [1u32, 3600, 600, 86400, 60] {
+        rdata.extend(value.to_be_bytes());
+    }
+    record(zone, rr::SOA, 300, rdata)
+}
+
+/// Response to `query` with the given sections
+pub fn response(
+    query: &[u8],
+    aa: u16,
+    answers: Vec<ResourceRecord>,
+    authorities: Vec<ResourceRecord>,
+    additionals: Vec<ResourceRecord>,
+) -> Vec<u8> {
+    let mut dns_response = DnsMessage::parse(query).unwrap();
+    dns_response.header.flags.qr = 1;
+    dns_response.header.flags.aa = aa;
+    dns_response.header.an_cnt = answers.len() as u16;
+    dns_response.header.ns_cnt = authorities.len() as u16;
+    dns_response.header.ar_cnt = additionals.len() as u16;
+    dns_response.answers = answers;
+    dns_response.authorities = authorities;
+    dns_response.additionals = additionals;
+    dns_response.to_be_bytes()
+}
+
+/// Response of a recursive server to `query` with the given answers, or
+/// NXDOMAIN when there are none
+pub fn recursive(query: &[u8], answers: Vec<ResourceRecord>) -> Vec<u8> {
+    let mut dns_response = DnsMessage::parse(&response(query, 0, answers, vec![], vec![])).unwrap();
+    dns_response.header.flags.ra = 1;
+    if dns_response.answers.is_empty() {
+        dns_response.header.flags.r_code = 3;
+    }
+    dns_response.to_be_bytes()
+}
+
+/// Authoritative response to `query` without answers, with the given
+/// response code and the SOA of `zone`
+pub fn negative(query: &[u8], r_code: u16, zone: &str) -> Vec<u8> {
+    let mut dns_response =
+        DnsMessage::parse(&response(query, 1, vec![], vec![soa(zone)], vec![])).unwrap();
+    dns_response.header.flags.r_code = r_code;
+    dns_response.to_be_bytes()
+}
+
+/// Response of a recursive server to `query` without answers, with the
+/// given response code and the SOA of `zone`
+pub fn recursive_negative(query: &[u8], r_code: u16, zone: &str) -> Vec<u8> {
+    let mut dns_response = DnsMessage::parse(&negative(query, r_code, zone)).unwrap();
+    dns_response.header.flags.aa = 0;
+    dns_response.header.flags.ra = 1;
+    dns_response.to_be_bytes()
+}
+
+/// Referral of `zone` to the name server `ns_name` at `ns_ip`
+pub fn referral(query: &[u8], zone: &str, ns_name: &str, ns_ip: [u8; 4]) -> Vec<u8> {
+    response(
+        query,
+        0,
+        vec![],
+        vec![record(
+            zone,
+            rr::NS,
+            300,
+            DnsMessage::encode_address(ns_name),
+        )],
+        vec![record(ns_name, rr::A, 300, ns_ip.to_vec())],
+    )
+}
+
+/// Name and type asked for in `query`
+pub fn question(query: &[u8]) -> (String, u16) {
+    let dns_query = DnsMessage::parse(query).unwrap();
+    (
+        DnsMessage::decode_address(&dns_query.question.q_name),
+        dns_query.question.q_type,
+    )
+}
+
+/// Address of a server at `ip` on the DNS port
+pub fn addr(ip: &str) -> SocketAddr {
+    format!("{}:53", ip).parse().unwrap()
+}
+
+/// Transport serving a root at 10.0.0.1 that refers `com` to 10.0.0.2,
+/// which refers `example.com` to 10.0.0.3, which holds `www.example.com`
+pub fn hierarchy() -> MemoryTransport {
+    let transport = MemoryTransport::new();
+    transport.add_server(addr("10.0.0.1"), |query| {
+        Some(referral(query, "com", "a.gtld-servers.net", [10, 0, 0, 2]))
+    });
+    transport.add_server(addr("10.0.0.2"), |query| {
+        Some(referral(
+            query,
+            "example.com",
+            "ns1.example.com",
+            [10, 0, 0, 3],
+        ))
+    });
+    transport.add_server(addr("10.0.0.3"), |query| {
+        let answer = record("www.example.com", rr::A, 300, vec![192, 0, 2, 1]);
+        Some(response(query, 1, vec![answer], vec![], vec![]))
+    });
+    transport
+}
+
+/// Settings resolving iteratively from the root at 10.0.0.1, without
+/// priming, hosts file, retries or exploring slower servers
+pub fn memory_config() -> ResolverConfig {
+    ResolverConfig {
+        root_hints: RootHints::from_addresses(&[String::from("10.0.0.1")]),
+        prime_roots: false,
+        hosts_path: None,
+        retries: 0,
+        rtt_exploration: 0.0,
+        ..ResolverConfig::default()
+    }
+}
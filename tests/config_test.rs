@@ -0,0 +1,57 @@
+use std::time::Duration;
+
+use dns_resolver::client::config::ResolverConfig;
+
+#[test]
+fn back_off_doubles_timeout() {
+    let config = ResolverConfig {
+        query_timeout: Duration::from_millis(500),
+        max_query_timeout: Duration::from_secs(3),
+        ..ResolverConfig::default()
+    };
+
+    assert_eq!(config.timeout_for_attempt(0), Duration::from_millis(500));
+    assert_eq!(config.timeout_for_attempt(1), Duration::from_millis(1000));
+    assert_eq!(config.timeout_for_attempt(2), Duration::from_millis(2000));
+    assert_eq!(config.timeout_for_attempt(3), Duration::from_secs(3));
+    assert_eq!(config.timeout_for_attempt(40), Duration::from_secs(3));
+}
+
+#[test]
+fn expand_relative_names() {
+    let config = ResolverConfig {
+        search: vec![
+            String::from("corp.example.com"),
+            String::from("example.com."),
+        ],
+        ndots: 2,
+        ..ResolverConfig::default()
+    };
+
+    assert_eq!(
+        config.search_candidates("www"),
+        vec!["www.corp.example.com", "www.example.com", "www"]
+    );
+    assert_eq!(
+        config.search_candidates("dns.google.com"),
+        vec![
+            "dns.google.com",
+            "dns.google.com.corp.example.com",
+            "dns.google.com.example.com"
+        ]
+    );
+    assert_eq!(config.search_candidates("www.lab."), vec!["www.lab"]);
+}
+
+#[test]
+fn minimise_one_label_at_a_time_then_in_chunks() {
+    let config = ResolverConfig::default();
+
+    assert_eq!(config.minimised_labels(3, 0, 0), 1);
+    assert_eq!(config.minimised_labels(3, 2, 1), 3);
+    assert_eq!(config.minimised_labels(20, 3, 3), 4);
+    // 16 labels left for the 6 minimised queries left
+    assert_eq!(config.minimised_labels(20, 4, 4), 7);
+    assert_eq!(config.minimised_labels(20, 19, 9), 20);
+    assert_eq!(config.minimised_labels(20, 5, 10), 20);
+}
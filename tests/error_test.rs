@@ -0,0 +1,78 @@
+use dns_resolver::client::error::ResolveError;
+
+#[test]
+fn distinct_exit_codes() {
+    let errors = vec![
+        ResolveError::NxDomain {
+            name: String::from("nope.com"),
+            server: String::from("192.0.2.1"),
+            step: 3,
+            soa: None,
+        },
+        ResolveError::NoData {
+            name: String::from("google.com"),
+            server: String::from("192.0.2.1"),
+            step: 3,
+            soa: None,
+        },
+        ResolveError::ServFail {
+            name: String::from("google.com"),
+            server: String::from("192.0.2.1"),
+            step: 2,
+        },
+        ResolveError::Refused {
+            name: String::from("google.com"),
+            server: String::from("192.0.2.1"),
+            step: 2,
+        },
+        ResolveError::Timeout {
+            name: String::from("google.com"),
+            server: String::from("192.0.2.1"),
+            step: 1,
+        },
+        ResolveError::LameDelegation {
+            name: String::from("google.com"),
+            server: String::from("192.0.2.1"),
+            step: 2,
+            zone: String::from("google.com"),
+        },
+        ResolveError::Malformed {
+            server: String::from("192.0.2.1"),
+            step: 1,
+            reason: String::from("Message too short"),
+        },
+        ResolveError::LoopDetected {
+            name: String::from("google.com"),
+            step: 9,
+            reason: String::from("alias chain through google.com"),
+        },
+        ResolveError::Bogus {
+            name: String::from("google.com"),
+            server: String::from("192.0.2.1"),
+            step: 3,
+            reason: String::from("signature expired"),
+        },
+    ];
+
+    let mut exit_codes = errors.iter().map(|e| e.exit_code()).collect::<Vec<_>>();
+    assert!(exit_codes.iter().all(|&code| code > 1));
+    exit_codes.sort();
+    exit_codes.dedup();
+    assert_eq!(exit_codes.len(), errors.len());
+}
+
+#[test]
+fn describe_failing_server() {
+    let err = ResolveError::ServFail {
+        name: String::from("google.com"),
+        server: String::from("192.0.2.1"),
+        step: 2,
+    };
+
+    assert!(!err.is_final());
+    assert_eq!(err.server(), Some("192.0.2.1"));
+    assert_eq!(
+        err.to_string(),
+        "192.0.2.1 failed resolving google.com (SERVFAIL at step 2)"
+    );
+}
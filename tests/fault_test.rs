@@ -0,0 +1,191 @@
+#![cfg(feature = "test-util")]
+
+mod common;
+
+use std::time::{Duration, Instant};
+
+use dns_resolver::client::config::ResolverConfig;
+use dns_resolver::client::error::ResolveError;
+use dns_resolver::client::fault::{Fault, Faults, FaultyTransport};
+use dns_resolver::client::transport::{Connection, MemoryTransport, Transport};
+use dns_resolver::client::DnsClient;
+
+use common::{addr, hierarchy, memory_config};
+
+/// Exchange `count` messages with an echo server over `transport`, giving
+/// what came back
+fn echo_exchanges(transport: &FaultyTransport<MemoryTransport>, count: u8) -> Vec<Vec<u8>> {
+    let deadline = Instant::now() + Duration::from_secs(1);
+    let mut received = Vec::new();
+    for i in 0..count {
+        let mut connection = transport.connect(addr("10.0.0.1"), deadline).unwrap();
+        connection.send(&[i; 16]).unwrap();
+        while let Ok(response) = connection.receive(deadline) {
+            received.push(response);
+        }
+    }
+    received
+}
+
+#[test]
+fn same_seed_injects_same_faults() {
+    let faults = Faults {
+        drop: 0.2,
+        reorder: 0.2,
+        duplicate: 0.2,
+        truncate: 0.2,
+        corrupt: 0.2,
+        ..Faults::default()
+    };
+    let transports = [1, 1, 2].map(|seed| {
+        let echo = MemoryTransport::new();
+        echo.add_server(addr("10.0.0.1"), |query| Some(query.to_vec()));
+        FaultyTransport::new(echo, faults.clone(), seed)
+    });
+
+    let received = transports
+        .iter()
+        .map(|transport| echo_exchanges(transport, 50))
+        .collect::<Vec<_>>();
+    assert_eq!(received[0], received[1]);
+    assert_eq!(transports[0].injected(), transports[1].injected());
+    assert_ne!(transports[0].injected(), transports[2].injected());
+    assert!(!transports[0].injected().is_empty());
+}
+
+#[test]
+fn retry_lost_packets() {
+    let faults = Faults {
+        drop: 0.3,
+        ..Faults::default()
+    };
+    let transport = FaultyTransport::new(hierarchy(), faults, 7);
+    let config = ResolverConfig {
+        retries: 8,
+        ..memory_config()
+    };
+    let dns_client = DnsClient::with_transports(config, transport.clone(), transport.clone());
+
+    let result = dns_client.ask("www.example.com").unwrap();
+    assert_eq!(result.ip_addrs, vec!["192.0.2.1"]);
+    assert!(transport
+        .injected()
+        .iter()
+        .any(|fault| matches!(fault, Fault::DropQuery(_) | Fault::DropResponse(_))));
+}
+
+#[test]
+fn time_out_when_every_packet_is_lost() {
+    let faults = Faults {
+        drop: 1.0,
+        ..Faults::default()
+    };
+    let transport = FaultyTransport::new(hierarchy(), faults, 7);
+    let dns_client = DnsClient::with_transports(memory_config(), transport.clone(), transport);
+
+    match dns_client.ask("www.example.com") {
+        Err(ResolveError::Timeout { server, .. }) => assert_eq!(server, "10.0.0.1"),
+        other => panic!("Expected a timeout, got {:?}", other),
+    }
+}
+
+#[test]
+fn match_delayed_and_duplicated_responses() {
+    let faults = Faults {
+        delay: 1.0,
+        max_delay: Duration::from_millis(10),
+        duplicate: 1.0,
+        ..Faults::default()
+    };
+    let transport = FaultyTransport::new(hierarchy(), faults, 7);
+    let dns_client =
+        DnsClient::with_transports(memory_config(), transport.clone(), transport.clone());
+
+    let result = dns_client.ask("www.example.com").unwrap();
+    assert_eq!(result.ip_addrs, vec!["192.0.2.1"]);
+    let injected = transport.injected();
+    for kind in ["Delay", "Duplicate"] {
+        assert!(injected
+            .iter()
+            .any(|fault| format!("{:?}", fault).starts_with(kind)));
+    }
+}
+
+#[test]
+fn deliver_reordered_response_to_next_query() {
+    let echo = MemoryTransport::new();
+    echo.add_server(addr("10.0.0.1"), |query| Some(query.to_vec()));
+    let faults = Faults {
+        reorder: 1.0,
+        ..Faults::default()
+    };
+    let transport = FaultyTransport::new(echo, faults, 7);
+
+    assert_eq!(
+        echo_exchanges(&transport, 3),
+        vec![vec![0; 16], vec![1; 16]]
+    );
+    assert_eq!(
+        transport.injected(),
+        vec![Fault::Reorder(addr("10.0.0.1")); 3]
+    );
+}
+
+#[test]
+fn discard_responses_reordered_into_later_queries() {
+    let faults = Faults {
+        reorder: 1.0,
+        ..Faults::default()
+    };
+    let transport = FaultyTransport::new(hierarchy(), faults, 7);
+    let config = ResolverConfig {
+        retries: 3,
+        ..memory_config()
+    };
+    let dns_client = DnsClient::with_transports(config, transport.clone(), transport.clone());
+
+    // Every attempt only gets the response to the previous one, whose ID
+    // doesn't match
+    match dns_client.ask("www.example.com") {
+        Err(ResolveError::Timeout { server, .. }) => assert_eq!(server, "10.0.0.1"),
+        other => panic!("Expected a timeout, got {:?}", other),
+    }
+    assert_eq!(transport.injected().len(), 4);
+}
+
+#[test]
+fn retry_after_reordered_responses() {
+    let faults = Faults {
+        reorder: 0.5,
+        ..Faults::default()
+    };
+    let transport = FaultyTransport::new(hierarchy(), faults, 7);
+    let config = ResolverConfig {
+        retries: 8,
+        ..memory_config()
+    };
+    let dns_client = DnsClient::with_transports(config, transport.clone(), transport.clone());
+
+    let result = dns_client.ask("www.example.com").unwrap();
+    assert_eq!(result.ip_addrs, vec!["192.0.2.1"]);
+    assert!(transport
+        .injected()
+        .iter()
+        .any(|fault| matches!(fault, Fault::Reorder(_))));
+}
+
+#[test]
+fn survive_damaged_responses() {
+    let faults = Faults {
+        truncate: 0.5,
+        corrupt: 0.5,
+        ..Faults::default()
+    };
+    for seed in 0..200 {
+        let transport = FaultyTransport::new(hierarchy(), faults.clone(), seed);
+        let dns_client = DnsClient::with_transports(memory_config(), transport.clone(), transport);
+
+        // Damaged responses must be rejected or survived, never panic
+        let _ = dns_client.ask("www.example.com");
+    }
+}
@@ -0,0 +1,69 @@
+use std::fs;
+use std::net::IpAddr;
+use std::thread;
+use std::time::Duration;
+
+use dns_resolver::client::hosts::{Hosts, HostsFile};
+
+fn ip(ip_addr: &str) -> IpAddr {
+    ip_addr.parse().unwrap()
+}
+
+#[test]
+fn forward_lookup() {
+    let hosts = Hosts::parse(&fs::read_to_string("tests/fixtures/hosts").unwrap());
+
+    assert_eq!(hosts.lookup("ci"), Some(vec![ip("192.0.2.10")]));
+    assert_eq!(
+        hosts.lookup("BUILD.lab.example.com."),
+        Some(vec![ip("192.0.2.10")])
+    );
+    assert_eq!(
+        hosts.lookup("cache.lab.example.com"),
+        Some(vec![ip("192.0.2.11"), ip("2001:db8::11")])
+    );
+    assert_eq!(
+        hosts.lookup("localhost"),
+        Some(vec![ip("127.0.0.1"), ip("::1")])
+    );
+    assert_eq!(hosts.lookup("broken"), None);
+    assert_eq!(hosts.lookup("examp"), None);
+}
+
+#[test]
+fn reverse_lookup() {
+    let hosts = Hosts::parse(&fs::read_to_string("tests/fixtures/hosts").unwrap());
+
+    assert_eq!(
+        hosts.reverse(ip("192.0.2.10")),
+        Some(vec![
+            String::from("build.lab.example.com"),
+            String::from("build"),
+            String::from("ci")
+        ])
+    );
+    assert_eq!(
+        hosts.reverse(ip("::1")),
+        Some(vec![
+            String::from("localhost"),
+            String::from("ip6-localhost"),
+            String::from("ip6-loopback")
+        ])
+    );
+    assert_eq!(hosts.reverse(ip("192.0.2.12")), None);
+}
+
+#[test]
+fn reload_changed_file() {
+    let path = std::env::temp_dir().join(format!("dns_resolver_hosts_{}", std::process::id()));
+    fs::write(&path, "192.0.2.1 printer\n").unwrap();
+    let hosts_file = HostsFile::new(&path);
+    assert_eq!(hosts_file.lookup("printer"), Some(vec![ip("192.0.2.1")]));
+
+    thread::sleep(Duration::from_millis(20));
+    fs::write(&path, "192.0.2.2 printer\n").unwrap();
+    assert_eq!(hosts_file.lookup("printer"), Some(vec![ip("192.0.2.2")]));
+
+    fs::remove_file(&path).unwrap();
+    assert_eq!(hosts_file.lookup("printer"), None);
+}
@@ -0,0 +1,90 @@
+use std::thread;
+use std::time::Duration;
+
+use dns_resolver::client::infra::InfraCache;
+
+fn servers() -> Vec<String> {
+    vec![
+        String::from("192.0.2.1"),
+        String::from("192.0.2.2"),
+        String::from("192.0.2.3"),
+    ]
+}
+
+#[test]
+fn order_by_smoothed_rtt() {
+    let infra = InfraCache::new(0.0, Duration::from_secs(60), 1 << 20);
+    infra.record_rtt("192.0.2.1", Duration::from_millis(80));
+    infra.record_rtt("192.0.2.2", Duration::from_millis(20));
+
+    assert_eq!(
+        infra.order(&servers()),
+        vec!["192.0.2.3", "192.0.2.2", "192.0.2.1"]
+    );
+
+    infra.record_rtt("192.0.2.2", Duration::from_millis(320));
+    let stats = infra.get("192.0.2.2").unwrap();
+    assert_eq!(stats.srtt, Duration::from_millis(110));
+    assert_eq!(stats.responses, 2);
+    assert_eq!(infra.order(&servers())[1..], ["192.0.2.1", "192.0.2.2"]);
+}
+
+#[test]
+fn penalise_failing_server() {
+    let infra = InfraCache::new(0.0, Duration::from_secs(60), 1 << 20);
+    for server in servers() {
+        infra.record_rtt(&server, Duration::from_millis(30));
+    }
+    infra.record_failure("192.0.2.1", Duration::from_millis(800));
+    infra.record_failure("192.0.2.1", Duration::from_millis(1600));
+
+    let stats = infra.get("192.0.2.1").unwrap();
+    assert_eq!(stats.failures, 2);
+    assert!(infra.effective_rtt("192.0.2.1") > Duration::from_millis(2000));
+    assert_eq!(infra.order(&servers())[2], "192.0.2.1");
+    assert_eq!(infra.servers()[2].0, "192.0.2.1");
+
+    infra.record_rtt("192.0.2.1", Duration::from_millis(30));
+    assert_eq!(infra.get("192.0.2.1").unwrap().failures, 0);
+}
+
+#[test]
+fn decay_penalty_over_time() {
+    let infra = InfraCache::new(0.0, Duration::from_millis(10), 1 << 20);
+    infra.record_rtt("192.0.2.1", Duration::from_millis(30));
+    infra.record_rtt("192.0.2.2", Duration::from_millis(50));
+    infra.record_failure("192.0.2.1", Duration::from_secs(1));
+    assert!(infra.effective_rtt("192.0.2.1") > Duration::from_millis(500));
+
+    thread::sleep(Duration::from_millis(200));
+    assert!(infra.effective_rtt("192.0.2.1") < Duration::from_millis(40));
+    assert_eq!(
+        infra.order(&servers()[0..2]),
+        vec!["192.0.2.1", "192.0.2.2"]
+    );
+}
+
+#[test]
+fn explore_other_servers() {
+    let infra = InfraCache::new(1.0, Duration::from_secs(60), 1 << 20);
+    infra.record_rtt("192.0.2.1", Duration::from_millis(10));
+    infra.record_rtt("192.0.2.2", Duration::from_millis(20));
+    infra.record_rtt("192.0.2.3", Duration::from_millis(30));
+
+    for _ in 0..20 {
+        assert_ne!(infra.order(&servers())[0], "192.0.2.1");
+    }
+}
+
+#[test]
+fn forget_least_recently_used_server() {
+    let infra = InfraCache::new(0.0, Duration::from_secs(60), 300);
+    for i in 0..10 {
+        infra.record_rtt(&format!("192.0.2.{}", i), Duration::from_millis(30));
+    }
+
+    assert!(infra.len() < 10);
+    assert!(infra.bytes() <= 300);
+    assert!(infra.get("192.0.2.0").is_none());
+    assert!(infra.get("192.0.2.9").is_some());
+}
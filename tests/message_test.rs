@@ -62,3 +62,92 @@ fn parse_dns_response() {
         println!("{}", ip_addr);
     }
 }
+
+#[test]
+fn parse_truncated_response() {
+    let response_bytes = vec![
+        0x00, 0x16, 0x80, 0x80, 0x00, 0x01, 0x00, 0x02, 0x00, 0x00, 0x00, 0x00, 0x03, 0x64, 0x6e,
+        0x73, 0x06, 0x67, 0x6f, 0x6f, 0x67, 0x6c, 0x65, 0x03, 0x63, 0x6f, 0x6d, 0x00, 0x00, 0x01,
+        0x00, 0x01, 0xc0, 0x0c, 0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x02, 0x14, 0x00, 0x04, 0x08,
+    ];
+
+    assert!(DnsMessage::parse(&response_bytes).is_err());
+    assert!(DnsMessage::parse(&response_bytes[0..8]).is_err());
+}
+
+#[test]
+fn subdomain_of_zone() {
+    assert!(DnsMessage::is_subdomain("dns.google.com", "com"));
+    assert!(DnsMessage::is_subdomain("dns.google.com", "Google.COM."));
+    assert!(DnsMessage::is_subdomain("com", ""));
+    assert!(!DnsMessage::is_subdomain("dns.notgoogle.com", "google.com"));
+}
+
+fn response_to(query: &DnsMessage, name: &str) -> Vec<u8> {
+    let mut response = query.to_be_bytes();
+    response[2] |= 0x80;
+    response.truncate(12);
+    response.extend(DnsMessage::encode_address(name));
+    response.extend([0x00, 0x01, 0x00, 0x01]);
+    response
+}
+
+#[test]
+fn accept_matching_response() {
+    let query = DnsMessage::new("dns.google.com");
+    let response = DnsMessage::parse(&response_to(&query, "dns.google.com")).unwrap();
+    assert!(response.validate_response(&query, false).is_ok());
+    assert!(response.validate_response(&query, true).is_ok());
+
+    let response = DnsMessage::parse(&response_to(&query, "DNS.Google.com")).unwrap();
+    assert!(response.validate_response(&query, false).is_ok());
+    assert!(response.validate_response(&query, true).is_err());
+}
+
+#[test]
+fn reject_mismatched_response() {
+    let query = DnsMessage::new("dns.google.com");
+
+    let mut bytes = response_to(&query, "dns.google.com");
+    bytes[1] = bytes[1].wrapping_add(1);
+    let response = DnsMessage::parse(&bytes).unwrap();
+    assert!(response.validate_response(&query, false).is_err());
+
+    let mut bytes = response_to(&query, "dns.google.com");
+    bytes[2] &= 0x7f;
+    let response = DnsMessage::parse(&bytes).unwrap();
+    assert!(response.validate_response(&query, false).is_err());
+
+    let response = DnsMessage::parse(&response_to(&query, "dns.evil.com")).unwrap();
+    assert!(response.validate_response(&query, false).is_err());
+}
+
+#[test]
+fn randomize_question_case() {
+    let name = "abcdefghijklmnopqrstuvwxyz.example.com";
+    let mut query = DnsMessage::new(name);
+    query.randomize_case();
+
+    let q_name = &query.question.q_name;
+    assert!(q_name.eq_ignore_ascii_case(&DnsMessage::encode_address(name)));
+    assert_ne!(*q_name, DnsMessage::encode_address(name));
+
+    let echoed = DnsMessage::decode_address(q_name);
+    let response = DnsMessage::parse(&response_to(&query, &echoed)).unwrap();
+    assert!(response.validate_response(&query, true).is_ok());
+    let response = DnsMessage::parse(&response_to(&query, name)).unwrap();
+    assert!(response.validate_response(&query, true).is_err());
+}
+
+#[test]
+fn add_edns_record() {
+    let mut query = DnsMessage::new("dns.google.com");
+    query.add_edns(1232);
+    let bytes = query.to_be_bytes();
+
+    assert_eq!(query.header.ar_cnt, 1);
+    assert_eq!(
+        bytes[bytes.len() - 11..],
+        [0x00, 0x00, 0x29, 0x04, 0xd0, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]
+    );
+}
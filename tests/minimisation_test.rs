@@ -0,0 +1,190 @@
+mod common;
+
+use dns_resolver::client::config::ResolverConfig;
+use dns_resolver::client::error::ResolveError;
+use dns_resolver::client::message::DnsMessage;
+use dns_resolver::client::rr;
+use dns_resolver::client::transport::MemoryTransport;
+use dns_resolver::client::DnsClient;
+
+use common::{addr, hierarchy, memory_config, negative, question, record, response};
+
+/// Names asked of `server` over `transport`, in order
+fn asked(transport: &MemoryTransport, server: &str) -> Vec<String> {
+    transport
+        .queries()
+        .iter()
+        .filter(|(server_addr, _)| *server_addr == addr(server))
+        .map(|(_, query)| question(query).0)
+        .collect()
+}
+
+/// Transport serving the `hierarchy` of the common fixtures, where the
+/// `example.com` server at 10.0.0.3 holds `host` and nothing else. It says
+/// names above `host` exist without records unless `deny_empty` makes it
+/// deny they exist
+fn minimal_hierarchy(host: &'static str, deny_empty: bool) -> MemoryTransport {
+    let transport = hierarchy();
+    transport.add_server(addr("10.0.0.3"), move |query| {
+        let (name, _) = question(query);
+        if name == host {
+            let answer = record(host, rr::A, 300, vec![192, 0, 2, 1]);
+            Some(response(query, 1, vec![answer], vec![], vec![]))
+        } else if host.ends_with(&format!(".{}", name)) && !deny_empty {
+            Some(negative(query, 0, "example.com"))
+        } else {
+            Some(negative(query, 3, "example.com"))
+        }
+    });
+    transport
+}
+
+fn client(
+    config: ResolverConfig,
+    transport: &MemoryTransport,
+) -> DnsClient<MemoryTransport, MemoryTransport> {
+    DnsClient::with_transports(config, transport.clone(), transport.clone())
+}
+
+#[test]
+fn reveal_one_label_per_zone() {
+    let transport = minimal_hierarchy("www.example.com", false);
+    let dns_client = client(memory_config(), &transport);
+
+    let result = dns_client.ask("www.example.com").unwrap();
+    assert_eq!(result.ip_addrs, vec!["192.0.2.1"]);
+    assert_eq!(asked(&transport, "10.0.0.1"), vec!["com"]);
+    assert_eq!(asked(&transport, "10.0.0.2"), vec!["example.com"]);
+    assert_eq!(asked(&transport, "10.0.0.3"), vec!["www.example.com"]);
+    assert!(transport
+        .queries()
+        .iter()
+        .all(|(_, query)| question(query).1 == rr::A));
+}
+
+#[test]
+fn send_full_name_when_minimisation_is_off() {
+    let transport = minimal_hierarchy("www.example.com", false);
+    let config = ResolverConfig {
+        qname_minimisation: false,
+        ..memory_config()
+    };
+    let dns_client = client(config, &transport);
+
+    dns_client.ask("www.example.com").unwrap();
+    assert!(transport
+        .queries()
+        .iter()
+        .all(|(_, query)| question(query).0 == "www.example.com"));
+}
+
+#[test]
+fn reveal_more_below_empty_non_terminal() {
+    let transport = minimal_hierarchy("a.b.example.com", false);
+    let dns_client = client(memory_config(), &transport);
+
+    let result = dns_client.ask("a.b.example.com").unwrap();
+    assert_eq!(result.ip_addrs, vec!["192.0.2.1"]);
+    assert_eq!(
+        asked(&transport, "10.0.0.3"),
+        vec!["b.example.com", "a.b.example.com"]
+    );
+}
+
+#[test]
+fn ask_full_name_when_empty_non_terminal_is_denied() {
+    let transport = minimal_hierarchy("a.b.example.com", true);
+    let dns_client = client(memory_config(), &transport);
+
+    let result = dns_client.ask("a.b.example.com").unwrap();
+    assert_eq!(result.ip_addrs, vec!["192.0.2.1"]);
+    assert_eq!(
+        asked(&transport, "10.0.0.3"),
+        vec!["b.example.com", "a.b.example.com"]
+    );
+}
+
+#[test]
+fn report_missing_name_below_missing_parent() {
+    let transport = minimal_hierarchy("www.example.com", false);
+    let dns_client = client(memory_config(), &transport);
+
+    match dns_client.ask("x.y.example.com") {
+        Err(ResolveError::NxDomain { name, .. }) => assert_eq!(name, "x.y.example.com"),
+        other => panic!("Expected a non-existent domain, got {:?}", other),
+    }
+}
+
+#[test]
+fn cap_minimised_queries_for_deep_names() {
+    let host = "l1.l2.l3.l4.l5.l6.l7.l8.l9.l10.l11.l12.l13.l14.l15.l16.example.com";
+    let transport = minimal_hierarchy(host, false);
+    let dns_client = client(memory_config(), &transport);
+
+    dns_client.ask(host).unwrap();
+    let names = asked(&transport, "10.0.0.3");
+    // The minimised queries to the root and com count towards the limits
+    assert!(names.len() <= 10 - 2 + 1, "asked {:?}", names);
+    assert_eq!(names.last().unwrap(), host);
+    assert_eq!(names[..2], ["l16.example.com", "l15.l16.example.com"]);
+    assert!(names[2].starts_with("l12."), "asked {:?}", names);
+}
+
+#[test]
+fn minimise_again_after_alias() {
+    let transport = hierarchy();
+    transport.add_server(addr("10.0.0.3"), |query| {
+        let (name, _) = question(query);
+        match name.as_str() {
+            "alias.example.com" => {
+                let alias = DnsMessage::encode_address("a.b.example.com");
+                let answer = record("alias.example.com", rr::CNAME, 300, alias);
+                Some(response(query, 1, vec![answer], vec![], vec![]))
+            }
+            "a.b.example.com" => {
+                let answer = record("a.b.example.com", rr::A, 300, vec![192, 0, 2, 1]);
+                Some(response(query, 1, vec![answer], vec![], vec![]))
+            }
+            "b.example.com" => Some(negative(query, 0, "example.com")),
+            _ => Some(negative(query, 3, "example.com")),
+        }
+    });
+    let config = ResolverConfig {
+        max_minimise_count: 2,
+        ..memory_config()
+    };
+    let dns_client = client(config, &transport);
+
+    let result = dns_client.ask("alias.example.com").unwrap();
+    assert_eq!(result.ip_addrs, vec!["192.0.2.1"]);
+    // The queries for the alias don't count against its target
+    assert_eq!(
+        asked(&transport, "10.0.0.3"),
+        vec!["alias.example.com", "b.example.com", "a.b.example.com"]
+    );
+}
+
+#[test]
+fn ask_full_name_when_minimised_query_fails() {
+    // SERVFAIL and REFUSED
+    for r_code in [2, 5] {
+        let transport = minimal_hierarchy("a.b.example.com", false);
+        transport.add_server(addr("10.0.0.3"), move |query| {
+            let (name, _) = question(query);
+            if name == "a.b.example.com" {
+                let answer = record(&name, rr::A, 300, vec![192, 0, 2, 1]);
+                Some(response(query, 1, vec![answer], vec![], vec![]))
+            } else {
+                Some(negative(query, r_code, "example.com"))
+            }
+        });
+        let dns_client = client(memory_config(), &transport);
+
+        let result = dns_client.ask("a.b.example.com").unwrap();
+        assert_eq!(result.ip_addrs, vec!["192.0.2.1"]);
+        assert_eq!(
+            asked(&transport, "10.0.0.3"),
+            vec!["b.example.com", "a.b.example.com"]
+        );
+    }
+}
@@ -0,0 +1,101 @@
+// The mock servers listen on loopback addresses only Linux has by default
+#![cfg(all(feature = "test-util", target_os = "linux"))]
+
+use std::net::Ipv4Addr;
+
+use dns_resolver::client::error::ResolveError;
+use dns_resolver::client::DnsClient;
+use dns_resolver::test_util::{MockHierarchy, Zone};
+
+/// Root, `com`, `example.com` and `cdn.net` zones, where `www.example.com`
+/// is an alias of `edge.cdn.net`
+fn hierarchy() -> MockHierarchy {
+    MockHierarchy::start(vec![
+        Zone::new("com"),
+        Zone::new("net"),
+        Zone::new("example.com")
+            .a("example.com", Ipv4Addr::new(192, 0, 2, 1))
+            .cname("www.example.com", "edge.cdn.net")
+            .a("big.example.com", Ipv4Addr::new(192, 0, 2, 3))
+            .truncate("big.example.com")
+            .drop_queries("slow.example.com"),
+        Zone::new("cdn.net").a("edge.cdn.net", Ipv4Addr::new(192, 0, 2, 2)),
+    ])
+    .unwrap()
+}
+
+#[test]
+fn follow_referrals_from_mock_root() {
+    let hierarchy = hierarchy();
+    let dns_client = DnsClient::with_config(hierarchy.config());
+
+    let result = dns_client.ask("example.com").unwrap();
+    assert_eq!(result.ip_addrs, vec!["192.0.2.1"]);
+    for zone in ["", "com", "example.com"] {
+        assert_eq!(hierarchy.server(zone).unwrap().queries(), 1);
+    }
+    assert_eq!(hierarchy.server("net").unwrap().queries(), 0);
+}
+
+#[test]
+fn follow_alias_into_other_zone() {
+    let hierarchy = hierarchy();
+    let dns_client = DnsClient::with_config(hierarchy.config());
+
+    let result = dns_client.ask("www.example.com").unwrap();
+    assert_eq!(result.ip_addrs, vec!["192.0.2.2"]);
+    assert_eq!(hierarchy.server("cdn.net").unwrap().queries(), 1);
+}
+
+#[test]
+fn report_missing_name() {
+    let hierarchy = hierarchy();
+    let dns_client = DnsClient::with_config(hierarchy.config());
+
+    match dns_client.ask("missing.example.com") {
+        Err(ResolveError::NxDomain { name, soa, .. }) => {
+            assert_eq!(name, "missing.example.com");
+            assert_eq!(soa.unwrap().zone, "example.com");
+        }
+        other => panic!("Expected a non-existent domain, got {:?}", other),
+    }
+}
+
+#[test]
+fn report_name_without_address() {
+    let hierarchy = hierarchy();
+    let dns_client = DnsClient::with_config(hierarchy.config());
+
+    match dns_client.ask("ns1.cdn.net.") {
+        Ok(result) => assert_eq!(result.ip_addrs.len(), 1),
+        other => panic!("Expected the address of the name server, got {:?}", other),
+    }
+    match dns_client.ask("cdn.net") {
+        Err(ResolveError::NoData { name, .. }) => assert_eq!(name, "cdn.net"),
+        other => panic!("Expected no data, got {:?}", other),
+    }
+}
+
+#[test]
+fn retry_truncated_answer_over_tcp() {
+    let hierarchy = hierarchy();
+    let dns_client = DnsClient::with_config(hierarchy.config());
+
+    let result = dns_client.ask("big.example.com").unwrap();
+    assert_eq!(result.ip_addrs, vec!["192.0.2.3"]);
+    assert_eq!(hierarchy.server("example.com").unwrap().queries(), 2);
+}
+
+#[test]
+fn time_out_on_dropped_query() {
+    let hierarchy = hierarchy();
+    let dns_client = DnsClient::with_config(hierarchy.config());
+
+    match dns_client.ask("slow.example.com") {
+        Err(ResolveError::Timeout { server, .. }) => {
+            let server_addr = hierarchy.server("example.com").unwrap().addr();
+            assert_eq!(server, server_addr.ip().to_string());
+        }
+        other => panic!("Expected a timeout, got {:?}", other),
+    }
+}
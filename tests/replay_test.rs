@@ -0,0 +1,207 @@
+use std::io;
+use std::net::SocketAddr;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use dns_resolver::client::message::DnsMessage;
+use dns_resolver::client::replay::{Protocol, Recording};
+use dns_resolver::client::rr;
+use dns_resolver::client::transport::{Connection, MemoryTransport, Transport};
+
+fn addr(ip: &str) -> SocketAddr {
+    format!("{}:53", ip).parse().unwrap()
+}
+
+/// Response to `query` with no records
+fn empty_response(query: &[u8]) -> Vec<u8> {
+    let mut dns_response = DnsMessage::parse(query).unwrap();
+    dns_response.header.flags.qr = 1;
+    dns_response.to_be_bytes()
+}
+
+/// Query for `name` with the transaction ID `id`
+fn query(name: &str, id: u16) -> DnsMessage {
+    let mut dns_query = DnsMessage::new_query(name, rr::A);
+    dns_query.header.id = id;
+    dns_query
+}
+
+/// Send `msg` to `server_addr` over `transport`, giving what came back
+fn exchange<T: Transport>(transport: &T, server_addr: SocketAddr, msg: &[u8]) -> Vec<Vec<u8>> {
+    let deadline = Instant::now() + Duration::from_secs(1);
+    let mut connection = transport.connect(server_addr, deadline).unwrap();
+    connection.send(msg).unwrap();
+    let mut responses = vec![];
+    while let Ok(response) = connection.receive(deadline) {
+        responses.push(response);
+    }
+    responses
+}
+
+/// Recording of a query for `www.example.com` answered by 10.0.0.1 and
+/// one for `missing.example.com` 10.0.0.2 didn't answer
+fn recorded() -> Recording {
+    let memory = MemoryTransport::new();
+    memory.add_server(addr("10.0.0.1"), |query| Some(empty_response(query)));
+    let recording = Recording::new();
+    let transport = recording.record(memory, Protocol::Udp);
+    let www = query("www.example.com", 1).to_be_bytes();
+    assert_eq!(exchange(&transport, addr("10.0.0.1"), &www).len(), 1);
+    let missing = query("missing.example.com", 2).to_be_bytes();
+    assert!(exchange(&transport, addr("10.0.0.2"), &missing).is_empty());
+    recording
+}
+
+#[test]
+fn record_queries_and_responses() {
+    let exchanges = recorded().exchanges();
+
+    assert_eq!(exchanges.len(), 2);
+    assert_eq!(exchanges[0].server_addr, addr("10.0.0.1"));
+    assert_eq!(exchanges[0].protocol, Protocol::Udp);
+    assert_eq!(
+        exchanges[0].query,
+        query("www.example.com", 1).to_be_bytes()
+    );
+    assert_eq!(exchanges[0].responses.len(), 1);
+    assert_eq!(exchanges[1].server_addr, addr("10.0.0.2"));
+    assert!(exchanges[1].responses.is_empty());
+}
+
+#[test]
+fn save_and_load_recording() {
+    let recording = recorded();
+    let path = std::env::temp_dir().join(format!("{}-replay.rec", std::process::id()));
+
+    assert_eq!(recording.save(&path).unwrap(), 2);
+    let loaded = Recording::load(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(loaded.exchanges(), recording.exchanges());
+}
+
+#[test]
+fn reject_file_that_is_not_a_recording() {
+    let path = std::env::temp_dir().join(format!("{}-not-replay.rec", std::process::id()));
+    std::fs::write(&path, b"DNSC\x01").unwrap();
+
+    assert!(Recording::load(&path).is_err());
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn replay_response_matched_to_new_query() {
+    let transport = recorded().replay(Protocol::Udp);
+    let mut dns_query = query("www.example.com", 7);
+    dns_query.question.q_name = DnsMessage::encode_address("wWw.ExAmple.com");
+
+    let responses = exchange(&transport, addr("10.0.0.1"), &dns_query.to_be_bytes());
+    assert_eq!(responses.len(), 1);
+    let dns_response = DnsMessage::parse(&responses[0]).unwrap();
+    dns_response.validate_response(&dns_query, true).unwrap();
+    assert_eq!(transport.remaining(), 1);
+
+    // Each recorded exchange is replayed once
+    let responses = exchange(&transport, addr("10.0.0.1"), &dns_query.to_be_bytes());
+    assert!(responses.is_empty());
+}
+
+#[test]
+fn replay_from_other_server_when_none_recorded_for_it() {
+    let transport = recorded().replay(Protocol::Udp);
+    let dns_query = query("www.example.com", 7).to_be_bytes();
+
+    assert_eq!(exchange(&transport, addr("10.0.0.9"), &dns_query).len(), 1);
+    assert_eq!(recorded().replay(Protocol::Tcp).remaining(), 0);
+}
+
+/// Transport whose responses take `delay` to arrive
+struct SlowTransport {
+    inner: MemoryTransport,
+    delay: Duration,
+}
+
+struct SlowConnection<C> {
+    inner: C,
+    delay: Duration,
+}
+
+impl Transport for SlowTransport {
+    type Connection = SlowConnection<<MemoryTransport as Transport>::Connection>;
+
+    fn connect(&self, server_addr: SocketAddr, deadline: Instant) -> io::Result<Self::Connection> {
+        Ok(SlowConnection {
+            inner: self.inner.connect(server_addr, deadline)?,
+            delay: self.delay,
+        })
+    }
+}
+
+impl<C: Connection> Connection for SlowConnection<C> {
+    fn send(&mut self, msg: &[u8]) -> io::Result<()> {
+        self.inner.send(msg)
+    }
+
+    fn receive(&mut self, deadline: Instant) -> io::Result<Vec<u8>> {
+        thread::sleep(self.delay);
+        self.inner.receive(deadline)
+    }
+}
+
+#[test]
+fn replay_responses_as_late_as_recorded() {
+    let memory = MemoryTransport::new();
+    memory.add_server(addr("10.0.0.1"), |query| Some(empty_response(query)));
+    let slow = SlowTransport {
+        inner: memory,
+        delay: Duration::from_millis(200),
+    };
+    let recording = Recording::new();
+    let transport = recording.record(slow, Protocol::Udp);
+    let dns_query = query("www.example.com", 1).to_be_bytes();
+    assert_eq!(exchange(&transport, addr("10.0.0.1"), &dns_query).len(), 1);
+    let transport = recording.replay(Protocol::Udp);
+
+    let started = Instant::now();
+    let mut connection = transport.connect(addr("10.0.0.1"), started).unwrap();
+    connection.send(&dns_query).unwrap();
+    let early = connection.receive(started + Duration::from_millis(50));
+    assert_eq!(early.unwrap_err().kind(), io::ErrorKind::TimedOut);
+    assert!(started.elapsed() >= Duration::from_millis(50));
+
+    // The response is still on its way for a later deadline
+    assert!(connection.receive(started + Duration::from_secs(1)).is_ok());
+    assert!(started.elapsed() >= Duration::from_millis(200));
+}
+
+#[cfg(all(feature = "test-util", target_os = "linux"))]
+#[test]
+fn replay_recorded_resolution_offline() {
+    use dns_resolver::client::DnsClient;
+    use dns_resolver::test_util::{MockHierarchy, Zone};
+    use std::net::Ipv4Addr;
+
+    let hierarchy = MockHierarchy::start(vec![Zone::new("example.com")
+        .a("www.example.com", Ipv4Addr::new(192, 0, 2, 1))
+        .a("big.example.com", Ipv4Addr::new(192, 0, 2, 2))
+        .truncate("big.example.com")])
+    .unwrap();
+    let config = hierarchy.config();
+    let recording = Recording::new();
+    let dns_client = DnsClient::recording(config.clone(), &recording);
+    let names = ["www.example.com", "big.example.com", "missing.example.com"];
+    let live = names.map(|name| format!("{:?}", dns_client.ask(name)));
+    let path = std::env::temp_dir().join(format!("{}-resolution.rec", std::process::id()));
+    recording.save(&path).unwrap();
+    drop(hierarchy);
+
+    let recording = Recording::load(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert!(recording
+        .exchanges()
+        .iter()
+        .any(|exchange| exchange.protocol == Protocol::Tcp));
+    let dns_client = DnsClient::replaying(config, &recording);
+    let replayed = names.map(|name| format!("{:?}", dns_client.ask(name)));
+    assert_eq!(replayed, live);
+    assert!(live[0].contains("192.0.2.1"));
+}
@@ -0,0 +1,54 @@
+use std::time::Duration;
+
+use dns_resolver::client::config::ResolverMode;
+use dns_resolver::client::resolv_conf::ResolvConf;
+
+#[test]
+fn parse_resolv_conf() {
+    let resolv_conf = ResolvConf::read("tests/fixtures/resolv.conf").unwrap();
+
+    assert_eq!(
+        resolv_conf.name_servers,
+        vec!["10.0.0.53", "10.0.1.53", "2001:db8::53"]
+    );
+    assert_eq!(resolv_conf.search, vec!["corp.example.com", "example.com"]);
+    assert_eq!(resolv_conf.ndots, 2);
+    assert_eq!(resolv_conf.timeout, Duration::from_secs(3));
+    assert_eq!(resolv_conf.attempts, 4);
+    assert!(resolv_conf.rotate);
+    assert!(resolv_conf.edns0);
+    assert!(resolv_conf.use_vc);
+}
+
+#[test]
+fn parse_domain_and_clamp_options() {
+    let resolv_conf = ResolvConf::read("tests/fixtures/resolv_domain.conf").unwrap();
+
+    assert_eq!(resolv_conf.name_servers, vec!["127.0.0.1"]);
+    assert_eq!(resolv_conf.search, vec!["lab.example.org"]);
+    assert_eq!(resolv_conf.ndots, 15);
+    assert_eq!(resolv_conf.timeout, Duration::from_secs(1));
+    assert_eq!(resolv_conf.attempts, 5);
+    assert!(!resolv_conf.rotate);
+}
+
+#[test]
+fn build_stub_config() {
+    let config = ResolvConf::read("tests/fixtures/resolv.conf")
+        .unwrap()
+        .to_config();
+
+    assert_eq!(
+        config.mode,
+        ResolverMode::Stub(vec![
+            String::from("10.0.0.53"),
+            String::from("10.0.1.53"),
+            String::from("2001:db8::53")
+        ])
+    );
+    assert_eq!(config.query_timeout, Duration::from_secs(3));
+    assert_eq!(config.retries, 3);
+    assert!(config.force_tcp);
+    assert!(config.rotate);
+    assert!(config.edns0);
+}
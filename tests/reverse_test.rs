@@ -0,0 +1,158 @@
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+
+use dns_resolver::client::config::{ResolverConfig, ResolverMode};
+use dns_resolver::client::error::ResolveError;
+use dns_resolver::client::message::DnsMessage;
+use dns_resolver::client::reverse::{parse_reverse_name, reverse_name};
+use dns_resolver::client::rr::{self, ResourceRecord};
+use dns_resolver::client::transport::MemoryTransport;
+use dns_resolver::client::DnsClient;
+
+const IPV6_NAME: &str = "b.a.9.8.7.6.5.0.4.0.0.0.3.0.0.0.2.0.0.0.1.0.0.0.0.0.0.0.1.2.3.4.ip6.arpa";
+
+fn ip(ip: &str) -> IpAddr {
+    ip.parse().unwrap()
+}
+
+#[test]
+fn build_reverse_names() {
+    assert_eq!(reverse_name(ip("192.0.2.1")), "1.2.0.192.in-addr.arpa");
+    assert_eq!(reverse_name(ip("4321:0:1:2:3:4:567:89ab")), IPV6_NAME);
+}
+
+#[test]
+fn parse_reverse_names() {
+    assert_eq!(
+        parse_reverse_name("1.2.0.192.in-addr.arpa").unwrap(),
+        ip("192.0.2.1")
+    );
+    assert_eq!(
+        parse_reverse_name("1.2.0.192.IN-ADDR.ARPA.").unwrap(),
+        ip("192.0.2.1")
+    );
+    assert_eq!(
+        parse_reverse_name(&IPV6_NAME.to_uppercase()).unwrap(),
+        ip("4321:0:1:2:3:4:567:89ab")
+    );
+    for addr in ["0.0.0.0", "255.255.255.255", "::", "2001:db8::ff00:42:8329"] {
+        assert_eq!(
+            parse_reverse_name(&reverse_name(ip(addr))).unwrap(),
+            ip(addr)
+        );
+    }
+}
+
+#[test]
+fn reject_malformed_reverse_names() {
+    for name in [
+        "2.0.192.in-addr.arpa",
+        "1.1.2.0.192.in-addr.arpa",
+        "256.2.0.192.in-addr.arpa",
+        "+1.2.0.192.in-addr.arpa",
+        "1..0.192.in-addr.arpa",
+        "www.example.com",
+        "in-addr.arpa",
+        "1.2.0.192.in-addr.arpa.example",
+        &IPV6_NAME.replacen("b.", "", 1),
+        &IPV6_NAME.replacen("b.", "g.", 1),
+        &IPV6_NAME.replacen("b.", "bb.", 1),
+    ] {
+        assert!(parse_reverse_name(name).is_err(), "{} was accepted", name);
+    }
+}
+
+/// Recursive server at 10.0.0.1 knowing the host name of 192.0.2.1 only
+fn recursive_server() -> MemoryTransport {
+    let transport = MemoryTransport::new();
+    let server_addr: SocketAddr = "10.0.0.1:53".parse().unwrap();
+    transport.add_server(server_addr, |query| {
+        let mut dns_response = DnsMessage::parse(query).unwrap();
+        dns_response.header.flags.qr = 1;
+        dns_response.header.flags.ra = 1;
+        let name = DnsMessage::decode_address(&dns_response.question.q_name);
+        if dns_response.question.q_type == rr::PTR && name == "1.2.0.192.in-addr.arpa" {
+            let rdata = DnsMessage::encode_address("host.example.com");
+            dns_response.answers.push(ResourceRecord {
+                rr_name: dns_response.question.q_name.clone(),
+                rr_type: rr::PTR,
+                rr_class: rr::IN,
+                rr_ttl: 300,
+                rr_rdlength: rdata.len() as u16,
+                rr_rdata: rdata,
+            });
+            dns_response.header.an_cnt = 1;
+        } else {
+            dns_response.header.flags.r_code = 3;
+        }
+        Some(dns_response.to_be_bytes())
+    });
+    transport
+}
+
+fn stub_config() -> ResolverConfig {
+    ResolverConfig {
+        mode: ResolverMode::Stub(vec![String::from("10.0.0.1")]),
+        hosts_path: None,
+        retries: 0,
+        ..ResolverConfig::default()
+    }
+}
+
+#[test]
+fn look_up_host_names_of_address() {
+    let transport = recursive_server();
+    let dns_client =
+        DnsClient::with_transports(stub_config(), transport.clone(), transport.clone());
+
+    assert_eq!(
+        dns_client.reverse(ip("192.0.2.1")).unwrap(),
+        vec!["host.example.com"]
+    );
+    let queries = transport.queries();
+    let dns_query = DnsMessage::parse(&queries[0].1).unwrap();
+    assert_eq!(dns_query.question.q_type, rr::PTR);
+
+    // The answer is cached apart from the addresses of the name
+    assert_eq!(
+        dns_client.reverse(ip("192.0.2.1")).unwrap(),
+        vec!["host.example.com"]
+    );
+    assert_eq!(transport.queries().len(), 1);
+    assert!(dns_client.ask("1.2.0.192.in-addr.arpa.").is_err());
+}
+
+#[test]
+fn report_address_without_host_name() {
+    let transport = recursive_server();
+    let dns_client = DnsClient::with_transports(stub_config(), transport.clone(), transport);
+
+    match dns_client.reverse(ip("2001:db8::1")) {
+        Err(ResolveError::NxDomain { name, .. }) => {
+            assert_eq!(name, reverse_name(ip("2001:db8::1")))
+        }
+        other => panic!("Expected a non-existent domain, got {:?}", other),
+    }
+}
+
+#[test]
+fn host_names_from_hosts_file_before_dns() {
+    // The server never answers, so only the hosts file can
+    let transport = MemoryTransport::new();
+    let dns_client = DnsClient::with_transports(
+        ResolverConfig {
+            hosts_path: Some(PathBuf::from("tests/fixtures/hosts")),
+            ..stub_config()
+        },
+        transport.clone(),
+        transport.clone(),
+    );
+
+    assert_eq!(
+        dns_client.reverse(ip("192.0.2.10")).unwrap(),
+        vec!["build.lab.example.com", "build", "ci"]
+    );
+    assert!(transport.queries().is_empty());
+    assert!(dns_client.reverse(ip("192.0.2.12")).is_err());
+    assert_eq!(transport.queries().len(), 1);
+}
@@ -0,0 +1,76 @@
+use dns_resolver::client::message::DnsMessage;
+use dns_resolver::client::root_hints::RootHints;
+
+#[test]
+fn builtin_root_servers() {
+    let root_hints = RootHints::builtin();
+
+    assert_eq!(root_hints.servers.len(), 13);
+    assert!(root_hints
+        .servers
+        .iter()
+        .any(|server| server.name == "g.root-servers.net"));
+    assert_eq!(root_hints.addresses().len(), 13);
+    assert!(root_hints
+        .addresses()
+        .contains(&String::from("192.112.36.4")));
+}
+
+#[test]
+fn read_named_root() {
+    let root_hints = RootHints::read("tests/fixtures/named.root").unwrap();
+
+    assert_eq!(root_hints.servers.len(), 3);
+    assert_eq!(root_hints.servers[0].name, "a.root-servers.net");
+    assert_eq!(
+        root_hints.servers[0].ip_addrs,
+        vec!["198.41.0.4", "2001:503:ba3e::2:30"]
+    );
+    assert_eq!(
+        root_hints.addresses(),
+        vec!["198.41.0.4", "170.247.170.2", "10.53.0.1"]
+    );
+}
+
+#[test]
+fn reject_invalid_root_hints() {
+    assert!(RootHints::parse(". 3600000 NS\n").is_err());
+    assert!(RootHints::parse("A.ROOT-SERVERS.NET. 3600000 A 198.41.0\n").is_err());
+    assert!(RootHints::parse("; nothing here\n").is_err());
+}
+
+#[test]
+fn parse_priming_response() {
+    let mut response = vec![
+        0x12, 0x34, 0x84, 0x00, 0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x02,
+        0x00, 0x01,
+    ];
+    // . NS a.root-servers.net
+    response.extend([
+        0x00, 0x00, 0x02, 0x00, 0x01, 0x00, 0x07, 0xe9, 0x00, 0x00, 0x14,
+    ]);
+    response.extend(DnsMessage::encode_address("a.root-servers.net"));
+    // a.root-servers.net A 198.41.0.4
+    response.extend([
+        0xc0, 0x1c, 0x00, 0x01, 0x00, 0x01, 0x00, 0x36, 0xee, 0x80, 0x00, 0x04,
+    ]);
+    response.extend([198, 41, 0, 4]);
+    // a.root-servers.net AAAA 2001:503:ba3e::2:30
+    response.extend([
+        0xc0, 0x1c, 0x00, 0x1c, 0x00, 0x01, 0x00, 0x36, 0xee, 0x80, 0x00, 0x10,
+    ]);
+    response.extend([
+        0x20, 0x01, 0x05, 0x03, 0xba, 0x3e, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x00,
+        0x30,
+    ]);
+
+    let response = DnsMessage::parse(&response).unwrap();
+    let root_hints = RootHints::from_response(&response).unwrap();
+
+    assert_eq!(root_hints.servers.len(), 1);
+    assert_eq!(root_hints.servers[0].name, "a.root-servers.net");
+    assert_eq!(
+        root_hints.servers[0].ip_addrs,
+        vec!["198.41.0.4", "2001:503:ba3e::2:30"]
+    );
+}
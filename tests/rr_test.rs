@@ -42,3 +42,40 @@ fn parse_resource_record() {
         ]
     );
 }
+
+#[test]
+fn parse_soa_record() {
+    let mut response = vec![
+        0x12, 0x34, 0x81, 0x83, 0x0, 0x1, 0x0, 0x0, 0x0, 0x1, 0x0, 0x0,
+    ];
+    response.extend(DnsMessage::encode_address("nope.com"));
+    response.extend([0x0, 0x1, 0x0, 0x1]);
+
+    let mut rdata = vec![0x1, b'a', 0xc];
+    rdata.extend(b"gtld-servers");
+    rdata.extend([0xc0, 0x11, 0x5]);
+    rdata.extend(b"nstld");
+    rdata.push(0xc);
+    rdata.extend(b"verisign-grs");
+    rdata.extend([0xc0, 0x11]);
+    for value in [1718000000u32, 1800, 900, 604800, 86400] {
+        rdata.extend(value.to_be_bytes());
+    }
+    response.extend([0xc0, 0x11, 0x0, 0x6, 0x0, 0x1, 0x0, 0x0, 0x3, 0x84]);
+    response.extend((rdata.len() as u16).to_be_bytes());
+    response.extend(rdata);
+
+    let response = DnsMessage::parse(&response).unwrap();
+    assert_eq!(response.header.flags.qr, 1);
+    assert_eq!(response.header.flags.rd, 1);
+    assert_eq!(response.header.flags.ra, 1);
+    assert_eq!(response.header.flags.r_code, 3);
+
+    let soa = response.authorities[0].get_soa().unwrap();
+    assert_eq!(soa.zone, "com");
+    assert_eq!(soa.m_name, "a.gtld-servers.com");
+    assert_eq!(soa.r_name, "nstld.verisign-grs.com");
+    assert_eq!(soa.serial, 1718000000);
+    assert_eq!(soa.minimum, 86400);
+    assert_eq!(soa.ttl, 900);
+}
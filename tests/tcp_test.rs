@@ -0,0 +1,22 @@
+use std::io::Cursor;
+
+use dns_resolver::client::tcp;
+
+#[test]
+fn frame_message() {
+    let mut stream = vec![];
+    tcp::write_message(&mut stream, &[0x12, 0x34, 0x56]).unwrap();
+    assert_eq!(stream, vec![0x00, 0x03, 0x12, 0x34, 0x56]);
+
+    let mut cursor = Cursor::new(stream);
+    assert_eq!(
+        tcp::read_message(&mut cursor).unwrap(),
+        vec![0x12, 0x34, 0x56]
+    );
+}
+
+#[test]
+fn read_short_message() {
+    let mut cursor = Cursor::new(vec![0x00, 0x04, 0x12, 0x34]);
+    assert!(tcp::read_message(&mut cursor).is_err());
+}
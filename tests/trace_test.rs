@@ -0,0 +1,194 @@
+mod common;
+
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use dns_resolver::client::config::ResolverConfig;
+use dns_resolver::client::error::ResolveError;
+use dns_resolver::client::message::DnsMessage;
+use dns_resolver::client::root_hints::RootHints;
+use dns_resolver::client::rr;
+use dns_resolver::client::trace::{Choice, Outcome, TraceStep};
+use dns_resolver::client::transport::MemoryTransport;
+use dns_resolver::client::DnsClient;
+
+use common::{addr, memory_config, question, record, referral, response};
+
+/// Transport serving a root at 10.0.0.1 that refers `com` to 10.0.0.2 and
+/// `net` to 10.0.0.4. `com` refers `example.com` to `ns.example.net`
+/// without glue, which `net` says is at 10.0.0.3
+fn hierarchy() -> MemoryTransport {
+    let transport = MemoryTransport::new();
+    transport.add_server(addr("10.0.0.1"), |query| {
+        let (zone, ns_name, ns_ip) = if question(query).0.to_ascii_lowercase().ends_with("net") {
+            ("net", "a.gtld-servers.net", [10, 0, 0, 4])
+        } else {
+            ("com", "b.gtld-servers.net", [10, 0, 0, 2])
+        };
+        Some(response(
+            query,
+            0,
+            vec![],
+            vec![record(
+                zone,
+                rr::NS,
+                300,
+                DnsMessage::encode_address(ns_name),
+            )],
+            vec![record(ns_name, rr::A, 300, ns_ip.to_vec())],
+        ))
+    });
+    transport.add_server(addr("10.0.0.2"), |query| {
+        let ns = DnsMessage::encode_address("ns.example.net");
+        Some(response(
+            query,
+            0,
+            vec![],
+            vec![record("example.com", rr::NS, 300, ns)],
+            vec![],
+        ))
+    });
+    transport.add_server(addr("10.0.0.4"), |query| {
+        let answer = record("ns.example.net", rr::A, 300, vec![10, 0, 0, 3]);
+        Some(response(query, 1, vec![answer], vec![], vec![]))
+    });
+    transport.add_server(addr("10.0.0.3"), |query| {
+        let answer = record("www.example.com", rr::A, 300, vec![192, 0, 2, 1]);
+        Some(response(query, 1, vec![answer], vec![], vec![]))
+    });
+    transport
+}
+
+/// Settings resolving from the given root servers
+fn root_config(root_servers: &[&str]) -> ResolverConfig {
+    let root_servers = root_servers
+        .iter()
+        .map(|ip| ip.to_string())
+        .collect::<Vec<_>>();
+    ResolverConfig {
+        root_hints: RootHints::from_addresses(&root_servers),
+        ..memory_config()
+    }
+}
+
+/// Client over `transport` collecting its trace
+fn traced_client(
+    config: ResolverConfig,
+    transport: MemoryTransport,
+) -> (
+    DnsClient<MemoryTransport, MemoryTransport>,
+    Arc<Mutex<Vec<TraceStep>>>,
+) {
+    let steps = Arc::new(Mutex::new(vec![]));
+    let traced = steps.clone();
+    let dns_client = DnsClient::with_transports(config, transport.clone(), transport)
+        .with_trace(move |step| traced.lock().unwrap().push(step.clone()));
+    (dns_client, steps)
+}
+
+#[test]
+fn trace_each_query_of_the_walk() {
+    let (dns_client, steps) = traced_client(root_config(&["10.0.0.1"]), hierarchy());
+
+    let result = dns_client.ask("www.example.com").unwrap();
+    assert_eq!(result.ip_addrs, vec!["192.0.2.1"]);
+    let steps = steps.lock().unwrap();
+    let summary = steps
+        .iter()
+        .map(|step| {
+            (
+                step.depth,
+                step.name.as_str(),
+                step.server.as_str(),
+                step.choice.clone(),
+            )
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        summary,
+        vec![
+            (0, "com", "10.0.0.1", Choice::Root),
+            (0, "example.com", "10.0.0.2", Choice::Referral("com".into())),
+            (1, "net", "10.0.0.1", Choice::Root),
+            (1, "example.net", "10.0.0.4", Choice::Referral("net".into())),
+            (1, "ns.example.net", "10.0.0.4", Choice::Longer),
+            (
+                0,
+                "www.example.com",
+                "10.0.0.3",
+                Choice::Referral("example.com".into())
+            ),
+        ]
+    );
+    assert_eq!(
+        steps[1].outcome,
+        Outcome::Referral {
+            zone: "example.com".into(),
+            name_servers: vec!["ns.example.net".into()],
+        }
+    );
+    assert_eq!(steps[3].outcome, Outcome::NoCut);
+    assert_eq!(steps[4].outcome, Outcome::Answer(vec!["10.0.0.3".into()]));
+    assert_eq!(steps[5].outcome, Outcome::Answer(vec!["192.0.2.1".into()]));
+    assert_eq!(steps[5].zone, "example.com");
+}
+
+#[test]
+fn trace_fallback_to_next_server() {
+    let config = root_config(&["10.0.0.9", "10.0.0.1"]);
+    let (dns_client, steps) = traced_client(config, hierarchy());
+
+    dns_client.ask("www.example.com").unwrap();
+    let steps = steps.lock().unwrap();
+    assert_eq!(steps[0].server, "10.0.0.9");
+    assert!(matches!(
+        steps[0].outcome,
+        Outcome::Error(ResolveError::Timeout { .. })
+    ));
+    assert_eq!(steps[1].server, "10.0.0.1");
+    assert_eq!(steps[1].choice, Choice::Fallback);
+}
+
+#[test]
+fn no_trace_of_queries_past_the_deadline() {
+    let transport = hierarchy();
+    transport.add_server(addr("10.0.0.1"), |query| {
+        thread::sleep(Duration::from_millis(100));
+        Some(referral(query, "com", "b.gtld-servers.net", [10, 0, 0, 2]))
+    });
+    let config = ResolverConfig {
+        deadline: Duration::from_millis(50),
+        ..root_config(&["10.0.0.1"])
+    };
+    let (dns_client, steps) = traced_client(config, transport);
+
+    let err = dns_client.ask("www.example.com").unwrap_err();
+    assert!(matches!(err, ResolveError::Timeout { .. }), "{:?}", err);
+    let steps = steps.lock().unwrap();
+    assert_eq!(steps.len(), 1, "{:?}", steps);
+    assert_eq!(steps[0].server, "10.0.0.1");
+}
+
+#[test]
+fn display_trace_step() {
+    let step = TraceStep {
+        depth: 1,
+        step: 2,
+        name: String::from("ns.example.net"),
+        zone: String::new(),
+        server: String::from("10.0.0.1"),
+        choice: Choice::Root,
+        elapsed: Duration::from_millis(12),
+        outcome: Outcome::Referral {
+            zone: String::from("net"),
+            name_servers: vec![String::from("a.gtld-servers.net")],
+        },
+    };
+
+    assert_eq!(
+        step.to_string(),
+        "     2. ns.example.net in . at 10.0.0.1 (root server) in 12 ms: \
+         referral to net [a.gtld-servers.net]"
+    );
+}
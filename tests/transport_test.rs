@@ -0,0 +1,254 @@
+mod common;
+
+use std::io::{ErrorKind, Write};
+use std::net::{TcpListener, UdpSocket};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use dns_resolver::client::cache::CacheKey;
+use dns_resolver::client::config::ResolverConfig;
+use dns_resolver::client::error::ResolveError;
+use dns_resolver::client::message::DnsMessage;
+use dns_resolver::client::rr;
+use dns_resolver::client::tcp;
+use dns_resolver::client::transport::{
+    Connection, MemoryTransport, TcpTransport, Transport, UdpTransport,
+};
+use dns_resolver::client::DnsClient;
+
+use common::{addr, hierarchy, memory_config, question, record, referral, response};
+
+#[test]
+fn memory_transport_answers_and_logs_queries() {
+    let transport = MemoryTransport::new();
+    transport.add_server(addr("10.0.0.1"), |query| Some(query.to_vec()));
+    let deadline = Instant::now() + Duration::from_secs(1);
+
+    let mut connection = transport.connect(addr("10.0.0.1"), deadline).unwrap();
+    connection.send(&[1, 2, 3]).unwrap();
+    assert_eq!(connection.receive(deadline).unwrap(), vec![1, 2, 3]);
+    assert_eq!(
+        connection.receive(deadline).unwrap_err().kind(),
+        ErrorKind::TimedOut
+    );
+
+    let mut connection = transport.connect(addr("10.0.0.2"), deadline).unwrap();
+    connection.send(&[4]).unwrap();
+    assert!(connection.receive(deadline).is_err());
+    assert_eq!(
+        transport.queries(),
+        vec![
+            (addr("10.0.0.1"), vec![1, 2, 3]),
+            (addr("10.0.0.2"), vec![4])
+        ]
+    );
+}
+
+#[test]
+fn udp_transport_exchanges_datagrams() {
+    let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let server_addr = server.local_addr().unwrap();
+    thread::spawn(move || {
+        let mut buffer = [0; 512];
+        let (len, source) = server.recv_from(&mut buffer).unwrap();
+        server.send_to(&buffer[..len], source).unwrap();
+    });
+    let deadline = Instant::now() + Duration::from_secs(1);
+
+    let mut connection = UdpTransport.connect(server_addr, deadline).unwrap();
+    connection.send(&[1, 2, 3]).unwrap();
+    assert_eq!(connection.receive(deadline).unwrap(), vec![1, 2, 3]);
+    let deadline = Instant::now() + Duration::from_millis(50);
+    assert!(connection.receive(deadline).is_err());
+}
+
+#[test]
+fn tcp_transport_exchanges_framed_messages() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let server_addr = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let msg = tcp::read_message(&mut stream).unwrap();
+        tcp::write_message(&mut stream, &msg).unwrap();
+    });
+    let deadline = Instant::now() + Duration::from_secs(1);
+
+    let mut connection = TcpTransport.connect(server_addr, deadline).unwrap();
+    connection.send(&[1, 2, 3]).unwrap();
+    assert_eq!(connection.receive(deadline).unwrap(), vec![1, 2, 3]);
+}
+
+#[test]
+fn tcp_transport_gives_up_on_slow_response_at_deadline() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let server_addr = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        tcp::read_message(&mut stream).unwrap();
+        // Announce a long message and send it a byte at a time
+        stream.write_all(&[0x00, 0x40]).unwrap();
+        for _ in 0..0x40 {
+            thread::sleep(Duration::from_millis(20));
+            if stream.write_all(&[0]).is_err() {
+                break;
+            }
+        }
+    });
+    let start = Instant::now();
+    let deadline = start + Duration::from_millis(200);
+
+    let mut connection = TcpTransport.connect(server_addr, deadline).unwrap();
+    connection.send(&[1, 2, 3]).unwrap();
+    assert_eq!(
+        connection.receive(deadline).unwrap_err().kind(),
+        ErrorKind::TimedOut
+    );
+    assert!(start.elapsed() < Duration::from_millis(600));
+}
+
+#[test]
+fn resolve_iteratively_in_memory() {
+    let transport = hierarchy();
+    let dns_client =
+        DnsClient::with_transports(memory_config(), transport.clone(), transport.clone());
+
+    let result = dns_client.ask("www.example.com").unwrap();
+    assert_eq!(result.ip_addrs, vec!["192.0.2.1"]);
+    let servers = transport
+        .queries()
+        .iter()
+        .map(|(server, _)| server.ip().to_string())
+        .collect::<Vec<_>>();
+    assert_eq!(servers, vec!["10.0.0.1", "10.0.0.2", "10.0.0.3"]);
+}
+
+#[test]
+fn retry_truncated_response_over_stream_transport() {
+    let udp = hierarchy();
+    udp.add_server(addr("10.0.0.3"), |query| {
+        let mut truncated = response(query, 1, vec![], vec![], vec![]);
+        truncated[2] |= 0x02;
+        Some(truncated)
+    });
+    let tcp = hierarchy();
+    let dns_client = DnsClient::with_transports(memory_config(), udp, tcp.clone());
+
+    let result = dns_client.ask("www.example.com").unwrap();
+    assert_eq!(result.ip_addrs, vec!["192.0.2.1"]);
+    assert_eq!(tcp.queries().len(), 1);
+}
+
+#[test]
+fn retry_cut_short_truncated_response_over_stream_transport() {
+    let udp = hierarchy();
+    udp.add_server(addr("10.0.0.3"), |query| {
+        let answer = record("www.example.com", rr::A, 300, vec![192, 0, 2, 9]);
+        let mut truncated = response(query, 1, vec![answer], vec![], vec![]);
+        truncated[2] |= 0x02;
+        // Claim more answers than the datagram carries
+        truncated[7] = 3;
+        Some(truncated)
+    });
+    let tcp = hierarchy();
+    let dns_client = DnsClient::with_transports(memory_config(), udp, tcp.clone());
+
+    let result = dns_client.ask("www.example.com").unwrap();
+    assert_eq!(result.ip_addrs, vec!["192.0.2.1"]);
+    assert_eq!(tcp.queries().len(), 1);
+}
+
+#[test]
+fn prime_again_after_failure() {
+    let transport = hierarchy();
+    let primes = Arc::new(AtomicUsize::new(0));
+    let count = primes.clone();
+    transport.add_server(addr("10.0.0.1"), move |query| {
+        if question(query) != (String::new(), rr::NS) {
+            return Some(referral(query, "com", "a.gtld-servers.net", [10, 0, 0, 2]));
+        }
+        // The first priming query goes unanswered
+        if count.fetch_add(1, Ordering::SeqCst) == 0 {
+            return None;
+        }
+        let ns_name = DnsMessage::encode_address("a.root-servers.net");
+        let answers = vec![record("", rr::NS, 300, ns_name)];
+        let additionals = vec![record("a.root-servers.net", rr::A, 300, vec![10, 0, 0, 9])];
+        Some(response(query, 1, answers, vec![], additionals))
+    });
+    transport.add_server(addr("10.0.0.9"), |query| {
+        Some(referral(query, "com", "a.gtld-servers.net", [10, 0, 0, 2]))
+    });
+    let config = ResolverConfig {
+        prime_roots: true,
+        prime_retry: Duration::from_millis(100),
+        cache: false,
+        ..memory_config()
+    };
+    let dns_client = DnsClient::with_transports(config, transport.clone(), transport.clone());
+
+    // Lookups go on with the root hints, priming again only after a while
+    for _ in 0..2 {
+        dns_client.ask("www.example.com").unwrap();
+    }
+    assert_eq!(primes.load(Ordering::SeqCst), 1);
+
+    thread::sleep(Duration::from_millis(150));
+    dns_client.ask("www.example.com").unwrap();
+    assert_eq!(primes.load(Ordering::SeqCst), 2);
+    let last = transport.queries().len();
+    dns_client.ask("www.example.com").unwrap();
+    assert_eq!(primes.load(Ordering::SeqCst), 2);
+    assert_eq!(transport.queries()[last].0, addr("10.0.0.9"));
+}
+
+#[test]
+fn name_server_lookups_are_not_hits() {
+    let transport = MemoryTransport::new();
+    transport.add_server(addr("10.0.0.1"), |query| {
+        Some(match question(query).0.ends_with("net") {
+            true => referral(query, "net", "a.gtld-servers.net", [10, 0, 0, 4]),
+            false => referral(query, "com", "b.gtld-servers.net", [10, 0, 0, 2]),
+        })
+    });
+    // Every zone of com is served by ns.example.net, without glue
+    transport.add_server(addr("10.0.0.2"), |query| {
+        let (name, _) = question(query);
+        let labels = name.split('.').collect::<Vec<_>>();
+        let zone = labels[labels.len().saturating_sub(2)..].join(".");
+        let ns = DnsMessage::encode_address("ns.example.net");
+        let authorities = vec![record(&zone, rr::NS, 300, ns)];
+        Some(response(query, 0, vec![], authorities, vec![]))
+    });
+    transport.add_server(addr("10.0.0.4"), |query| {
+        let answer = record("ns.example.net", rr::A, 300, vec![10, 0, 0, 3]);
+        Some(response(query, 1, vec![answer], vec![], vec![]))
+    });
+    transport.add_server(addr("10.0.0.3"), |query| {
+        let (name, _) = question(query);
+        let answer = record(&name, rr::A, 300, vec![192, 0, 2, 1]);
+        Some(response(query, 1, vec![answer], vec![], vec![]))
+    });
+    let config = ResolverConfig {
+        prefetch: true,
+        ..memory_config()
+    };
+    let dns_client = DnsClient::with_transports(config, transport.clone(), transport);
+
+    dns_client.ask("www.example.com").unwrap();
+    dns_client.ask("www.other.com").unwrap();
+    let key = CacheKey::new("ns.example.net", rr::A, rr::IN);
+    assert!(!dns_client.cache().claim_prefetch(&key, 1.0, 1));
+}
+
+#[test]
+fn time_out_unanswered_servers_in_memory() {
+    let transport = MemoryTransport::new();
+    let dns_client = DnsClient::with_transports(memory_config(), transport.clone(), transport);
+
+    match dns_client.ask("www.example.com") {
+        Err(ResolveError::Timeout { server, .. }) => assert_eq!(server, "10.0.0.1"),
+        other => panic!("Expected a timeout, got {:?}", other),
+    }
+}